@@ -1,87 +1,685 @@
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
 
-use cpal::{
-    self,
-    traits::{DeviceTrait, HostTrait, StreamTrait},
+use anyhow::{anyhow, Result};
+use audiopus::{
+    coder::{Decoder, Encoder},
+    packet::Packet,
+    Application, Bitrate, Channels, MutSignals, SampleRate,
 };
-use tokio::time::{Duration, Sleep};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Duration};
+use tracing::{error, info};
 use webrtc::{
     media::Sample,
-    track::track_local::{track_local_static_sample::TrackLocalStaticSample, TrackLocal},
+    track::{
+        track_local::track_local_static_sample::TrackLocalStaticSample, track_remote::TrackRemote,
+    },
 };
 
-const SAMPLE_RATE: u32 = 480000;
-const CHANNELS: u16 = 2;
+use crate::core::backend::{AudioBackend, FrameReceiver};
+use crate::core::bitrate::EncoderSettings;
+use crate::core::jitter::{JitterBuffer, JitterOutput};
+use crate::core::mixer::{Mixer, SourceId};
+use crate::core::processing::{AudioProcessor, EchoReference, ProcessingControls};
+use crate::core::recorder::{LocalTrackRecording, Recording, TrackRecording};
+use crate::core::soundboard::Soundboard;
+use crate::core::vad::VoiceActivityDetector;
+use crate::utils::types::{NodeId, VoiceState};
 
-struct Audio {
-    host: cpal::Host,
-    device: cpal::Device,
+//Opus only operates on a fixed set of sample rates. WebRTC always advertises opus/48000
+pub const SAMPLE_RATE: u32 = 48000;
+//Voice is encoded in mono, the remote decoder handles upmixing if needed
+pub const CHANNELS: u16 = 1;
+pub const FRAME_DURATION_MS: u64 = 20;
+//Number of samples per channel in a single 20ms frame
+pub const FRAME_SIZE: usize = (SAMPLE_RATE as u64 * FRAME_DURATION_MS / 1000) as usize;
+//Max size of an opus packet as defined by RFC 6716
+pub const MAX_OPUS_PACKET_SIZE: usize = 1275;
+//Opus packets can hold up to 120ms of audio
+const MAX_DECODED_FRAME_SIZE: usize = FRAME_SIZE * 6;
+
+//Handle to a running audio source or sink. Backends that need a device keep it alive on a
+//dedicated thread. Dropping the handle stops the stream and any tasks feeding it.
+#[derive(Debug, Default)]
+pub struct AudioStream {
+    stop_tx: Option<std::sync::mpsc::Sender<()>>,
+    thread_handle: Option<thread::JoinHandle<()>>,
+    task_handles: Vec<JoinHandle<()>>,
 }
 
-impl Audio {
-    fn new() -> Self {
-        let host = cpal::default_host();
-        let device = host
-            .default_input_device()
-            .expect("Failed to find input device");
-        Self { host, device }
+impl AudioStream {
+    pub fn from_thread(
+        stop_tx: std::sync::mpsc::Sender<()>,
+        thread_handle: thread::JoinHandle<()>,
+    ) -> Self {
+        Self {
+            stop_tx: Some(stop_tx),
+            thread_handle: Some(thread_handle),
+            task_handles: Vec::new(),
+        }
     }
 
-    async fn stream_audio(&self, track: Arc<TrackLocalStaticSample>) {
-        let track = Arc::clone(&track);
-        let config = self.device.default_input_config().unwrap();
+    pub fn push_task(&mut self, handle: JoinHandle<()>) {
+        self.task_handles.push(handle);
+    }
 
-        let stream = self.device.build_input_stream(
-            &config.into(),
-            move |data: &[f32], _: &cpal::InputCallbackInfo| {
-                let sample = Sample {
-                    data: vec![].into(),
-                    duration: Duration::from_secs_f32(1.0 / 50.0),
-                    ..Default::default()
-                };
-                track.write_sample(&sample);
-            },
-            |err| eprintln!("Error capturing audio: {:?}", err),
-        );
-    }
-
-    /// Capture microphone input and send it to a WebRTC audio track.
-    async fn capture_and_stream_audio(&self, track: Arc<TrackLocalStaticSample>) {
-        let track = Arc::clone(&track);
-        let config = self.device.default_input_config().unwrap();
-
-        let track_clone = track.clone();
-
-        // Build and run the audio capture stream
-        let stream = self
-            .device
-            .build_input_stream(
-                &config.into(),
-                move |data: &[f32], _: &cpal::InputCallbackInfo| {
-                    // Convert captured audio to bytes and send to the WebRTC track
-                    let audio_data: Vec<u8> = data
-                        .iter()
-                        .flat_map(|&sample| sample.to_le_bytes().to_vec())
-                        .collect();
-
-                    let track_clone = Arc::clone(&track);
-
-                    let _ = tokio::spawn(async move {
-                        let sample = Sample {
-                            data: audio_data.clone().into(),
-                            duration: Duration::from_secs_f32(1.0 / 50.0),
-                            ..Default::default()
-                        };
-
-                        if let Err(err) = track_clone.write_sample(&sample).await {
-                            eprintln!("Error sending audio sample: {:?}", err);
+    pub fn stop(&mut self) {
+        for handle in self.task_handles.drain(..) {
+            handle.abort();
+        }
+        //Dropping the sender wakes up the stream thread
+        self.stop_tx.take();
+        if let Some(handle) = self.thread_handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for AudioStream {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+//Mute, deafen and push-to-talk state shared by every call the client is in
+#[derive(Debug, Default)]
+pub struct VoiceControls {
+    muted: AtomicBool,
+    deafened: AtomicBool,
+    push_to_talk: AtomicBool,
+    talking: AtomicBool,
+}
+
+impl VoiceControls {
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+    }
+
+    pub fn set_deafened(&self, deafened: bool) {
+        self.deafened.store(deafened, Ordering::Relaxed);
+    }
+
+    pub fn set_push_to_talk(&self, push_to_talk: bool) {
+        self.push_to_talk.store(push_to_talk, Ordering::Relaxed);
+        self.talking.store(false, Ordering::Relaxed);
+    }
+
+    //Driven by key down/up while push to talk is enabled
+    pub fn set_talking(&self, talking: bool) {
+        self.talking.store(talking, Ordering::Relaxed);
+    }
+
+    //Deafening also mutes, same as most voice chat apps
+    pub fn is_transmitting(&self) -> bool {
+        let push_to_talk = self.push_to_talk.load(Ordering::Relaxed);
+        !self.muted.load(Ordering::Relaxed)
+            && !self.deafened.load(Ordering::Relaxed)
+            && (!push_to_talk || self.talking.load(Ordering::Relaxed))
+    }
+
+    pub fn is_deafened(&self) -> bool {
+        self.deafened.load(Ordering::Relaxed)
+    }
+
+    //What the peer sees. A push to talk user is shown as muted while the key is up
+    pub fn state(&self) -> VoiceState {
+        VoiceState {
+            muted: !self.is_transmitting(),
+            deafened: self.is_deafened(),
+            recording: false,
+        }
+    }
+}
+
+//Playback gain for a single peer that can be changed while audio is playing
+#[derive(Debug)]
+pub struct Volume(AtomicU32);
+
+impl Default for Volume {
+    fn default() -> Self {
+        Self(AtomicU32::new(1f32.to_bits()))
+    }
+}
+
+impl Volume {
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, gain: f32) {
+        self.0.store(gain.max(0.0).to_bits(), Ordering::Relaxed);
+    }
+}
+
+//Raised by the audio tasks and forwarded to the frontend by the client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AudioEvent {
+    //None is the local user
+    Speaking(Option<NodeId>, bool),
+}
+
+pub type AudioEventSender = mpsc::UnboundedSender<AudioEvent>;
+pub type AudioEventReceiver = mpsc::UnboundedReceiver<AudioEvent>;
+
+//Everything a call needs to capture and play audio, shared by all of the client's connections
+#[derive(Debug, Clone)]
+pub struct AudioContext {
+    pub backend: Arc<dyn AudioBackend>,
+    pub controls: Arc<VoiceControls>,
+    pub processing: Arc<ProcessingControls>,
+    pub events_tx: AudioEventSender,
+    pub playback: Arc<Playback>,
+    pub recording: Arc<Recording>,
+    pub local_recording: Arc<LocalTrackRecording>,
+    pub soundboard: Arc<Soundboard>,
+}
+
+impl AudioContext {
+    pub fn new(backend: Arc<dyn AudioBackend>) -> (Self, AudioEventReceiver) {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let audio = Self {
+            backend,
+            controls: Arc::new(VoiceControls::default()),
+            processing: Arc::new(ProcessingControls::default()),
+            events_tx,
+            playback: Arc::new(Playback::default()),
+            recording: Arc::new(Recording::default()),
+            local_recording: Arc::new(LocalTrackRecording::default()),
+            soundboard: Arc::new(Soundboard::default()),
+        };
+        (audio, events_rx)
+    }
+}
+
+//Starts capturing from the backend's input and streams opus frames into the track
+pub async fn start_capture(
+    audio: &AudioContext,
+    track: Arc<TrackLocalStaticSample>,
+    encoder_settings: watch::Receiver<EncoderSettings>,
+) -> Result<AudioStream> {
+    let (mut audio_stream, frame_rx) = Arc::clone(&audio.backend).start_input().await?;
+    let encoder_handle = tokio::spawn(encode_and_write(
+        track,
+        frame_rx,
+        audio.clone(),
+        encoder_settings,
+    ));
+    audio_stream.push_task(encoder_handle);
+    Ok(audio_stream)
+}
+
+//Every remote track is mixed into a single output on the backend, so several peers can be heard
+//at once and the output device can be switched in one place
+#[derive(Debug)]
+pub struct Playback {
+    mixer: Arc<Mutex<Mixer>>,
+    output: tokio::sync::Mutex<Option<AudioStream>>,
+    echo_reference: Arc<EchoReference>,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Self {
+            mixer: Arc::new(Mutex::new(Mixer::new(SAMPLE_RATE, CHANNELS))),
+            output: tokio::sync::Mutex::new(None),
+            echo_reference: Arc::new(EchoReference::default()),
+        }
+    }
+}
+
+impl Playback {
+    //Opens the output the first time someone needs to be played
+    pub async fn add_source(
+        &self,
+        backend: Arc<dyn AudioBackend>,
+        gain: f32,
+    ) -> Result<PlaybackSource> {
+        let mut output = self.output.lock().await;
+        if output.is_none() {
+            *output = Some(self.start_output(backend).await?);
+        }
+        let mut mixer = self
+            .mixer
+            .lock()
+            .map_err(|_| anyhow!("Mixer lock poisoned"))?;
+        let id = mixer.add_source();
+        mixer.set_gain(id, gain);
+        Ok(PlaybackSource {
+            id,
+            mixer: Arc::clone(&self.mixer),
+        })
+    }
+
+    pub async fn switch_output(&self, backend: Arc<dyn AudioBackend>) -> Result<()> {
+        let mut output = self.output.lock().await;
+        if output.is_some() {
+            let audio_stream = self.start_output(backend).await?;
+            if let Some(mut previous) = output.replace(audio_stream) {
+                previous.stop();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn echo_reference(&self) -> &EchoReference {
+        &self.echo_reference
+    }
+
+    //Releases the output device once nobody is left to play
+    pub async fn stop_if_idle(&self) {
+        let mut output = self.output.lock().await;
+        let idle = self
+            .mixer
+            .lock()
+            .map(|mixer| mixer.is_empty())
+            .unwrap_or(true);
+        if idle {
+            if let Some(mut audio_stream) = output.take() {
+                audio_stream.stop();
+            }
+        }
+    }
+
+    async fn start_output(&self, backend: Arc<dyn AudioBackend>) -> Result<AudioStream> {
+        let (mut audio_stream, frame_tx) = backend.start_output().await?;
+        let mixer = Arc::clone(&self.mixer);
+        let echo_reference = Arc::clone(&self.echo_reference);
+        let mixer_handle = tokio::spawn(async move {
+            let mut ticker = interval(Duration::from_millis(FRAME_DURATION_MS));
+            loop {
+                ticker.tick().await;
+                let frame = mixer.lock().ok().and_then(|mut mixer| mixer.mix());
+                //The mix is already 48kHz mono, so it doubles as the echo canceller's reference.
+                //Silence is pushed too so the reference keeps pace with the microphone
+                match frame {
+                    Some(frame) => {
+                        echo_reference.push(&frame);
+                        if frame_tx.send(frame).is_err() {
+                            break;
                         }
-                    });
-                },
-                |err| eprintln!("Error capturing audio: {:?}", err),
-            )
-            .expect("Failed to build input stream");
+                    }
+                    None => echo_reference.push(&[0.0; FRAME_SIZE]),
+                }
+            }
+        });
+        audio_stream.push_task(mixer_handle);
+        Ok(audio_stream)
+    }
+}
+
+//A single peer's input into the mix. Removed from the mixer when dropped
+#[derive(Debug)]
+pub struct PlaybackSource {
+    id: SourceId,
+    mixer: Arc<Mutex<Mixer>>,
+}
+
+impl PlaybackSource {
+    pub fn push(&self, samples: &[f32]) {
+        if let Ok(mut mixer) = self.mixer.lock() {
+            mixer.push(self.id, samples);
+        }
+    }
+
+    pub fn set_gain(&self, gain: f32) {
+        if let Ok(mut mixer) = self.mixer.lock() {
+            mixer.set_gain(self.id, gain);
+        }
+    }
+}
+
+impl Drop for PlaybackSource {
+    fn drop(&mut self) {
+        if let Ok(mut mixer) = self.mixer.lock() {
+            mixer.remove_source(self.id);
+        }
+    }
+}
+
+//Playback of a single remote track
+#[derive(Debug)]
+pub struct RemoteAudio {
+    decoder_stream: AudioStream,
+    source: Arc<PlaybackSource>,
+}
+
+impl RemoteAudio {
+    pub fn set_volume(&self, volume: f32) {
+        self.source.set_gain(volume);
+    }
+
+    pub fn stop(&mut self) {
+        self.decoder_stream.stop();
+    }
+}
+
+//Reads opus RTP from a remote track, decodes it and feeds it into the playback mix
+pub async fn start_playback(
+    audio: &AudioContext,
+    track: Arc<TrackRemote>,
+    node_id: NodeId,
+    volume: f32,
+) -> Result<RemoteAudio> {
+    let source = audio
+        .playback
+        .add_source(Arc::clone(&audio.backend), volume)
+        .await?;
+    let source = Arc::new(source);
+
+    let (rtp_tx, rtp_rx) = mpsc::channel::<(u16, Vec<u8>)>(100);
+    let reader_handle = tokio::spawn(async move {
+        while let Ok((packet, _)) = track.read_rtp().await {
+            if packet.payload.is_empty() {
+                continue;
+            }
+            let seq = packet.header.sequence_number;
+            if rtp_tx.send((seq, packet.payload.to_vec())).await.is_err() {
+                break;
+            }
+        }
+        info!("Remote audio track ended");
+    });
+    let decoder_handle = tokio::spawn(decode_and_play(
+        rtp_rx,
+        Arc::clone(&source),
+        audio.clone(),
+        node_id,
+    ));
+
+    let mut decoder_stream = AudioStream::default();
+    decoder_stream.push_task(reader_handle);
+    decoder_stream.push_task(decoder_handle);
+    Ok(RemoteAudio {
+        decoder_stream,
+        source,
+    })
+}
+
+//Plays a finished clip, e.g. a voice message, through the playback mix alongside any call audio
+pub async fn play_samples(audio: &AudioContext, samples: Vec<f32>) -> Result<()> {
+    let source = audio
+        .playback
+        .add_source(Arc::clone(&audio.backend), 1.0)
+        .await?;
+    let mut ticker = interval(Duration::from_millis(FRAME_DURATION_MS));
+    for frame in samples.chunks(FRAME_SIZE) {
+        ticker.tick().await;
+        source.push(frame);
+    }
+    //Let the last frame play out before the source leaves the mix
+    ticker.tick().await;
+    drop(source);
+    audio.playback.stop_if_idle().await;
+    Ok(())
+}
+
+pub fn new_encoder() -> Result<Encoder> {
+    let encoder = Encoder::new(SampleRate::Hz48000, Channels::Mono, Application::Voip)?;
+    Ok(encoder)
+}
+
+pub fn new_decoder() -> Result<Decoder> {
+    let decoder = Decoder::new(SampleRate::Hz48000, Channels::Mono)?;
+    Ok(decoder)
+}
+
+//Applies the bitrate, FEC and expected loss picked from the peer's RTCP feedback
+pub fn apply_encoder_settings(encoder: &mut Encoder, settings: EncoderSettings) -> Result<()> {
+    encoder.set_bitrate(Bitrate::BitsPerSecond(settings.bitrate))?;
+    encoder.set_inband_fec(settings.fec)?;
+    encoder.set_packet_loss_perc(settings.packet_loss_perc)?;
+    Ok(())
+}
+
+//Decodes a single opus packet into mono 48kHz samples. Passing None conceals a lost packet.
+pub fn decode_packet(decoder: &mut Decoder, payload: Option<&[u8]>) -> Result<Vec<f32>> {
+    let mut pcm = vec![0f32; MAX_DECODED_FRAME_SIZE];
+    let len = match payload {
+        Some(payload) => {
+            let packet = Packet::try_from(payload)?;
+            decoder.decode_float(Some(packet), MutSignals::try_from(&mut pcm[..])?, false)?
+        }
+        None => decoder.decode_float(None, MutSignals::try_from(&mut pcm[..FRAME_SIZE])?, false)?,
+    };
+    pcm.truncate(len * CHANNELS as usize);
+    Ok(pcm)
+}
+
+//Collects 48kHz mono samples into 20ms frames, encodes them and writes them to the track
+async fn encode_and_write(
+    track: Arc<TrackLocalStaticSample>,
+    mut frame_rx: FrameReceiver,
+    audio: AudioContext,
+    mut encoder_settings: watch::Receiver<EncoderSettings>,
+) {
+    let mut encoder = match new_encoder() {
+        Ok(encoder) => encoder,
+        Err(e) => {
+            error!("Error creating opus encoder: {}", e);
+            return;
+        }
+    };
+    //Silence goes out as tiny DTX packets. Every frame is still written so the RTP timestamp
+    //keeps up with real time across pauses, and speech onset is never held back
+    if let Err(e) = encoder.set_dtx(true) {
+        error!("Error enabling opus DTX: {}", e);
+    }
+    //Pick up where the previous capture left off, e.g. after switching input devices
+    let settings = *encoder_settings.borrow_and_update();
+    if let Err(e) = apply_encoder_settings(&mut encoder, settings) {
+        error!("Error applying encoder settings: {}", e);
+    }
+
+    let mut frame: Vec<f32> = Vec::with_capacity(FRAME_SIZE * 2);
+    let mut packet = vec![0u8; MAX_OPUS_PACKET_SIZE];
+    let mut vad = VoiceActivityDetector::default();
+    let track_recording = audio.local_recording.writer();
+    let mut sounds = audio.soundboard.subscribe();
+    let mut processor = AudioProcessor::new(
+        Arc::clone(&audio.processing),
+        audio.playback.echo_reference(),
+    );
+    while let Some(samples) = frame_rx.recv().await {
+        frame.extend(samples);
+
+        while frame.len() >= FRAME_SIZE {
+            if encoder_settings.has_changed().unwrap_or(false) {
+                let settings = *encoder_settings.borrow_and_update();
+                match apply_encoder_settings(&mut encoder, settings) {
+                    Ok(()) => info!("Encoder settings changed to {:?}", settings),
+                    Err(e) => error!("Error changing encoder settings: {}", e),
+                }
+            }
+            let mut pcm: Vec<f32> = frame.drain(..FRAME_SIZE).collect();
+            processor.process(&mut pcm);
+            if !audio.controls.is_transmitting() {
+                pcm.fill(0.0);
+            }
+            //Soundboard clips still go out while muted
+            sounds.mix_into(&mut pcm);
+            if let Some(speaking) = vad.update(&pcm) {
+                let _ = audio.events_tx.send(AudioEvent::Speaking(None, speaking));
+            }
+            let len = match encoder.encode_float(&pcm, &mut packet) {
+                Ok(len) => len,
+                Err(e) => {
+                    error!("Error encoding opus frame: {}", e);
+                    track_recording.record(&audio.recording, None);
+                    continue;
+                }
+            };
+
+            track_recording.record(&audio.recording, Some(&packet[..len]));
+            let sample = Sample {
+                data: packet[..len].to_vec().into(),
+                duration: Duration::from_millis(FRAME_DURATION_MS),
+                ..Default::default()
+            };
+            if let Err(e) = track.write_sample(&sample).await {
+                error!("Error sending audio sample: {:?}", e);
+            }
+        }
+    }
+    if vad.is_speaking() {
+        let _ = audio.events_tx.send(AudioEvent::Speaking(None, false));
+    }
+    info!("Audio capture stopped");
+}
+
+//Pulls one packet out of the jitter buffer every 20ms and hands the decoded audio to the backend
+async fn decode_and_play(
+    mut rtp_rx: mpsc::Receiver<(u16, Vec<u8>)>,
+    source: Arc<PlaybackSource>,
+    audio: AudioContext,
+    node_id: NodeId,
+) {
+    let mut decoder = match new_decoder() {
+        Ok(decoder) => decoder,
+        Err(e) => {
+            error!("Error creating opus decoder: {}", e);
+            return;
+        }
+    };
+
+    let mut jitter_buffer = JitterBuffer::new();
+    let mut vad = VoiceActivityDetector::default();
+    let mut track_recording = TrackRecording::new(node_id.fmt_short());
+    let mut ticker = interval(Duration::from_millis(FRAME_DURATION_MS));
+    loop {
+        tokio::select! {
+            packet = rtp_rx.recv() => match packet {
+                Some((seq, payload)) => jitter_buffer.push(seq, payload),
+                None => break,
+            },
+            _ = ticker.tick() => {
+                let decoded = match jitter_buffer.pop() {
+                    JitterOutput::Packet(payload) => {
+                        track_recording.record(&audio.recording, Some(&payload));
+                        decode_packet(&mut decoder, Some(&payload))
+                    }
+                    JitterOutput::Lost => {
+                        track_recording.record(&audio.recording, None);
+                        decode_packet(&mut decoder, None)
+                    }
+                    //The peer stops sending while silent
+                    JitterOutput::Empty => {
+                        track_recording.record(&audio.recording, None);
+                        Ok(Vec::new())
+                    }
+                };
+                if let Ok(pcm) = &decoded {
+                    if let Some(speaking) = vad.update(pcm) {
+                        let _ = audio.events_tx.send(AudioEvent::Speaking(Some(node_id), speaking));
+                    }
+                }
+                match decoded {
+                    Ok(pcm) if pcm.is_empty() => {}
+                    //Still decode while deafened so the decoder state stays in sync
+                    Ok(_) if audio.controls.is_deafened() => {}
+                    Ok(pcm) => source.push(&pcm),
+                    Err(e) => error!("Error decoding opus packet: {}", e),
+                }
+            }
+        }
+    }
+    if vad.is_speaking() {
+        let _ = audio
+            .events_tx
+            .send(AudioEvent::Speaking(Some(node_id), false));
+    }
+    info!("Audio playback stopped");
+}
+
+//Averages interleaved channels into a single channel
+pub fn downmix(samples: &[f32], channels: u16) -> Vec<f32> {
+    if channels <= 1 {
+        return samples.to_vec();
+    }
+    samples
+        .chunks(channels as usize)
+        .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+        .collect()
+}
+
+//Copies each mono sample into every output channel
+pub fn upmix(samples: &[f32], channels: u16) -> Vec<f32> {
+    if channels <= 1 {
+        return samples.to_vec();
+    }
+    samples
+        .iter()
+        .flat_map(|&s| std::iter::repeat_n(s, channels as usize))
+        .collect()
+}
+
+//Linear interpolation resampler. Good enough for voice and avoids pulling in a dsp crate.
+pub fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if from_rate == to_rate || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let ratio = from_rate as f64 / to_rate as f64;
+    let out_len = (samples.len() as f64 / ratio).round() as usize;
+    (0..out_len)
+        .map(|i| {
+            let pos = i as f64 * ratio;
+            let index = pos.floor() as usize;
+            let frac = (pos - index as f64) as f32;
+            let a = samples[index.min(samples.len() - 1)];
+            let b = samples[(index + 1).min(samples.len() - 1)];
+            a + (b - a) * frac
+        })
+        .collect()
+}
+
+//Streaming version of `resample` for audio that arrives in chunks. Carries the read position and
+//the last sample over so chunk boundaries don't add or drop samples.
+#[derive(Debug)]
+pub struct Resampler {
+    ratio: f64,
+    pos: f64,
+    last: Option<f32>,
+}
+
+impl Resampler {
+    pub fn new(from_rate: u32, to_rate: u32) -> Self {
+        Self {
+            ratio: from_rate as f64 / to_rate as f64,
+            pos: 0.0,
+            last: None,
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        if self.ratio == 1.0 {
+            return samples.to_vec();
+        }
+        //The previous chunk's last sample sits in front of this one
+        let offset = self.last.is_some() as usize;
+        let len = samples.len() + offset;
+        let last = self.last.unwrap_or(0.0);
+        let at = |i: usize| {
+            if i < offset {
+                last
+            } else {
+                samples[i - offset]
+            }
+        };
 
-        stream.play().expect("Error playing stream");
+        let mut output = Vec::with_capacity((samples.len() as f64 / self.ratio) as usize + 1);
+        while (self.pos as usize) + 1 < len {
+            let index = self.pos as usize;
+            let frac = (self.pos - index as f64) as f32;
+            let (a, b) = (at(index), at(index + 1));
+            output.push(a + (b - a) * frac);
+            self.pos += self.ratio;
+        }
+        if let Some(&sample) = samples.last() {
+            self.pos -= (len - 1) as f64;
+            self.last = Some(sample);
+        }
+        output
     }
 }
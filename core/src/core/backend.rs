@@ -0,0 +1,557 @@
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::{anyhow, Result};
+use cpal::{
+    self,
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+};
+use serde::{Deserialize, Serialize};
+use tokio::runtime::Handle;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{interval, Duration};
+use tracing::{error, info};
+
+use crate::core::audio::{
+    downmix, resample, upmix, AudioStream, Resampler, FRAME_DURATION_MS, FRAME_SIZE, SAMPLE_RATE,
+};
+use crate::utils::enums::AudioDirection;
+use crate::utils::types::BoxedFuture;
+
+//Caps how far playback can lag behind the decoder before old audio is dropped
+const MAX_PLAYBACK_BUFFER_MS: usize = 200;
+
+//Interleaved samples waiting to be consumed by the output device
+pub type PlaybackBuffer = Arc<Mutex<VecDeque<f32>>>;
+
+//Backends exchange mono 48kHz samples with the rest of the audio pipeline
+pub type FrameSender = mpsc::UnboundedSender<Vec<f32>>;
+pub type FrameReceiver = mpsc::UnboundedReceiver<Vec<f32>>;
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct AudioConfigInfo {
+    #[serde(rename = "channels")]
+    pub channels: u16,
+    #[serde(rename = "minSampleRate")]
+    pub min_sample_rate: u32,
+    #[serde(rename = "maxSampleRate")]
+    pub max_sample_rate: u32,
+    #[serde(rename = "sampleFormat")]
+    pub sample_format: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct AudioDeviceInfo {
+    #[serde(rename = "name")]
+    pub name: String,
+    #[serde(rename = "isDefault")]
+    pub is_default: bool,
+    #[serde(rename = "configs")]
+    pub configs: Vec<AudioConfigInfo>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
+pub struct AudioDevices {
+    #[serde(rename = "inputs")]
+    pub inputs: Vec<AudioDeviceInfo>,
+    #[serde(rename = "outputs")]
+    pub outputs: Vec<AudioDeviceInfo>,
+}
+
+//Source and sink of raw audio for calls. Lets call paths run without sound hardware.
+pub trait AudioBackend: Send + Sync + Debug {
+    //Starts producing samples, e.g. from a microphone
+    fn start_input(self: Arc<Self>) -> BoxedFuture<Result<(AudioStream, FrameReceiver)>>;
+    //Starts consuming samples, e.g. playing them on speakers
+    fn start_output(self: Arc<Self>) -> BoxedFuture<Result<(AudioStream, FrameSender)>>;
+
+    fn list_devices(&self) -> Result<AudioDevices> {
+        Ok(AudioDevices::default())
+    }
+
+    //None switches back to the system default. Takes effect the next time a stream is started.
+    fn select_device(&self, _direction: AudioDirection, _name: Option<String>) -> Result<()> {
+        Err(anyhow!("Audio backend does not support selecting devices"))
+    }
+}
+
+//Uses the system's input and output devices, the defaults unless one has been selected
+#[derive(Debug, Default)]
+pub struct CpalBackend {
+    input_device: Mutex<Option<String>>,
+    output_device: Mutex<Option<String>>,
+}
+
+impl CpalBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn selected_device(&self, direction: &AudioDirection) -> Option<String> {
+        let selected = match direction {
+            AudioDirection::Input => &self.input_device,
+            AudioDirection::Output => &self.output_device,
+        };
+        selected.lock().ok().and_then(|name| name.clone())
+    }
+}
+
+impl AudioBackend for CpalBackend {
+    fn start_input(self: Arc<Self>) -> BoxedFuture<Result<(AudioStream, FrameReceiver)>> {
+        Box::pin(async move {
+            let (frame_tx, frame_rx) = mpsc::unbounded_channel();
+            let name = self.selected_device(&AudioDirection::Input);
+            let (audio_stream, ()) = spawn_stream_thread(move || {
+                let device = find_device(AudioDirection::Input, name)?;
+                let config = input_config(&device)?;
+                let sample_format = config.sample_format();
+                let config: cpal::StreamConfig = config.into();
+                info!(
+                    "Capturing audio from {:?} at {}Hz with {} channel(s)",
+                    device.name(),
+                    config.sample_rate.0,
+                    config.channels
+                );
+
+                let stream = match sample_format {
+                    cpal::SampleFormat::F32 => build_input::<f32>(&device, &config, frame_tx)?,
+                    cpal::SampleFormat::I16 => build_input::<i16>(&device, &config, frame_tx)?,
+                    cpal::SampleFormat::U16 => build_input::<u16>(&device, &config, frame_tx)?,
+                };
+                stream.play()?;
+                Ok((stream, ()))
+            })
+            .await?;
+            Ok((audio_stream, frame_rx))
+        })
+    }
+
+    fn start_output(self: Arc<Self>) -> BoxedFuture<Result<(AudioStream, FrameSender)>> {
+        Box::pin(async move {
+            let buffer: PlaybackBuffer = Arc::new(Mutex::new(VecDeque::new()));
+            let output_buffer = Arc::clone(&buffer);
+            let name = self.selected_device(&AudioDirection::Output);
+            let (mut audio_stream, config) = spawn_stream_thread(move || {
+                let device = find_device(AudioDirection::Output, name)?;
+                let config = output_config(&device)?;
+                let sample_format = config.sample_format();
+                let config: cpal::StreamConfig = config.into();
+                info!(
+                    "Playing audio on {:?} at {}Hz with {} channel(s)",
+                    device.name(),
+                    config.sample_rate.0,
+                    config.channels
+                );
+
+                let stream = match sample_format {
+                    cpal::SampleFormat::F32 => {
+                        build_output::<f32>(&device, &config, output_buffer)?
+                    }
+                    cpal::SampleFormat::I16 => {
+                        build_output::<i16>(&device, &config, output_buffer)?
+                    }
+                    cpal::SampleFormat::U16 => {
+                        build_output::<u16>(&device, &config, output_buffer)?
+                    }
+                };
+                stream.play()?;
+                Ok((stream, config))
+            })
+            .await?;
+
+            //Convert to the device's format before handing samples to the output callback
+            let (frame_tx, mut frame_rx) = mpsc::unbounded_channel::<Vec<f32>>();
+            let output_rate = config.sample_rate.0;
+            let output_channels = config.channels;
+            let max_buffered =
+                output_rate as usize * output_channels as usize * MAX_PLAYBACK_BUFFER_MS / 1000;
+            let mut resampler = Resampler::new(SAMPLE_RATE, output_rate);
+            let handle = tokio::spawn(async move {
+                while let Some(frame) = frame_rx.recv().await {
+                    let samples = upmix(&resampler.process(&frame), output_channels);
+                    if let Ok(mut buffer) = buffer.lock() {
+                        buffer.extend(samples);
+                        while buffer.len() > max_buffered {
+                            buffer.pop_front();
+                        }
+                    }
+                }
+            });
+            audio_stream.push_task(handle);
+            Ok((audio_stream, frame_tx))
+        })
+    }
+
+    fn list_devices(&self) -> Result<AudioDevices> {
+        let host = cpal::default_host();
+        let default_input = host.default_input_device().and_then(|d| d.name().ok());
+        let default_output = host.default_output_device().and_then(|d| d.name().ok());
+
+        let mut devices = AudioDevices::default();
+        for device in host.input_devices()? {
+            let configs = device
+                .supported_input_configs()
+                .map(|configs| configs.map(config_info).collect())
+                .unwrap_or_default();
+            let name = device.name()?;
+            devices.inputs.push(AudioDeviceInfo {
+                is_default: default_input.as_ref() == Some(&name),
+                name,
+                configs,
+            });
+        }
+        for device in host.output_devices()? {
+            let configs = device
+                .supported_output_configs()
+                .map(|configs| configs.map(config_info).collect())
+                .unwrap_or_default();
+            let name = device.name()?;
+            devices.outputs.push(AudioDeviceInfo {
+                is_default: default_output.as_ref() == Some(&name),
+                name,
+                configs,
+            });
+        }
+        Ok(devices)
+    }
+
+    fn select_device(&self, direction: AudioDirection, name: Option<String>) -> Result<()> {
+        //Make sure the device exists before we try to open it mid call
+        if name.is_some() {
+            find_device(direction.clone(), name.clone())?;
+        }
+        let selected = match direction {
+            AudioDirection::Input => &self.input_device,
+            AudioDirection::Output => &self.output_device,
+        };
+        let mut selected = selected
+            .lock()
+            .map_err(|_| anyhow!("Audio device selection lock was poisoned"))?;
+        *selected = name;
+        Ok(())
+    }
+}
+
+fn config_info(config: cpal::SupportedStreamConfigRange) -> AudioConfigInfo {
+    AudioConfigInfo {
+        channels: config.channels(),
+        min_sample_rate: config.min_sample_rate().0,
+        max_sample_rate: config.max_sample_rate().0,
+        sample_format: format!("{:?}", config.sample_format()),
+    }
+}
+
+//Looks up a device by name, falling back to the system default when no name is given
+fn find_device(direction: AudioDirection, name: Option<String>) -> Result<cpal::Device> {
+    let host = cpal::default_host();
+    match (direction, name) {
+        (AudioDirection::Input, None) => host
+            .default_input_device()
+            .ok_or_else(|| anyhow!("Failed to find input device")),
+        (AudioDirection::Output, None) => host
+            .default_output_device()
+            .ok_or_else(|| anyhow!("Failed to find output device")),
+        (AudioDirection::Input, Some(name)) => host
+            .input_devices()?
+            .find(|d| d.name().map(|n| n == name).unwrap_or(false))
+            .ok_or_else(|| anyhow!("Failed to find input device {}", name)),
+        (AudioDirection::Output, Some(name)) => host
+            .output_devices()?
+            .find(|d| d.name().map(|n| n == name).unwrap_or(false))
+            .ok_or_else(|| anyhow!("Failed to find output device {}", name)),
+    }
+}
+
+//Prefer a config that runs at 48kHz so we can skip resampling
+fn input_config(device: &cpal::Device) -> Result<cpal::SupportedStreamConfig> {
+    let target = cpal::SampleRate(SAMPLE_RATE);
+    if let Ok(configs) = device.supported_input_configs() {
+        for config in configs {
+            if config.min_sample_rate() <= target && config.max_sample_rate() >= target {
+                return Ok(config.with_sample_rate(target));
+            }
+        }
+    }
+    Ok(device.default_input_config()?)
+}
+
+fn output_config(device: &cpal::Device) -> Result<cpal::SupportedStreamConfig> {
+    let target = cpal::SampleRate(SAMPLE_RATE);
+    if let Ok(configs) = device.supported_output_configs() {
+        for config in configs {
+            if config.min_sample_rate() <= target && config.max_sample_rate() >= target {
+                return Ok(config.with_sample_rate(target));
+            }
+        }
+    }
+    Ok(device.default_output_config()?)
+}
+
+fn build_input<T: cpal::Sample>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    frame_tx: FrameSender,
+) -> Result<cpal::Stream> {
+    let input_rate = config.sample_rate.0;
+    let input_channels = config.channels;
+    let mut resampler = Resampler::new(input_rate, SAMPLE_RATE);
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            //Never block the audio thread, hand the samples off to the encoder task
+            let samples: Vec<f32> = data.iter().map(|s| s.to_f32()).collect();
+            let mono = downmix(&samples, input_channels);
+            let _ = frame_tx.send(resampler.process(&mono));
+        },
+        |err| error!("Error capturing audio: {:?}", err),
+    )?;
+    Ok(stream)
+}
+
+fn build_output<T: cpal::Sample>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    buffer: PlaybackBuffer,
+) -> Result<cpal::Stream> {
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            let mut buffer = match buffer.lock() {
+                Ok(buffer) => buffer,
+                Err(_) => return,
+            };
+            for sample in data.iter_mut() {
+                *sample = T::from(&buffer.pop_front().unwrap_or(0.0));
+            }
+        },
+        |err| error!("Error playing audio: {:?}", err),
+    )?;
+    Ok(stream)
+}
+
+//Runs the cpal stream returned by `build` on its own thread since cpal streams are not Send on
+//every platform. The thread lives until the returned handle is dropped.
+async fn spawn_stream_thread<F, T>(build: F) -> Result<(AudioStream, T)>
+where
+    F: FnOnce() -> Result<(cpal::Stream, T)> + Send + 'static,
+    T: Send + 'static,
+{
+    let (stop_tx, stop_rx) = std::sync::mpsc::channel::<()>();
+    let (ready_tx, ready_rx) = oneshot::channel::<Result<T>>();
+    let runtime = Handle::current();
+
+    let thread_handle = thread::spawn(move || {
+        let _guard = runtime.enter();
+        match build() {
+            Ok((stream, value)) => {
+                let _ = ready_tx.send(Ok(value));
+                //Keep the stream alive until the handle is dropped
+                let _ = stop_rx.recv();
+                drop(stream);
+            }
+            Err(e) => {
+                let _ = ready_tx.send(Err(e));
+            }
+        }
+    });
+
+    match ready_rx.await {
+        Ok(Ok(value)) => Ok((AudioStream::from_thread(stop_tx, thread_handle), value)),
+        Ok(Err(e)) => Err(e),
+        Err(e) => Err(anyhow!("Audio thread exited before starting: {}", e)),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntheticInput {
+    Silence,
+    Sine(f32),
+}
+
+//Hardware free backend for tests and headless servers. Generates a tone or silence as input
+//and counts the samples it was asked to play.
+#[derive(Debug)]
+pub struct SyntheticBackend {
+    input: SyntheticInput,
+    played: Arc<AtomicUsize>,
+}
+
+impl SyntheticBackend {
+    pub fn sine(frequency: f32) -> Self {
+        Self {
+            input: SyntheticInput::Sine(frequency),
+            played: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn silence() -> Self {
+        Self {
+            input: SyntheticInput::Silence,
+            played: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn played_samples(&self) -> usize {
+        self.played.load(Ordering::Relaxed)
+    }
+}
+
+impl AudioBackend for SyntheticBackend {
+    fn start_input(self: Arc<Self>) -> BoxedFuture<Result<(AudioStream, FrameReceiver)>> {
+        Box::pin(async move {
+            let mut phase = 0f32;
+            let input = self.input.clone();
+            let frames = std::iter::repeat_with(move || match input {
+                SyntheticInput::Silence => vec![0.0; FRAME_SIZE],
+                SyntheticInput::Sine(frequency) => {
+                    let step = 2.0 * std::f32::consts::PI * frequency / SAMPLE_RATE as f32;
+                    (0..FRAME_SIZE)
+                        .map(|_| {
+                            phase = (phase + step) % (2.0 * std::f32::consts::PI);
+                            0.5 * phase.sin()
+                        })
+                        .collect()
+                }
+            });
+            Ok(spawn_paced_input(frames))
+        })
+    }
+
+    fn start_output(self: Arc<Self>) -> BoxedFuture<Result<(AudioStream, FrameSender)>> {
+        Box::pin(async move {
+            let played = Arc::clone(&self.played);
+            let (frame_tx, mut frame_rx) = mpsc::unbounded_channel::<Vec<f32>>();
+            let handle = tokio::spawn(async move {
+                while let Some(frame) = frame_rx.recv().await {
+                    played.fetch_add(frame.len(), Ordering::Relaxed);
+                }
+            });
+            let mut audio_stream = AudioStream::default();
+            audio_stream.push_task(handle);
+            Ok((audio_stream, frame_tx))
+        })
+    }
+}
+
+//Reads input from a WAV file and writes played audio to another WAV file.
+//Missing paths fall back to silence and discarding output.
+#[derive(Debug, Default)]
+pub struct FileBackend {
+    input_path: Option<PathBuf>,
+    output_path: Option<PathBuf>,
+}
+
+impl FileBackend {
+    pub fn new(input_path: Option<PathBuf>, output_path: Option<PathBuf>) -> Self {
+        Self {
+            input_path,
+            output_path,
+        }
+    }
+}
+
+impl AudioBackend for FileBackend {
+    fn start_input(self: Arc<Self>) -> BoxedFuture<Result<(AudioStream, FrameReceiver)>> {
+        Box::pin(async move {
+            let samples = match &self.input_path {
+                Some(path) => {
+                    let path = path.clone();
+                    tokio::task::spawn_blocking(move || read_wav(&path)).await??
+                }
+                None => Vec::new(),
+            };
+            info!("Streaming {} samples from file", samples.len());
+            let frames: Vec<Vec<f32>> = samples
+                .chunks(FRAME_SIZE)
+                .map(|chunk| {
+                    let mut frame = chunk.to_vec();
+                    frame.resize(FRAME_SIZE, 0.0);
+                    frame
+                })
+                .collect();
+            Ok(spawn_paced_input(frames.into_iter()))
+        })
+    }
+
+    fn start_output(self: Arc<Self>) -> BoxedFuture<Result<(AudioStream, FrameSender)>> {
+        Box::pin(async move {
+            let mut writer = match &self.output_path {
+                Some(path) => Some(hound::WavWriter::create(path, wav_spec())?),
+                None => None,
+            };
+            let (frame_tx, mut frame_rx) = mpsc::unbounded_channel::<Vec<f32>>();
+            let handle = tokio::spawn(async move {
+                while let Some(frame) = frame_rx.recv().await {
+                    if let Some(writer) = writer.as_mut() {
+                        for sample in frame {
+                            if let Err(e) = writer.write_sample(sample) {
+                                error!("Error writing audio to file: {}", e);
+                                return;
+                            }
+                        }
+                    }
+                }
+                if let Some(writer) = writer {
+                    if let Err(e) = writer.finalize() {
+                        error!("Error finalizing audio file: {}", e);
+                    }
+                }
+            });
+            let mut audio_stream = AudioStream::default();
+            audio_stream.push_task(handle);
+            Ok((audio_stream, frame_tx))
+        })
+    }
+}
+
+//Mono 48kHz float WAV, the same format the rest of the pipeline works in
+pub fn wav_spec() -> hound::WavSpec {
+    hound::WavSpec {
+        channels: 1,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    }
+}
+
+//Loads a WAV file as mono 48kHz samples
+pub fn read_wav(path: &PathBuf) -> Result<Vec<f32>> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let samples: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 / scale))
+                .collect::<Result<_, _>>()?
+        }
+    };
+    let mono = downmix(&samples, spec.channels);
+    Ok(resample(&mono, spec.sample_rate, SAMPLE_RATE))
+}
+
+//Emits one frame every 20ms so generated audio behaves like a real capture device
+fn spawn_paced_input<I>(frames: I) -> (AudioStream, FrameReceiver)
+where
+    I: Iterator<Item = Vec<f32>> + Send + 'static,
+{
+    let (frame_tx, frame_rx) = mpsc::unbounded_channel();
+    let handle = tokio::spawn(async move {
+        let mut ticker = interval(Duration::from_millis(FRAME_DURATION_MS));
+        for frame in frames {
+            ticker.tick().await;
+            if frame_tx.send(frame).is_err() {
+                break;
+            }
+        }
+    });
+    let mut audio_stream = AudioStream::default();
+    audio_stream.push_task(handle);
+    (audio_stream, frame_rx)
+}
@@ -0,0 +1,117 @@
+use webrtc::rtcp::{
+    packet::Packet,
+    payload_feedbacks::receiver_estimated_maximum_bitrate::ReceiverEstimatedMaximumBitrate,
+    receiver_report::ReceiverReport, sender_report::SenderReport,
+};
+
+//Bits per second. Below 12kbps voice gets hard to understand, above 64kbps there's nothing to gain
+pub const MIN_OPUS_BITRATE: i32 = 12_000;
+pub const MAX_OPUS_BITRATE: i32 = 64_000;
+pub const DEFAULT_OPUS_BITRATE: i32 = 32_000;
+
+//Loss above this is treated as congestion and the bitrate is cut
+const HIGH_LOSS: f32 = 0.10;
+//Loss below this means the link has room and the bitrate can grow
+const LOW_LOSS: f32 = 0.02;
+//In band FEC costs bitrate, so it is only turned on once packets actually go missing
+const FEC_LOSS: f32 = 0.01;
+const BITRATE_INCREASE: f32 = 1.08;
+//Weight of the newest report in the smoothed loss
+const LOSS_SMOOTHING: f32 = 0.3;
+
+//What the opus encoder should be set to for the current network conditions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncoderSettings {
+    pub bitrate: i32,
+    pub fec: bool,
+    pub packet_loss_perc: u8,
+}
+
+impl Default for EncoderSettings {
+    fn default() -> Self {
+        Self {
+            bitrate: DEFAULT_OPUS_BITRATE,
+            fec: false,
+            packet_loss_perc: 0,
+        }
+    }
+}
+
+//Loss based rate control in the spirit of GCC's sender side: back off on heavy loss, probe upwards
+//slowly on a clean link, and never go above what the peer's REMB says it can take
+#[derive(Debug)]
+pub struct BitrateController {
+    bitrate: f32,
+    loss: f32,
+    estimated_bandwidth: Option<f32>,
+}
+
+impl Default for BitrateController {
+    fn default() -> Self {
+        Self {
+            bitrate: DEFAULT_OPUS_BITRATE as f32,
+            loss: 0.0,
+            estimated_bandwidth: None,
+        }
+    }
+}
+
+impl BitrateController {
+    pub fn settings(&self) -> EncoderSettings {
+        EncoderSettings {
+            bitrate: self.bitrate.round() as i32,
+            fec: self.loss > FEC_LOSS,
+            packet_loss_perc: (self.loss * 100.0).round().min(100.0) as u8,
+        }
+    }
+
+    //fraction_lost as carried in a reception report, out of 256
+    pub fn on_loss(&mut self, fraction_lost: u8) {
+        let loss = fraction_lost as f32 / 256.0;
+        self.loss += (loss - self.loss) * LOSS_SMOOTHING;
+        if loss > HIGH_LOSS {
+            self.bitrate *= 1.0 - 0.5 * loss;
+        } else if loss < LOW_LOSS {
+            self.bitrate *= BITRATE_INCREASE;
+        }
+        self.clamp();
+    }
+
+    pub fn on_bandwidth_estimate(&mut self, bitrate: f32) {
+        self.estimated_bandwidth = Some(bitrate);
+        self.clamp();
+    }
+
+    //Feeds every packet of an RTCP compound packet through the controller. Returns true if
+    //anything in it was relevant
+    pub fn on_rtcp(&mut self, packets: &[Box<dyn Packet + Send + Sync>]) -> bool {
+        let mut updated = false;
+        for packet in packets {
+            let packet = packet.as_any();
+            let reports = if let Some(report) = packet.downcast_ref::<ReceiverReport>() {
+                &report.reports
+            } else if let Some(report) = packet.downcast_ref::<SenderReport>() {
+                &report.reports
+            } else if let Some(remb) = packet.downcast_ref::<ReceiverEstimatedMaximumBitrate>() {
+                self.on_bandwidth_estimate(remb.bitrate);
+                updated = true;
+                continue;
+            } else {
+                continue;
+            };
+            for report in reports {
+                self.on_loss(report.fraction_lost);
+                updated = true;
+            }
+        }
+        updated
+    }
+
+    fn clamp(&mut self) {
+        let max = match self.estimated_bandwidth {
+            Some(bandwidth) => bandwidth.min(MAX_OPUS_BITRATE as f32),
+            None => MAX_OPUS_BITRATE as f32,
+        };
+        self.bitrate = self.bitrate.min(max).max(MIN_OPUS_BITRATE as f32);
+    }
+}
@@ -0,0 +1,205 @@
+use anyhow::{anyhow, Result};
+
+use crate::utils::enums::{CallEndReason, CallSignal, SessionType};
+use crate::utils::types::NodeId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CallState {
+    #[default]
+    Idle,
+    //We called the peer and are waiting for them to pick up
+    Ringing(NodeId),
+    //The peer is calling us and the user hasn't answered yet
+    Incoming(NodeId),
+    Active(NodeId),
+}
+
+//Side effects the client has to carry out after a transition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallAction {
+    Signal(NodeId, CallSignal),
+    //Start the offerer side of the webrtc handshake
+    Offer(NodeId, SessionType),
+    //Start listening for the peer's offer
+    Answer(NodeId, SessionType),
+    Close(NodeId),
+    StartTimer(NodeId),
+    Event(CallEvent),
+}
+
+//Surfaced to the frontend
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallEvent {
+    Ringing(NodeId),
+    Incoming(NodeId),
+    Accepted(NodeId),
+    Ended(NodeId, CallEndReason),
+}
+
+//Tracks the single call the client can be in. Transitions are pure so they can be tested without
+//a network, the client applies the returned actions.
+#[derive(Debug, Default)]
+pub struct CallManager {
+    state: CallState,
+    //Voice or video, for the call in `state`
+    session_type: SessionType,
+}
+
+impl CallManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn state(&self) -> CallState {
+        self.state
+    }
+
+    pub fn session_type(&self) -> SessionType {
+        self.session_type
+    }
+
+    pub fn call(&mut self, node_id: NodeId, session_type: SessionType) -> Result<Vec<CallAction>> {
+        if !is_call(session_type) {
+            return Err(anyhow!("{:?} sessions aren't calls", session_type));
+        }
+        if self.state != CallState::Idle {
+            return Err(anyhow!("Already in a call"));
+        }
+        self.state = CallState::Ringing(node_id);
+        self.session_type = session_type;
+        Ok(vec![
+            CallAction::Signal(node_id, CallSignal::Ring(session_type)),
+            CallAction::StartTimer(node_id),
+            CallAction::Event(CallEvent::Ringing(node_id)),
+        ])
+    }
+
+    pub fn accept(&mut self) -> Result<Vec<CallAction>> {
+        match self.state {
+            CallState::Incoming(node_id) => {
+                self.state = CallState::Active(node_id);
+                //Listen for the offer before telling the caller to send it
+                Ok(vec![
+                    CallAction::Answer(node_id, self.session_type),
+                    CallAction::Signal(node_id, CallSignal::Accept(self.session_type)),
+                    CallAction::Event(CallEvent::Accepted(node_id)),
+                ])
+            }
+            _ => Err(anyhow!("No incoming call to accept")),
+        }
+    }
+
+    pub fn decline(&mut self) -> Result<Vec<CallAction>> {
+        match self.state {
+            CallState::Incoming(node_id) => {
+                self.state = CallState::Idle;
+                Ok(vec![
+                    CallAction::Signal(node_id, CallSignal::Decline),
+                    CallAction::Event(CallEvent::Ended(node_id, CallEndReason::Declined)),
+                ])
+            }
+            _ => Err(anyhow!("No incoming call to decline")),
+        }
+    }
+
+    //Cancels an outgoing call that hasn't been picked up yet, or ends an active one
+    pub fn hang_up(&mut self) -> Result<Vec<CallAction>> {
+        match self.state {
+            CallState::Ringing(node_id) => {
+                self.state = CallState::Idle;
+                Ok(vec![
+                    CallAction::Signal(node_id, CallSignal::Cancel),
+                    CallAction::Event(CallEvent::Ended(node_id, CallEndReason::Cancelled)),
+                ])
+            }
+            CallState::Active(node_id) => {
+                self.state = CallState::Idle;
+                Ok(vec![
+                    CallAction::Signal(node_id, CallSignal::HangUp),
+                    CallAction::Close(node_id),
+                    CallAction::Event(CallEvent::Ended(node_id, CallEndReason::HungUp)),
+                ])
+            }
+            CallState::Incoming(_) => self.decline(),
+            CallState::Idle => Err(anyhow!("Not in a call")),
+        }
+    }
+
+    pub fn on_signal(&mut self, node_id: NodeId, signal: CallSignal) -> Vec<CallAction> {
+        match (self.state, signal) {
+            (CallState::Idle, CallSignal::Ring(session_type)) if is_call(session_type) => {
+                self.state = CallState::Incoming(node_id);
+                self.session_type = session_type;
+                vec![
+                    CallAction::StartTimer(node_id),
+                    CallAction::Event(CallEvent::Incoming(node_id)),
+                ]
+            }
+            (CallState::Idle, CallSignal::Ring(_)) => Vec::new(),
+            (_, CallSignal::Ring(_)) => vec![CallAction::Signal(node_id, CallSignal::Busy)],
+            //The callee agreed to this kind of call, not just any
+            (CallState::Ringing(peer), CallSignal::Accept(session_type))
+                if peer == node_id && session_type == self.session_type =>
+            {
+                self.state = CallState::Active(node_id);
+                vec![
+                    CallAction::Offer(node_id, session_type),
+                    CallAction::Event(CallEvent::Accepted(node_id)),
+                ]
+            }
+            (CallState::Ringing(peer), CallSignal::Decline) if peer == node_id => {
+                self.end(node_id, CallEndReason::Declined)
+            }
+            (CallState::Ringing(peer), CallSignal::Busy) if peer == node_id => {
+                self.end(node_id, CallEndReason::Busy)
+            }
+            (CallState::Incoming(peer), CallSignal::Cancel) if peer == node_id => {
+                self.end(node_id, CallEndReason::Cancelled)
+            }
+            (CallState::Active(peer), CallSignal::HangUp) if peer == node_id => {
+                let mut actions = vec![CallAction::Close(node_id)];
+                actions.extend(self.end(node_id, CallEndReason::HungUp));
+                actions
+            }
+            //Stale or unexpected signal, e.g. a cancel that crossed with our accept
+            _ => Vec::new(),
+        }
+    }
+
+    //Fired once the ring timer runs out. Only matters if the call is still unanswered
+    pub fn on_timeout(&mut self, node_id: NodeId) -> Vec<CallAction> {
+        match self.state {
+            CallState::Ringing(peer) if peer == node_id => {
+                let mut actions = vec![CallAction::Signal(node_id, CallSignal::Cancel)];
+                actions.extend(self.end(node_id, CallEndReason::Missed));
+                actions
+            }
+            CallState::Incoming(peer) if peer == node_id => {
+                self.end(node_id, CallEndReason::Missed)
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    //The call's connection couldn't be opened or went away without a hang up, e.g. the peer's
+    //client crashed. The peer is told in case only our side noticed
+    pub fn on_connection_ended(&mut self, node_id: NodeId) -> Vec<CallAction> {
+        let signal = match self.state {
+            CallState::Ringing(peer) if peer == node_id => CallSignal::Cancel,
+            CallState::Active(peer) if peer == node_id => CallSignal::HangUp,
+            _ => return Vec::new(),
+        };
+        let mut actions = vec![CallAction::Signal(node_id, signal)];
+        actions.extend(self.end(node_id, CallEndReason::ConnectionLost));
+        actions
+    }
+
+    fn end(&mut self, node_id: NodeId, reason: CallEndReason) -> Vec<CallAction> {
+        self.state = CallState::Idle;
+        vec![CallAction::Event(CallEvent::Ended(node_id, reason))]
+    }
+}
+
+fn is_call(session_type: SessionType) -> bool {
+    matches!(session_type, SessionType::Call | SessionType::Video)
+}
@@ -0,0 +1,153 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::utils::types::NodeId;
+
+//Volume as a percentage, 100 is unchanged and anything above boosts the peer
+pub const DEFAULT_VOLUME: u16 = 100;
+pub const MAX_VOLUME: u16 = 200;
+
+//A drop-in voice channel. Every participant keeps a direct connection with an audio track to
+//every other participant, there is no server mixing the audio.
+//Participants can also go live, streaming video to whoever in the channel chooses to watch over a
+//separate connection per viewer
+#[derive(Debug)]
+pub struct VoiceChannel {
+    name: String,
+    participants: HashMap<NodeId, u16>,
+    //Participants that are live right now
+    streamers: HashSet<NodeId>,
+    //Streams we are watching
+    watching: HashSet<NodeId>,
+    //Who is watching us, only while we are live
+    viewers: Option<HashSet<NodeId>>,
+}
+
+impl VoiceChannel {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            participants: HashMap::new(),
+            streamers: HashSet::new(),
+            watching: HashSet::new(),
+            viewers: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    //Returns false if the peer was already in the channel
+    pub fn add_participant(&mut self, node_id: NodeId) -> bool {
+        if self.participants.contains_key(&node_id) {
+            return false;
+        }
+        self.participants.insert(node_id, DEFAULT_VOLUME);
+        true
+    }
+
+    //Also forgets their stream and stops them watching ours
+    pub fn remove_participant(&mut self, node_id: &NodeId) -> bool {
+        self.streamers.remove(node_id);
+        self.watching.remove(node_id);
+        self.remove_viewer(node_id);
+        self.participants.remove(node_id).is_some()
+    }
+
+    pub fn contains(&self, node_id: &NodeId) -> bool {
+        self.participants.contains_key(node_id)
+    }
+
+    pub fn set_volume(&mut self, node_id: &NodeId, volume: u16) -> Option<u16> {
+        let volume = volume.min(MAX_VOLUME);
+        let current = self.participants.get_mut(node_id)?;
+        *current = volume;
+        Some(volume)
+    }
+
+    pub fn participants(&self) -> Vec<(NodeId, u16)> {
+        self.participants
+            .iter()
+            .map(|(node_id, volume)| (*node_id, *volume))
+            .collect()
+    }
+
+    //Returns false if we were already live
+    pub fn go_live(&mut self) -> bool {
+        if self.viewers.is_some() {
+            return false;
+        }
+        self.viewers = Some(HashSet::new());
+        true
+    }
+
+    //Returns the viewers that were watching, None if we weren't live
+    pub fn end_live(&mut self) -> Option<Vec<NodeId>> {
+        self.viewers
+            .take()
+            .map(|viewers| viewers.into_iter().collect())
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.viewers.is_some()
+    }
+
+    //Only participants can watch, and only while we are live
+    pub fn add_viewer(&mut self, node_id: NodeId) -> bool {
+        if !self.participants.contains_key(&node_id) {
+            return false;
+        }
+        match self.viewers.as_mut() {
+            Some(viewers) => viewers.insert(node_id),
+            None => false,
+        }
+    }
+
+    pub fn remove_viewer(&mut self, node_id: &NodeId) -> bool {
+        self.viewers
+            .as_mut()
+            .is_some_and(|viewers| viewers.remove(node_id))
+    }
+
+    pub fn is_viewer(&self, node_id: &NodeId) -> bool {
+        self.viewers
+            .as_ref()
+            .is_some_and(|viewers| viewers.contains(node_id))
+    }
+
+    pub fn viewers(&self) -> Vec<NodeId> {
+        self.viewers.iter().flatten().copied().collect()
+    }
+
+    //Returns false if nothing changed. Watching a stream that ended stops. A streamer's
+    //announcement can beat their Present reply, so they don't have to be a participant yet
+    pub fn set_streaming(&mut self, node_id: NodeId, streaming: bool) -> bool {
+        if streaming {
+            self.streamers.insert(node_id)
+        } else {
+            self.watching.remove(&node_id);
+            self.streamers.remove(&node_id)
+        }
+    }
+
+    pub fn is_streaming(&self, node_id: &NodeId) -> bool {
+        self.streamers.contains(node_id)
+    }
+
+    //Returns false if the peer isn't live or we are already watching
+    pub fn watch(&mut self, node_id: NodeId) -> bool {
+        self.streamers.contains(&node_id) && self.watching.insert(node_id)
+    }
+
+    pub fn unwatch(&mut self, node_id: &NodeId) -> bool {
+        self.watching.remove(node_id)
+    }
+
+    pub fn is_watching(&self, node_id: &NodeId) -> bool {
+        self.watching.contains(node_id)
+    }
+
+    pub fn watching(&self) -> Vec<NodeId> {
+        self.watching.iter().copied().collect()
+    }
+}
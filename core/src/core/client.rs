@@ -1,28 +1,52 @@
-use crate::core::ipc::{IPCMessage, IPCResponse, SendUsersResp};
+use crate::core::audio::{self, AudioContext, AudioEvent, AudioEventReceiver, VoiceControls};
+use crate::core::backend::{AudioBackend, AudioDevices, CpalBackend};
+use crate::core::call::{CallAction, CallEvent, CallManager, CallState};
+use crate::core::channel::VoiceChannel;
+use crate::core::frame_source;
+use crate::core::ipc::{
+    CallEndedResp, CallResp, ConnectionClosedResp, ConnectionFailedResp, ConnectionStatusResp,
+    IPCErrorType, IPCMessage, IPCResponse, ParticipantInfo, ParticipantResp, PeerUnreachableResp,
+    PeerVoiceStateResp, RecordingResp, SendUsersResp, SoundsResp, SpeakingResp, StreamViewersResp,
+    VoiceMessagesResp, VoiceParticipantsResp,
+};
+use crate::core::negotiation;
+use crate::core::reconnect::Backoff;
 use crate::core::rtc::{APIWrapper, Connection, RTCConfigurationWrapper};
 use crate::core::signal::{SessionExchange, Signaler};
+use crate::core::soundboard;
+use crate::core::stats::ConnectionStats;
+use crate::core::video::{LiveFeed, VideoContext, VideoStream};
+use crate::core::voice_message::{self, VoiceNoteRecorder};
 use crate::database::{
     db::Database,
-    models::{FromRow, Message, User},
+    models::{FromRow, Message, User, VoiceMessageRecord},
 };
 
-use crate::utils::enums::{SessionType, UserStatus};
+use crate::utils::enums::{
+    AudioDirection, AudioProcessing, CallSignal, ConnectionFailure, ConnectionStatus,
+    HandshakePhase, SessionType, UserStatus, VideoSource, VoiceChannelSignal,
+};
 use crate::utils::{
     constants::{
-        SDP_ALPN, SEND_TEXT_MESSAGE_DELAY, SEND_TEXT_MESSAGE_TIMEOUT, SIGNAL_ALPN, STUN_SERVERS,
+        AUTO_GAIN_CONTROL_SETTING, CALL_RING_TIMEOUT, ECHO_CANCELLATION_SETTING,
+        ICE_RESTART_TIMEOUT, IDLE_CHECK_INTERVAL, IDLE_CONNECTION_TIMEOUT, INPUT_DEVICE_SETTING,
+        NOISE_SUPPRESSION_SETTING, OUTPUT_DEVICE_SETTING, RECORDINGS_DIR, SDP_ALPN,
+        SEND_TEXT_MESSAGE_DELAY, SEND_TEXT_MESSAGE_TIMEOUT, SIGNAL_ALPN, SOUNDBOARD_DIR,
+        STATS_INTERVAL, STUN_SERVERS, VIDEO_SOURCE_SETTING, VOICE_MESSAGES_DIR,
     },
-    enums::{ConnType, MessageType, RunMessage, SignalMessage},
-    types::{NodeId, TextMessage},
+    enums::{CloseReason, ConnType, MessageType, RunMessage},
+    errors::HandshakeError,
+    types::{BoxedFuture, NodeId, TextMessage, VoiceMessage},
 };
 
 use anyhow::Result;
 use futures::stream;
 use iroh::{
-    blobs::store::fs::Store,
+    blobs::{store::fs::Store, Hash},
     node::{Builder, Node},
 };
-use tokio::sync::{mpsc, oneshot, Mutex};
-use tokio::time::{sleep, timeout, Duration};
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::{sleep, sleep_until, timeout, Duration, Instant};
 use tokio_stream::wrappers::ReceiverStream;
 use tracing::{error, info};
 use webrtc::{
@@ -31,12 +55,15 @@ use webrtc::{
     },
     ice_transport::ice_server::RTCIceServer,
     interceptor::registry::Registry,
-    peer_connection::configuration::RTCConfiguration,
+    peer_connection::{
+        configuration::RTCConfiguration, peer_connection_state::RTCPeerConnectionState,
+    },
 };
 
 use futures::stream::StreamExt;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 #[derive(Debug)]
@@ -45,14 +72,42 @@ struct RTCConfig {
     config: RTCConfigurationWrapper,
 }
 
+//What is waiting to go out to a peer we couldn't reach
+#[derive(Debug)]
+enum QueuedMessage {
+    Text(String),
+    Voice(VoiceMessage),
+}
+
 #[derive(Debug)]
 pub struct Client {
-    connections: HashMap<String, Connection>,
+    connections: HashMap<NodeId, Connection>,
     rtc_config: RTCConfig,
     node: Node<Store>,
     session_exchange: Arc<SessionExchange>,
     db: Database,
     signaler: Arc<Signaler>,
+    audio: AudioContext,
+    audio_events_rx: Option<AudioEventReceiver>,
+    video: VideoContext,
+    call: CallManager,
+    voice_channel: Option<VoiceChannel>,
+    //Our Go-Live stream, while we are live in a voice channel
+    live_feed: Option<(LiveFeed, VideoStream)>,
+    //Go-Live connections, to our viewers or to the streamers we watch. Kept apart from the call
+    //connections since both exist with the same peer at once
+    stream_connections: HashMap<NodeId, Connection>,
+    //Voice channel connections, so joining or leaving doesn't touch a chat with the same peer
+    channel_connections: HashMap<NodeId, Connection>,
+    //Peers whose connection dropped and is being restored, and the messages waiting on them
+    reconnecting: HashSet<NodeId>,
+    queued_messages: HashMap<NodeId, Vec<QueuedMessage>>,
+    //Peers we are opening a chat with because messages are waiting for them
+    connecting: HashSet<NodeId>,
+    data_dir: PathBuf,
+    voice_note: Option<VoiceNoteRecorder>,
+    //Lets connection tasks hand work back to the runtime loop, set once the client is running
+    runtime_tx: Option<mpsc::Sender<RunMessage>>,
 }
 
 impl Client {
@@ -126,7 +181,8 @@ impl Client {
             Err(e) => error!("Error initializing account. Error msg: {}", e),
         }
 
-        Client {
+        let (audio, audio_events_rx) = AudioContext::new(Arc::new(CpalBackend::new()));
+        let client = Client {
             connections: HashMap::new(),
             rtc_config: RTCConfig {
                 api: APIWrapper(api),
@@ -136,9 +192,638 @@ impl Client {
             session_exchange,
             db,
             signaler,
+            audio,
+            audio_events_rx: Some(audio_events_rx),
+            video: VideoContext::default(),
+            call: CallManager::new(),
+            voice_channel: None,
+            live_feed: None,
+            stream_connections: HashMap::new(),
+            channel_connections: HashMap::new(),
+            reconnecting: HashSet::new(),
+            queued_messages: HashMap::new(),
+            connecting: HashSet::new(),
+            data_dir: PathBuf::from(root),
+            voice_note: None,
+            runtime_tx: None,
+        };
+        client.load_audio_devices();
+        client.load_audio_processing();
+        client.load_video_source();
+        client
+    }
+
+    //Swap out where call audio comes from and goes to, e.g. a synthetic backend in tests
+    pub fn set_audio_backend(&mut self, audio_backend: Arc<dyn AudioBackend>) {
+        self.audio.backend = audio_backend;
+        self.load_audio_devices();
+    }
+
+    //Restores the devices picked in a previous session. Devices that have since been unplugged
+    //fall back to the system default
+    fn load_audio_devices(&self) {
+        for (direction, key) in [
+            (AudioDirection::Input, INPUT_DEVICE_SETTING),
+            (AudioDirection::Output, OUTPUT_DEVICE_SETTING),
+        ] {
+            match self.db.get_setting(key) {
+                Ok(Some(device)) => {
+                    if let Err(e) = self.audio.backend.select_device(direction, Some(device)) {
+                        error!("Failed to restore audio device: {}", e);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => error!("Error reading audio device setting: {}", e),
+            }
+        }
+    }
+
+    fn load_audio_processing(&self) {
+        for processing in [
+            AudioProcessing::EchoCancellation,
+            AudioProcessing::NoiseSuppression,
+            AudioProcessing::AutoGainControl,
+        ] {
+            match self.db.get_setting(audio_processing_setting(processing)) {
+                Ok(Some(enabled)) => self.audio.processing.set(processing, enabled == "true"),
+                Ok(None) => {}
+                Err(e) => error!("Error reading audio processing setting: {}", e),
+            }
+        }
+    }
+
+    //Takes effect on running calls straight away
+    pub fn set_audio_processing(&self, processing: AudioProcessing, enabled: bool) -> Result<()> {
+        self.audio.processing.set(processing, enabled);
+        self.db.write_setting(
+            audio_processing_setting(processing),
+            Some(&enabled.to_string()),
+        )
+    }
+
+    fn load_video_source(&self) {
+        match self.db.get_setting(VIDEO_SOURCE_SETTING) {
+            Ok(Some(source)) => match serde_json::from_str(&source) {
+                Ok(source) => self.video.set_source(source),
+                Err(e) => error!("Invalid video source setting: {}", e),
+            },
+            Ok(None) => {}
+            Err(e) => error!("Error reading video source setting: {}", e),
+        }
+    }
+
+    pub fn get_video_source(&self) -> VideoSource {
+        self.video.source()
+    }
+
+    //Used by video calls started from now on
+    pub fn set_video_source(&self, source: VideoSource) -> Result<()> {
+        if let VideoSource::File(path) = &source {
+            if !Path::new(path).exists() {
+                return Err(anyhow::anyhow!("Video file {} does not exist", path));
+            }
+        }
+        self.db
+            .write_setting(VIDEO_SOURCE_SETTING, Some(&serde_json::to_string(&source)?))?;
+        self.video.set_source(source);
+        Ok(())
+    }
+
+    pub fn get_audio_devices(&self) -> Result<AudioDevices> {
+        self.audio.backend.list_devices()
+    }
+
+    //Selects and persists the device, then moves any active calls over to it
+    pub async fn set_audio_device(
+        &mut self,
+        direction: AudioDirection,
+        device: Option<String>,
+    ) -> Result<()> {
+        self.audio
+            .backend
+            .select_device(direction.clone(), device.clone())?;
+        let key = match direction {
+            AudioDirection::Input => INPUT_DEVICE_SETTING,
+            AudioDirection::Output => OUTPUT_DEVICE_SETTING,
+        };
+        self.db.write_setting(key, device.as_deref())?;
+
+        match direction {
+            AudioDirection::Input => {
+                let connections = self.connections.values_mut();
+                for conn in connections.chain(self.channel_connections.values_mut()) {
+                    conn.restart_capture().await?;
+                }
+            }
+            AudioDirection::Output => {
+                let backend = Arc::clone(&self.audio.backend);
+                self.audio.playback.switch_output(backend).await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn voice_controls(&self) -> Arc<VoiceControls> {
+        Arc::clone(&self.audio.controls)
+    }
+
+    pub async fn get_connection_stats(&self, node_id: NodeId) -> Result<ConnectionStats> {
+        self.connections
+            .get(&node_id)
+            .or_else(|| self.channel_connections.get(&node_id))
+            .ok_or_else(|| anyhow::anyhow!("No connection to {}", node_id.fmt_short()))?
+            .get_connection_stats()
+            .await
+    }
+
+    //Lets everyone we are in a call with know our mute/deafen/recording state
+    pub async fn broadcast_voice_state(&self) {
+        for (remote_node_id, conn) in self.audio_connections() {
+            if conn.has_audio() {
+                self.send_voice_state(*remote_node_id);
+            }
+        }
+    }
+
+    //Calls and everyone in our voice channel
+    fn audio_connections(&self) -> impl Iterator<Item = (&NodeId, &Connection)> {
+        self.connections
+            .iter()
+            .chain(self.channel_connections.iter())
+    }
+
+    pub fn send_voice_state(&self, remote_node_id: NodeId) {
+        let mut voice_state = self.audio.controls.state();
+        voice_state.recording = self.audio.recording.is_active();
+        let signaler = Arc::clone(&self.signaler);
+        tokio::spawn(async move {
+            if let Err(e) = signaler
+                .notify_voice_state(remote_node_id, voice_state)
+                .await
+            {
+                error!("Error sending voice state: {}", e);
+            }
+        });
+    }
+
+    //Only for presenting a peer, falls back to the short node id for unknown peers
+    pub fn get_display_name_of(&self, node_id: NodeId) -> String {
+        serde_json::to_string(&node_id)
+            .map_err(anyhow::Error::from)
+            .and_then(|node_id| self.get_display_name(node_id))
+            .unwrap_or_else(|_| node_id.fmt_short())
+    }
+
+    //Only chats, calls and streams are ended by the people in them
+    pub async fn close_idle_connections(&mut self, idle_timeout: Duration) -> Result<Vec<NodeId>> {
+        let mut idle = Vec::new();
+        for (node_id, conn) in self.connections.iter() {
+            if *conn.session_type() == SessionType::Chat && conn.idle_for().await >= idle_timeout {
+                idle.push(*node_id);
+            }
+        }
+        for node_id in idle.iter() {
+            info!("Closing idle connection to {}", node_id.fmt_short());
+            self.close_connection_with(*node_id).await?;
+        }
+        Ok(idle)
+    }
+
+    //Forgets a connection that ended on its own. Returns false if it was already gone or replaced
+    async fn remove_connection(
+        &mut self,
+        node_id: NodeId,
+        session_type: &SessionType,
+        connection_id: u64,
+    ) -> Result<bool> {
+        let connections = self.connections_for(session_type);
+        let mut conn = match connections.get(&node_id) {
+            Some(conn) if conn.id() == connection_id => connections.remove(&node_id),
+            _ => None,
+        };
+        match conn.as_mut() {
+            Some(conn) => {
+                conn.close_connection().await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn connections_for(&mut self, session_type: &SessionType) -> &mut HashMap<NodeId, Connection> {
+        match session_type {
+            SessionType::Stream => &mut self.stream_connections,
+            SessionType::VoiceChannel => &mut self.channel_connections,
+            _ => &mut self.connections,
+        }
+    }
+
+    //Returns false if there was no connection with the peer
+    pub async fn close_connection_with(&mut self, node_id: NodeId) -> Result<bool> {
+        self.reconnecting.remove(&node_id);
+        match self.connections.remove(&node_id) {
+            Some(mut conn) => {
+                conn.close_connection().await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    //Announces that we joined. Peers already in the channel reply with Present
+    pub fn join_voice_channel(&mut self, channel: String) -> Result<()> {
+        if self.voice_channel.is_some() || self.call.state() != CallState::Idle {
+            return Err(anyhow::anyhow!("Already in a call"));
+        }
+        self.voice_channel = Some(VoiceChannel::new(channel.clone()));
+        for node_id in self.db.get_node_ids()? {
+            self.notify_voice_channel(node_id, channel.clone(), VoiceChannelSignal::Join);
+        }
+        Ok(())
+    }
+
+    pub async fn leave_voice_channel(&mut self) -> Result<()> {
+        let voice_channel = self
+            .voice_channel
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("Not in a voice channel"))?;
+        //Leaving ends our stream and any we were watching, everyone finds out through Leave
+        self.live_feed = None;
+        for (_, mut conn) in self.stream_connections.drain() {
+            conn.close_connection().await?;
+        }
+        for (node_id, _) in voice_channel.participants() {
+            let channel = voice_channel.name().to_string();
+            self.notify_voice_channel(node_id, channel, VoiceChannelSignal::Leave);
+        }
+        for (_, mut conn) in self.channel_connections.drain() {
+            conn.close_connection().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn set_participant_volume(&mut self, node_id: NodeId, volume: u16) -> Result<()> {
+        let volume = self
+            .voice_channel
+            .as_mut()
+            .and_then(|voice_channel| voice_channel.set_volume(&node_id, volume))
+            .ok_or_else(|| anyhow::anyhow!("Peer is not in our voice channel"))?;
+        if let Some(conn) = self.channel_connections.get(&node_id) {
+            conn.set_volume(volume as f32 / 100.0).await;
+        }
+        Ok(())
+    }
+
+    fn notify_voice_channel(
+        &self,
+        node_id: NodeId,
+        channel: String,
+        channel_signal: VoiceChannelSignal,
+    ) {
+        let signaler = Arc::clone(&self.signaler);
+        tokio::spawn(async move {
+            if let Err(e) = signaler
+                .notify_voice_channel(node_id, channel, channel_signal)
+                .await
+            {
+                error!("Error sending {:?} to peer: {}", channel_signal, e);
+            }
+        });
+    }
+
+    //Goes live in our voice channel. Everyone in it is told and can choose to watch
+    pub fn start_stream(&mut self, source: VideoSource) -> Result<()> {
+        let voice_channel = self
+            .voice_channel
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("Not in a voice channel"))?;
+        if voice_channel.is_live() {
+            return Err(anyhow::anyhow!("Already streaming"));
+        }
+        let source = frame_source::open_source(&source)?;
+        voice_channel.go_live();
+        let channel = voice_channel.name().to_string();
+        let participants = voice_channel.participants();
+        self.live_feed = Some(LiveFeed::start(source));
+        for (node_id, _) in participants {
+            self.notify_voice_channel(node_id, channel.clone(), VoiceChannelSignal::StreamStarted);
+        }
+        Ok(())
+    }
+
+    pub async fn stop_stream(&mut self) -> Result<()> {
+        let voice_channel = self
+            .voice_channel
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("Not in a voice channel"))?;
+        let viewers = voice_channel
+            .end_live()
+            .ok_or_else(|| anyhow::anyhow!("Not streaming"))?;
+        let channel = voice_channel.name().to_string();
+        let participants = voice_channel.participants();
+        self.live_feed = None;
+        for (node_id, _) in participants {
+            self.notify_voice_channel(node_id, channel.clone(), VoiceChannelSignal::StreamEnded);
+        }
+        for node_id in viewers {
+            self.close_stream_connection(node_id).await?;
+        }
+        Ok(())
+    }
+
+    //The streamer offers once they get Watch, so the caller has to be ready to answer
+    pub fn watch_stream(&mut self, node_id: NodeId) -> Result<()> {
+        let voice_channel = self
+            .voice_channel
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("Not in a voice channel"))?;
+        if !voice_channel.watch(node_id) {
+            return Err(anyhow::anyhow!(
+                "Peer is not streaming or we are already watching"
+            ));
+        }
+        let channel = voice_channel.name().to_string();
+        self.notify_voice_channel(node_id, channel, VoiceChannelSignal::Watch);
+        Ok(())
+    }
+
+    pub async fn unwatch_stream(&mut self, node_id: NodeId) -> Result<()> {
+        let voice_channel = self
+            .voice_channel
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("Not in a voice channel"))?;
+        if !voice_channel.unwatch(&node_id) {
+            return Err(anyhow::anyhow!("Not watching that stream"));
+        }
+        let channel = voice_channel.name().to_string();
+        self.notify_voice_channel(node_id, channel, VoiceChannelSignal::Unwatch);
+        self.close_stream_connection(node_id).await
+    }
+
+    async fn close_stream_connection(&mut self, node_id: NodeId) -> Result<()> {
+        if let Some(mut conn) = self.stream_connections.remove(&node_id) {
+            conn.close_connection().await?;
+        }
+        Ok(())
+    }
+
+    //Returns false if the connection was closed instead, e.g. a stream nobody wants anymore
+    async fn add_connection(
+        &mut self,
+        node_id: NodeId,
+        session_type: &SessionType,
+        conn: Connection,
+    ) -> Result<bool> {
+        match session_type {
+            SessionType::Stream => return self.add_stream_connection(node_id, conn).await,
+            SessionType::VoiceChannel => return self.add_channel_connection(node_id, conn).await,
+            _ => {}
+        }
+        if let Some(mut previous) = self.connections.insert(node_id, conn) {
+            previous.close_connection().await?;
+        }
+        self.reconnecting.remove(&node_id);
+        //Let the peer know if we are muted or recording before they hear anything
+        if matches!(session_type, SessionType::Call | SessionType::Video) {
+            self.send_voice_state(node_id);
+        }
+        if let Err(e) = self.flush_queued_messages(node_id).await {
+            error!("Error sending queued messages {}", e);
+        }
+        Ok(true)
+    }
+
+    //Keeps a voice channel connection unless the peer left while it was connecting
+    async fn add_channel_connection(
+        &mut self,
+        node_id: NodeId,
+        mut conn: Connection,
+    ) -> Result<bool> {
+        let wanted = self
+            .voice_channel
+            .as_ref()
+            .is_some_and(|voice_channel| voice_channel.contains(&node_id));
+        if !wanted {
+            conn.close_connection().await?;
+            return Ok(false);
+        }
+        if let Some(mut previous) = self.channel_connections.insert(node_id, conn) {
+            previous.close_connection().await?;
+        }
+        self.send_voice_state(node_id);
+        Ok(true)
+    }
+
+    async fn close_channel_connection(&mut self, node_id: NodeId) -> Result<()> {
+        if let Some(mut conn) = self.channel_connections.remove(&node_id) {
+            conn.close_connection().await?;
+        }
+        Ok(())
+    }
+
+    //Forgets a participant who left or can't be reached, along with their connections. Returns
+    //what the frontend has to be told
+    async fn remove_participant(&mut self, node_id: NodeId) -> Result<Vec<IPCResponse>> {
+        let Some(voice_channel) = self.voice_channel.as_mut() else {
+            return Ok(Vec::new());
+        };
+        let was_viewer = voice_channel.is_viewer(&node_id);
+        if !voice_channel.remove_participant(&node_id) {
+            return Ok(Vec::new());
+        }
+        let channel = voice_channel.name().to_string();
+        let mut responses = vec![IPCResponse::ParticipantLeft(ParticipantResp {
+            node_id,
+            channel,
+        })];
+        if was_viewer {
+            responses.push(stream_viewers(&self.voice_channel));
+        }
+        self.close_channel_connection(node_id).await?;
+        self.close_stream_connection(node_id).await?;
+        Ok(responses)
+    }
+
+    //Keeps a stream connection once it is up. Returns false if the stream ended or the viewer left
+    //while it was connecting, in which case it is closed instead
+    async fn add_stream_connection(
+        &mut self,
+        node_id: NodeId,
+        mut conn: Connection,
+    ) -> Result<bool> {
+        let wanted = self.voice_channel.as_ref().is_some_and(|voice_channel| {
+            voice_channel.is_viewer(&node_id) || voice_channel.is_watching(&node_id)
+        });
+        if !wanted {
+            conn.close_connection().await?;
+            return Ok(false);
+        }
+        if let Some(mut previous) = self.stream_connections.insert(node_id, conn) {
+            previous.close_connection().await?;
+        }
+        Ok(true)
+    }
+
+    //Everyone in the call is told we are recording through their voice state
+    pub async fn start_recording(&self) -> Result<PathBuf> {
+        let directory = self
+            .audio
+            .recording
+            .start(&self.data_dir.join(RECORDINGS_DIR))?;
+        self.broadcast_voice_state().await;
+        Ok(directory)
+    }
+
+    pub async fn stop_recording(&self) -> Result<PathBuf> {
+        let directory = self.audio.recording.stop()?;
+        self.broadcast_voice_state().await;
+        Ok(directory)
+    }
+
+    pub fn list_sounds(&self) -> Result<Vec<String>> {
+        soundboard::list_sounds(&self.data_dir.join(SOUNDBOARD_DIR))
+    }
+
+    //Plays a clip from the soundboard into every call we are in
+    pub fn play_sound(&self, name: &str) -> Result<()> {
+        if !self.list_sounds()?.iter().any(|sound| sound == name) {
+            return Err(anyhow::anyhow!("No sound named {}", name));
+        }
+        let path = self
+            .data_dir
+            .join(SOUNDBOARD_DIR)
+            .join(format!("{}.ogg", name));
+        let sound = soundboard::load_sound(&path)?;
+        if !self.audio.soundboard.play(sound) {
+            return Err(anyhow::anyhow!("Not in a call"));
+        }
+        Ok(())
+    }
+
+    pub async fn record_voice_message(&mut self) -> Result<()> {
+        if self.voice_note.is_some() {
+            return Err(anyhow::anyhow!("Already recording a voice message"));
+        }
+        let recorder = VoiceNoteRecorder::start(Arc::clone(&self.audio.backend)).await?;
+        self.voice_note = Some(recorder);
+        Ok(())
+    }
+
+    pub fn cancel_voice_message(&mut self) -> Result<()> {
+        let recorder = self
+            .voice_note
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("Not recording a voice message"))?;
+        recorder.finish();
+        Ok(())
+    }
+
+    //Stops recording and sends whatever was recorded
+    pub async fn send_voice_message(&mut self, node_id: NodeId) -> Result<VoiceMessage> {
+        let recorder = self
+            .voice_note
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("Not recording a voice message"))?;
+        let samples = recorder.finish();
+        self.send_voice_samples(node_id, &samples).await
+    }
+
+    pub async fn import_voice_message(
+        &mut self,
+        node_id: NodeId,
+        path: &Path,
+    ) -> Result<VoiceMessage> {
+        let samples = voice_message::import_samples(path)?;
+        self.send_voice_samples(node_id, &samples).await
+    }
+
+    //Encodes the audio to Ogg/Opus and adds it to our blob store, the peer only gets the hash
+    async fn send_voice_samples(
+        &mut self,
+        node_id: NodeId,
+        samples: &[f32],
+    ) -> Result<VoiceMessage> {
+        let timestamp = chrono::Utc::now();
+        let directory = self.data_dir.join(VOICE_MESSAGES_DIR);
+        std::fs::create_dir_all(&directory)?;
+        let path = directory.join(format!("{}.ogg", timestamp.format("%Y%m%d-%H%M%S%3f")));
+        voice_message::write_voice_message(samples, &path)?;
+        let bytes = std::fs::read(&path)?;
+        std::fs::remove_file(&path)?;
+        let hash = self.node.blobs().add_bytes(bytes).await?.hash;
+
+        let voice_message = VoiceMessage {
+            sender: self.get_node_id(),
+            hash,
+            duration_ms: voice_message::duration_ms(samples),
+            waveform: voice_message::waveform(samples),
+            timestamp,
+        };
+        self.store_voice_message(&voice_message, false)?;
+        self.deliver_voice_message(node_id, voice_message.clone())
+            .await;
+        Ok(voice_message)
+    }
+
+    //Queued like a text message when the peer can't be reached, the audio stays in our blob store
+    async fn deliver_voice_message(&mut self, node_id: NodeId, voice_message: VoiceMessage) {
+        let sent = match self.connections.get(&node_id) {
+            Some(conn) if !self.reconnecting.contains(&node_id) => {
+                match conn.send_voice_message(&voice_message).await {
+                    Ok(()) => true,
+                    Err(e) => {
+                        error!("Error sending voice message {}", e);
+                        false
+                    }
+                }
+            }
+            _ => false,
+        };
+        if !sent {
+            info!(
+                "Queueing voice message until {} is connected",
+                node_id.fmt_short()
+            );
+            self.queued_messages
+                .entry(node_id)
+                .or_default()
+                .push(QueuedMessage::Voice(voice_message));
         }
     }
 
+    pub fn store_voice_message(&self, voice_message: &VoiceMessage, received: bool) -> Result<()> {
+        let record = VoiceMessageRecord {
+            voice_message_id: 0, //dummy id, assigned by the db
+            sender_node_id: serde_json::to_string(&voice_message.sender)?,
+            blob_hash: voice_message.hash.to_string(),
+            duration_ms: voice_message.duration_ms as i64,
+            waveform: voice_message.waveform.clone(),
+            sent_ts: Some(voice_message.timestamp.to_rfc3339()),
+            received_ts: received.then(|| chrono::Utc::now().to_rfc3339()),
+        };
+        self.db.write_voice_message(record)
+    }
+
+    pub fn get_voice_messages(&self, node_id: NodeId) -> Result<Vec<VoiceMessage>> {
+        let sender_node_id = serde_json::to_string(&node_id)?;
+        self.db
+            .get_voice_messages(&sender_node_id)?
+            .into_iter()
+            .map(|record| {
+                let timestamp = record
+                    .sent_ts
+                    .ok_or_else(|| anyhow::anyhow!("Voice message has no timestamp"))?;
+                Ok(VoiceMessage {
+                    sender: node_id,
+                    hash: record.blob_hash.parse()?,
+                    duration_ms: record.duration_ms as u64,
+                    waveform: record.waveform,
+                    timestamp: chrono::DateTime::parse_from_rfc3339(&timestamp)?.into(),
+                })
+            })
+            .collect()
+    }
+
     pub fn store_message(&mut self, message: TextMessage) -> Result<()> {
         let db = &mut self.db;
 
@@ -186,12 +871,25 @@ impl Client {
         node_id.clone()
     }
 
-    pub async fn send_message(&mut self, display_name: String, message: String) -> Result<()> {
+    pub async fn send_message(&mut self, node_id: NodeId, message: String) -> Result<()> {
+        let conn = match self.connections.get(&node_id) {
+            Some(conn) if !self.reconnecting.contains(&node_id) => conn,
+            _ => {
+                info!(
+                    "Queueing message until {} is connected",
+                    node_id.fmt_short()
+                );
+                self.queued_messages
+                    .entry(node_id)
+                    .or_default()
+                    .push(QueuedMessage::Text(message));
+                return Ok(());
+            }
+        };
         let message = TextMessage {
             content: message,
             timestamp: chrono::Utc::now(),
         };
-        let conn = &self.connections[&display_name];
         match timeout(Duration::from_secs(SEND_TEXT_MESSAGE_TIMEOUT), async {
             loop {
                 match conn.send_dc_message(message.content.clone()).await {
@@ -205,7 +903,14 @@ impl Client {
         .await
         {
             Ok(_) => info!("Succesfully sent text message"),
-            Err(_) => error!("Failed to send message. Will try again when peer is online"),
+            Err(_) => {
+                error!("Failed to send message. Will try again when peer is online");
+                self.queued_messages
+                    .entry(node_id)
+                    .or_default()
+                    .push(QueuedMessage::Text(message.content));
+                return Ok(());
+            }
         }
         let db = &mut self.db;
 
@@ -226,6 +931,39 @@ impl Client {
         Ok(())
     }
 
+    //True if a message to the peer has nothing to go over and we aren't already connecting, the
+    //caller opens a chat so it can be delivered
+    pub fn needs_connection(&mut self, node_id: NodeId) -> bool {
+        if self.connections.contains_key(&node_id)
+            || self.reconnecting.contains(&node_id)
+            || !self.queued_messages.contains_key(&node_id)
+        {
+            return false;
+        }
+        self.connecting.insert(node_id)
+    }
+
+    pub fn queued_message_count(&self, node_id: &NodeId) -> usize {
+        self.queued_messages.get(node_id).map_or(0, Vec::len)
+    }
+
+    //Sends what was queued for the peer while they couldn't be reached
+    pub async fn flush_queued_messages(&mut self, node_id: NodeId) -> Result<()> {
+        if !self.connections.contains_key(&node_id) {
+            return Ok(());
+        }
+        let messages = self.queued_messages.remove(&node_id).unwrap_or_default();
+        for message in messages {
+            match message {
+                QueuedMessage::Text(content) => self.send_message(node_id, content).await?,
+                QueuedMessage::Voice(voice_message) => {
+                    self.deliver_voice_message(node_id, voice_message).await
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub async fn read_messages(&mut self, node_id: NodeId) -> Result<Vec<Message>> {
         let db = &mut self.db;
         let conn = db.get_conn();
@@ -250,12 +988,12 @@ impl Client {
         Ok(node_id)
     }
 
-    pub fn get_user(&self, display_name: String) -> Result<User> {
+    pub fn get_user(&self, node_id: NodeId) -> Result<User> {
         let db = &self.db;
         let conn = db.get_conn();
         let user = conn.query_row(
             "select * from users where node_id = ?1",
-            [display_name],
+            [serde_json::to_string(&node_id)?],
             User::from_row,
         )?;
         Ok(user)
@@ -285,7 +1023,7 @@ impl Client {
 //Main runtime loop of backend
 //TODO: establish audio stream connections + file transmition
 pub async fn run(
-    client: Client,
+    mut client: Client,
     tx: mpsc::Sender<RunMessage>,
     mut rx: mpsc::Receiver<RunMessage>,
     data_tx: mpsc::Sender<IPCResponse>,
@@ -293,31 +1031,174 @@ pub async fn run(
     info!("Client is running...");
     //Pass sender so that the signaler can signal when an peer wants to establish a connection
     client.signaler.init_sender(tx.clone()).await;
+    client.runtime_tx = Some(tx.clone());
+
+    //Forward speaking events from the audio tasks through the runtime so they reach the frontend
+    if let Some(mut audio_events_rx) = client.audio_events_rx.take() {
+        let local_node_id = client.get_node_id();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            while let Some(event) = audio_events_rx.recv().await {
+                let message = match event {
+                    AudioEvent::Speaking(node_id, speaking) => {
+                        RunMessage::Speaking(node_id.unwrap_or(local_node_id), speaking)
+                    }
+                };
+                if tx.send(message).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    //Chats nobody is using are closed so a long running daemon doesn't pile them up
+    {
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_secs(IDLE_CHECK_INTERVAL));
+            loop {
+                ticker.tick().await;
+                if tx.send(RunMessage::CloseIdleConnections).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    //Drives the connection quality indicator while in a call
+    {
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_secs(STATS_INTERVAL));
+            loop {
+                ticker.tick().await;
+                if tx.send(RunMessage::PublishConnectionStats).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
     let client = Arc::new(Mutex::new(client));
     while let Some(message) = rx.recv().await {
         match message {
             RunMessage::RecvConn(session_type) => {
                 info!("Run message received");
                 let client = Arc::clone(&client);
-                let handle = tokio::spawn(receive_connection(client, SessionType::Chat));
+                //Calls only connect once the user accepts, see AcceptCall
+                if matches!(
+                    session_type,
+                    SessionType::Call | SessionType::Video | SessionType::VoiceChannel
+                ) {
+                    error!(
+                        "Ignoring {:?} connection that wasn't accepted",
+                        session_type
+                    );
+                    continue;
+                }
+                tokio::spawn(receive_connection(client, session_type, None));
             }
-            RunMessage::InitConn(session_type, display_name) => {
+            RunMessage::InitConn(session_type, node_id) => {
                 let client = Arc::clone(&client);
                 let client2 = Arc::clone(&client);
 
-                let client2 = client2.lock().await;
+                let mut client2 = client2.lock().await;
 
-                let node_id = client2.get_user_node_id(&display_name)?;
+                //Calls ring the peer first and only connect once they accept
+                if matches!(session_type, SessionType::Call | SessionType::Video) {
+                    let actions = client2.call.call(node_id, session_type);
+                    drop(client2);
+                    match actions {
+                        Ok(actions) => apply_call_actions(&client, actions, &tx, &data_tx).await?,
+                        Err(e) => error!("Failed to start call {}", e),
+                    }
+                    continue;
+                }
 
-                let handle =
-                    tokio::spawn(init_connection(client, node_id, display_name, session_type));
+                tokio::spawn(init_connection(client, node_id, session_type));
             }
-            //Assumes connection is already established
-            RunMessage::SendMessage(display_name, message) => {
-                //Send message after connection is established
-                let client = Arc::clone(&client);
-                let mut client = client.lock().await;
-                client.send_message(display_name, message).await?;
+            RunMessage::CloseConn(node_id) => {
+                let reason = CloseReason::Requested;
+                if let Err(e) = close_peer(&client, node_id, reason, &tx, &data_tx).await {
+                    error!("Error closing connection to {}: {}", node_id.fmt_short(), e);
+                }
+            }
+            //Only the voice channel was lost, a chat with the peer carries on
+            RunMessage::ConnectionLost(node_id, SessionType::VoiceChannel) => {
+                let responses = client.lock().await.remove_participant(node_id).await;
+                match responses {
+                    Ok(responses) => {
+                        for response in responses {
+                            data_tx.send(response).await?;
+                        }
+                        let reason = CloseReason::Failed;
+                        let response = ConnectionClosedResp { node_id, reason };
+                        data_tx
+                            .send(IPCResponse::ConnectionClosed(response))
+                            .await?;
+                    }
+                    Err(e) => error!("Error cleaning up after {}: {}", node_id.fmt_short(), e),
+                }
+            }
+            RunMessage::ConnectionLost(node_id, _) => {
+                let reason = CloseReason::Failed;
+                if let Err(e) = close_peer(&client, node_id, reason, &tx, &data_tx).await {
+                    error!("Error cleaning up after {}: {}", node_id.fmt_short(), e);
+                }
+            }
+            RunMessage::CloseIdleConnections => {
+                let closed = client
+                    .lock()
+                    .await
+                    .close_idle_connections(Duration::from_secs(IDLE_CONNECTION_TIMEOUT))
+                    .await;
+                match closed {
+                    Ok(closed) => {
+                        for node_id in closed {
+                            let reason = CloseReason::Idle;
+                            data_tx
+                                .send(IPCResponse::ConnectionClosed(ConnectionClosedResp {
+                                    node_id,
+                                    reason,
+                                }))
+                                .await?;
+                        }
+                    }
+                    Err(e) => error!("Error closing idle connections {}", e),
+                }
+            }
+            RunMessage::ConnectionClosed(node_id, reason) => {
+                data_tx
+                    .send(IPCResponse::ConnectionClosed(ConnectionClosedResp {
+                        node_id,
+                        reason,
+                    }))
+                    .await?;
+            }
+            RunMessage::ConnectionFailed(node_id, session_type, reason) => {
+                data_tx
+                    .send(IPCResponse::ConnectionFailed(ConnectionFailedResp {
+                        node_id,
+                        session_type,
+                        reason,
+                    }))
+                    .await?;
+            }
+            RunMessage::SendMessage(node_id, message) => {
+                let mut locked = client.lock().await;
+                if let Err(e) = locked.send_message(node_id, message).await {
+                    error!("Error sending message to {}: {}", node_id.fmt_short(), e);
+                    let response = IPCResponse::Error(IPCErrorType {
+                        error: format!("Failed to send message to {}", node_id.fmt_short()),
+                    });
+                    drop(locked);
+                    data_tx.send(response).await?;
+                    continue;
+                }
+                //It was queued, connect and it goes out once the data channel opens
+                if locked.needs_connection(node_id) {
+                    drop(locked);
+                    let client = Arc::clone(&client);
+                    let data_tx = data_tx.clone();
+                    tokio::spawn(connect_for_messages(client, node_id, data_tx));
+                }
             }
             RunMessage::UpdateStatus(node_id, user_status) => {
                 let client = Arc::clone(&client);
@@ -343,150 +1224,1085 @@ pub async fn run(
                 let response = SendUsersResp { users };
                 data_tx.send(IPCResponse::SendUsers(response)).await?;
             }
-            RunMessage::GetUser(display_name) => {
+            RunMessage::GetUser(node_id) => {
                 let client = Arc::clone(&client);
                 let client = client.lock().await;
-                let user = client.get_user(display_name)?;
+                let user = client.get_user(node_id)?;
                 data_tx.send(IPCResponse::SendUser(user)).await;
             }
-            RunMessage::Shutdown => {
-                info!("Shutting down...");
-                break;
+            RunMessage::GetAudioDevices => {
+                let client = Arc::clone(&client);
+                let client = client.lock().await;
+                let devices = client.get_audio_devices()?;
+                data_tx.send(IPCResponse::SendAudioDevices(devices)).await?;
             }
-        }
-    }
-
-    Ok(())
-}
-
-pub async fn init_connection(
-    client: Arc<Mutex<Client>>,
-    remote_node_id: NodeId,
-    display_name: String,
-    session_type: SessionType,
-) -> Result<()> {
-    //Initialize the connection then drop the mutex on client
-    let mut conn = {
-        let client = client.lock().await;
-        let conn = Connection::new(
-            &client.rtc_config.api,
-            client.rtc_config.config.clone(),
-            ConnType::Offerer,
-            client.session_exchange.clone(),
-        )
-        .await;
-        conn
-    };
-
-    conn.set_remote_node_id(remote_node_id).await?;
-
-    let mut receivers: Vec<mpsc::Receiver<MessageType>> = Vec::new();
-
-    match session_type {
-        SessionType::Idle => {}
-        SessionType::Chat => {
-            let dc_rx = conn.create_data_channel().await;
-            receivers.push(dc_rx);
-        }
-        SessionType::Call => {}
-        SessionType::Video => {}
-    }
-
-    //Typical WebRTC steps...
-    info!("Created data channel!");
-    conn.init_ice_handler().await;
-    info!("Listening for ice candidates");
-    conn.offer().await?;
-    info!("Created offer!");
-    match conn.init_remote_handler().await {
-        Ok(()) => info!("Succesfully created remote handler"),
-        Err(e) => error!("Error creating remote handler {}", e),
+            RunMessage::SetAudioDevice(direction, device) => {
+                let client = Arc::clone(&client);
+                let mut client = client.lock().await;
+                match client.set_audio_device(direction, device).await {
+                    Ok(()) => info!("Succesfully changed audio device"),
+                    Err(e) => error!("Failed to change audio device {}", e),
+                }
+            }
+            RunMessage::GetVideoSource => {
+                let client = client.lock().await;
+                data_tx
+                    .send(IPCResponse::SendVideoSource(client.get_video_source()))
+                    .await?;
+            }
+            RunMessage::SetVideoSource(source) => {
+                let client = client.lock().await;
+                match client.set_video_source(source) {
+                    Ok(()) => info!("Changed video source"),
+                    Err(e) => error!("Failed to change video source {}", e),
+                }
+            }
+            RunMessage::GetAudioProcessing => {
+                let client = client.lock().await;
+                let settings = client.audio.processing.settings();
+                data_tx
+                    .send(IPCResponse::SendAudioProcessing(settings))
+                    .await?;
+            }
+            RunMessage::SetAudioProcessing(processing, enabled) => {
+                let client = client.lock().await;
+                match client.set_audio_processing(processing, enabled) {
+                    Ok(()) => info!("Set {:?} to {}", processing, enabled),
+                    Err(e) => error!("Failed to change audio processing {}", e),
+                }
+            }
+            RunMessage::GetConnectionStats(node_id) => {
+                let client = client.lock().await;
+                let response = match client.get_connection_stats(node_id).await {
+                    Ok(stats) => IPCResponse::SendConnectionStats(stats),
+                    Err(e) => IPCResponse::Error(IPCErrorType {
+                        error: e.to_string(),
+                    }),
+                };
+                data_tx.send(response).await?;
+            }
+            RunMessage::ConnectionStatus(node_id, status) => {
+                data_tx
+                    .send(IPCResponse::ConnectionStatus(ConnectionStatusResp {
+                        node_id,
+                        status,
+                    }))
+                    .await?;
+                let mut client = client.lock().await;
+                match status {
+                    ConnectionStatus::Reconnecting => {
+                        client.reconnecting.insert(node_id);
+                    }
+                    ConnectionStatus::Reconnected => {
+                        client.reconnecting.remove(&node_id);
+                        if let Err(e) = client.flush_queued_messages(node_id).await {
+                            error!("Error sending queued messages {}", e);
+                        }
+                    }
+                    //Anything queued goes out once we are connected again
+                    ConnectionStatus::Disconnected => {
+                        client.reconnecting.remove(&node_id);
+                    }
+                }
+            }
+            RunMessage::PublishConnectionStats => {
+                let client = client.lock().await;
+                for (_, conn) in client
+                    .audio_connections()
+                    .filter(|(_, conn)| conn.has_audio())
+                {
+                    match conn.get_connection_stats().await {
+                        Ok(stats) => data_tx.send(IPCResponse::ConnectionStats(stats)).await?,
+                        Err(e) => error!("Error collecting connection stats: {}", e),
+                    }
+                }
+            }
+            RunMessage::SetMuted(muted) => {
+                let client = client.lock().await;
+                client.audio.controls.set_muted(muted);
+                client.broadcast_voice_state().await;
+            }
+            RunMessage::SetDeafened(deafened) => {
+                let client = client.lock().await;
+                client.audio.controls.set_deafened(deafened);
+                client.broadcast_voice_state().await;
+            }
+            RunMessage::SetPushToTalk(push_to_talk) => {
+                let client = client.lock().await;
+                client.audio.controls.set_push_to_talk(push_to_talk);
+                client.broadcast_voice_state().await;
+            }
+            RunMessage::PushToTalkDown => {
+                let client = client.lock().await;
+                client.audio.controls.set_talking(true);
+                client.broadcast_voice_state().await;
+            }
+            RunMessage::PushToTalkUp => {
+                let client = client.lock().await;
+                client.audio.controls.set_talking(false);
+                client.broadcast_voice_state().await;
+            }
+            RunMessage::PeerVoiceState(node_id, voice_state) => {
+                let response = PeerVoiceStateResp {
+                    node_id,
+                    voice_state,
+                };
+                data_tx.send(IPCResponse::PeerVoiceState(response)).await?;
+            }
+            RunMessage::Speaking(node_id, speaking) => {
+                let response = SpeakingResp { node_id, speaking };
+                data_tx.send(IPCResponse::Speaking(response)).await?;
+            }
+            RunMessage::AcceptCall => {
+                let actions = client.lock().await.call.accept();
+                match actions {
+                    Ok(actions) => apply_call_actions(&client, actions, &tx, &data_tx).await?,
+                    Err(e) => error!("Failed to accept call {}", e),
+                }
+            }
+            RunMessage::DeclineCall => {
+                let actions = client.lock().await.call.decline();
+                match actions {
+                    Ok(actions) => apply_call_actions(&client, actions, &tx, &data_tx).await?,
+                    Err(e) => error!("Failed to decline call {}", e),
+                }
+            }
+            RunMessage::HangUp => {
+                let actions = client.lock().await.call.hang_up();
+                match actions {
+                    Ok(actions) => apply_call_actions(&client, actions, &tx, &data_tx).await?,
+                    Err(e) => error!("Failed to hang up {}", e),
+                }
+            }
+            RunMessage::CallSignal(node_id, call_signal) => {
+                info!("Received call signal {:?}", call_signal);
+                let actions = {
+                    let mut client = client.lock().await;
+                    //Someone in a voice channel can't take a direct call
+                    if client.voice_channel.is_some() && matches!(call_signal, CallSignal::Ring(_))
+                    {
+                        vec![CallAction::Signal(node_id, CallSignal::Busy)]
+                    } else {
+                        client.call.on_signal(node_id, call_signal)
+                    }
+                };
+                apply_call_actions(&client, actions, &tx, &data_tx).await?;
+            }
+            RunMessage::CallTimeout(node_id) => {
+                let actions = client.lock().await.call.on_timeout(node_id);
+                apply_call_actions(&client, actions, &tx, &data_tx).await?;
+            }
+            RunMessage::CallConnectionEnded(node_id) => {
+                let actions = client.lock().await.call.on_connection_ended(node_id);
+                apply_call_actions(&client, actions, &tx, &data_tx).await?;
+            }
+            RunMessage::JoinVoiceChannel(channel) => {
+                let mut client = client.lock().await;
+                match client.join_voice_channel(channel) {
+                    Ok(()) => info!("Joined voice channel"),
+                    Err(e) => error!("Failed to join voice channel {}", e),
+                }
+            }
+            RunMessage::LeaveVoiceChannel => {
+                let mut client = client.lock().await;
+                match client.leave_voice_channel().await {
+                    Ok(()) => info!("Left voice channel"),
+                    Err(e) => error!("Failed to leave voice channel {}", e),
+                }
+            }
+            RunMessage::GetVoiceParticipants => {
+                let client = client.lock().await;
+                let response = match &client.voice_channel {
+                    Some(voice_channel) => {
+                        let participants = voice_channel
+                            .participants()
+                            .into_iter()
+                            .map(|(node_id, volume)| ParticipantInfo {
+                                node_id,
+                                volume,
+                                streaming: voice_channel.is_streaming(&node_id),
+                            })
+                            .collect();
+                        IPCResponse::SendVoiceParticipants(VoiceParticipantsResp {
+                            channel: voice_channel.name().to_string(),
+                            participants,
+                        })
+                    }
+                    None => IPCResponse::Error(IPCErrorType {
+                        error: "Not in a voice channel".to_string(),
+                    }),
+                };
+                data_tx.send(response).await?;
+            }
+            RunMessage::SetParticipantVolume(node_id, volume) => {
+                let mut client = client.lock().await;
+                if let Err(e) = client.set_participant_volume(node_id, volume).await {
+                    error!("Failed to set volume {}", e);
+                }
+            }
+            RunMessage::VoiceChannelSignal(node_id, channel, channel_signal) => {
+                handle_voice_channel_signal(&client, node_id, channel, channel_signal, &data_tx)
+                    .await?;
+            }
+            RunMessage::StartStream(source) => {
+                let mut client = client.lock().await;
+                match client.start_stream(source) {
+                    Ok(()) => {
+                        info!("Went live");
+                        let response = stream_viewers(&client.voice_channel);
+                        data_tx.send(response).await?;
+                    }
+                    Err(e) => error!("Failed to start stream {}", e),
+                }
+            }
+            RunMessage::StopStream => {
+                let mut client = client.lock().await;
+                match client.stop_stream().await {
+                    Ok(()) => info!("Stopped streaming"),
+                    Err(e) => error!("Failed to stop stream {}", e),
+                }
+            }
+            RunMessage::WatchStream(node_id) => {
+                let mut locked = client.lock().await;
+                match locked.watch_stream(node_id) {
+                    Ok(()) => {
+                        tokio::spawn(receive_connection(
+                            Arc::clone(&client),
+                            SessionType::Stream,
+                            Some(node_id),
+                        ));
+                    }
+                    Err(e) => error!("Failed to watch stream {}", e),
+                }
+            }
+            RunMessage::UnwatchStream(node_id) => {
+                let mut client = client.lock().await;
+                if let Err(e) = client.unwatch_stream(node_id).await {
+                    error!("Failed to stop watching stream {}", e);
+                }
+            }
+            RunMessage::GetStreamViewers => {
+                let response = stream_viewers(&client.lock().await.voice_channel);
+                data_tx.send(response).await?;
+            }
+            RunMessage::StartRecording => {
+                let client = client.lock().await;
+                match client.start_recording().await {
+                    Ok(path) => {
+                        let path = path.display().to_string();
+                        data_tx
+                            .send(IPCResponse::RecordingStarted(RecordingResp { path }))
+                            .await?;
+                    }
+                    Err(e) => error!("Failed to start recording {}", e),
+                }
+            }
+            RunMessage::StopRecording => {
+                let client = client.lock().await;
+                match client.stop_recording().await {
+                    Ok(path) => {
+                        let path = path.display().to_string();
+                        data_tx
+                            .send(IPCResponse::RecordingStopped(RecordingResp { path }))
+                            .await?;
+                    }
+                    Err(e) => error!("Failed to stop recording {}", e),
+                }
+            }
+            RunMessage::GetSounds => {
+                let client = client.lock().await;
+                let response = match client.list_sounds() {
+                    Ok(sounds) => IPCResponse::SendSounds(SoundsResp { sounds }),
+                    Err(e) => IPCResponse::Error(IPCErrorType {
+                        error: e.to_string(),
+                    }),
+                };
+                data_tx.send(response).await?;
+            }
+            RunMessage::PlaySound(name) => {
+                let client = client.lock().await;
+                if let Err(e) = client.play_sound(&name) {
+                    error!("Failed to play sound {}", e);
+                }
+            }
+            RunMessage::RecordVoiceMessage => {
+                let mut client = client.lock().await;
+                if let Err(e) = client.record_voice_message().await {
+                    error!("Failed to record voice message {}", e);
+                }
+            }
+            RunMessage::CancelVoiceMessage => {
+                let mut client = client.lock().await;
+                if let Err(e) = client.cancel_voice_message() {
+                    error!("Failed to cancel voice message {}", e);
+                }
+            }
+            RunMessage::SendVoiceMessage(node_id) => {
+                let mut locked = client.lock().await;
+                match locked.send_voice_message(node_id).await {
+                    Ok(voice_message) => {
+                        data_tx
+                            .send(IPCResponse::VoiceMessageSent(voice_message))
+                            .await?;
+                    }
+                    Err(e) => error!("Failed to send voice message {}", e),
+                }
+                if locked.needs_connection(node_id) {
+                    drop(locked);
+                    let client = Arc::clone(&client);
+                    let data_tx = data_tx.clone();
+                    tokio::spawn(connect_for_messages(client, node_id, data_tx));
+                }
+            }
+            RunMessage::ImportVoiceMessage(node_id, path) => {
+                let mut locked = client.lock().await;
+                match locked.import_voice_message(node_id, Path::new(&path)).await {
+                    Ok(voice_message) => {
+                        data_tx
+                            .send(IPCResponse::VoiceMessageSent(voice_message))
+                            .await?;
+                    }
+                    Err(e) => error!("Failed to send voice message {}", e),
+                }
+                if locked.needs_connection(node_id) {
+                    drop(locked);
+                    let client = Arc::clone(&client);
+                    let data_tx = data_tx.clone();
+                    tokio::spawn(connect_for_messages(client, node_id, data_tx));
+                }
+            }
+            RunMessage::ReceiveVoiceMessage(voice_message) => {
+                let client = Arc::clone(&client);
+                let data_tx = data_tx.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = receive_voice_message(client, voice_message, data_tx).await {
+                        error!("Failed to receive voice message {}", e);
+                    }
+                });
+            }
+            RunMessage::PlayVoiceMessage(hash) => {
+                let client = Arc::clone(&client);
+                tokio::spawn(async move {
+                    if let Err(e) = play_voice_message(client, hash).await {
+                        error!("Failed to play voice message {}", e);
+                    }
+                });
+            }
+            RunMessage::GetVoiceMessages(node_id) => {
+                let client = client.lock().await;
+                let response = match client.get_voice_messages(node_id) {
+                    Ok(voice_messages) => {
+                        IPCResponse::SendVoiceMessages(VoiceMessagesResp { voice_messages })
+                    }
+                    Err(e) => IPCResponse::Error(IPCErrorType {
+                        error: e.to_string(),
+                    }),
+                };
+                data_tx.send(response).await?;
+            }
+            RunMessage::Shutdown => {
+                info!("Shutting down...");
+                break;
+            }
+        }
     }
 
-    let conn_rx = conn.monitor_connection().await;
-    info!("Connection is running");
-    receivers.push(conn_rx);
+    Ok(())
+}
 
-    conn.wait_for_data_channel().await;
+pub async fn init_connection(
+    client: Arc<Mutex<Client>>,
+    remote_node_id: NodeId,
+    session_type: SessionType,
+) -> Result<()> {
+    let (conn, receivers) = match open_connection(
+        Arc::clone(&client),
+        session_type,
+        ConnType::Offerer,
+        Some(remote_node_id),
+    )
+    .await
+    {
+        Ok(opened) => opened,
+        Err(e) => {
+            report_connection_failure(&client, Some(remote_node_id), session_type, &e).await;
+            return Err(e);
+        }
+    };
+    let connection_id = conn.id();
 
-    //Save connection so we can refernce it by peer's display_name later
+    //Save connection so we can refernce it by peer's node id later
     {
         let mut client = client.lock().await;
-        let connections = &mut client.connections;
-        connections.insert(display_name, conn);
+        if !client
+            .add_connection(remote_node_id, &session_type, conn)
+            .await?
+        {
+            return Ok(());
+        }
     }
 
-    run_connection(Arc::clone(&client), receivers).await;
+    run_connection(
+        Arc::clone(&client),
+        receivers,
+        remote_node_id,
+        session_type,
+        connection_id,
+    )
+    .await;
     Ok(())
 }
 
+//Answers whoever offers this kind of session next, or only the given peer
 pub async fn receive_connection(
     client: Arc<Mutex<Client>>,
     session_type: SessionType,
+    remote_node_id: Option<NodeId>,
 ) -> Result<()> {
-    let mut conn = {
+    let (conn, receivers) = match open_connection(
+        Arc::clone(&client),
+        session_type,
+        ConnType::Answerer,
+        remote_node_id,
+    )
+    .await
+    {
+        Ok(opened) => opened,
+        Err(e) => {
+            report_connection_failure(&client, remote_node_id, session_type, &e).await;
+            return Err(e);
+        }
+    };
+
+    let remote_node_id = conn.get_remote_node_id().await?;
+    let connection_id = conn.id();
+    {
+        let mut client = client.lock().await;
+        if !client
+            .add_connection(remote_node_id, &session_type, conn)
+            .await?
+        {
+            return Ok(());
+        }
+    }
+
+    run_connection(
+        Arc::clone(&client),
+        receivers,
+        remote_node_id,
+        session_type,
+        connection_id,
+    )
+    .await;
+    Ok(())
+}
+
+//Opens a chat with a peer we have messages queued for. They are told to expect us first, then
+//the queue is flushed as soon as the connection is saved
+async fn connect_for_messages(
+    client: Arc<Mutex<Client>>,
+    remote_node_id: NodeId,
+    data_tx: mpsc::Sender<IPCResponse>,
+) -> Result<()> {
+    let signaler = Arc::clone(&client.lock().await.signaler);
+    let opened = match signaler
+        .notify_connection(remote_node_id, SessionType::Chat)
+        .await
+    {
+        Ok(()) => {
+            open_connection(
+                Arc::clone(&client),
+                SessionType::Chat,
+                ConnType::Offerer,
+                Some(remote_node_id),
+            )
+            .await
+        }
+        Err(e) => Err(e.context(HandshakeError::new(
+            HandshakePhase::SendingDescription,
+            ConnectionFailure::PeerUnreachable,
+        ))),
+    };
+
+    let (conn, receivers) = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            report_connection_failure(&client, Some(remote_node_id), SessionType::Chat, &e).await;
+            let queued_messages = {
+                let mut client = client.lock().await;
+                client.connecting.remove(&remote_node_id);
+                client.queued_message_count(&remote_node_id)
+            };
+            data_tx
+                .send(IPCResponse::PeerUnreachable(PeerUnreachableResp {
+                    node_id: remote_node_id,
+                    queued_messages,
+                }))
+                .await?;
+            return Ok(());
+        }
+    };
+    let connection_id = conn.id();
+
+    {
+        let mut client = client.lock().await;
+        client.connecting.remove(&remote_node_id);
+        if !client
+            .add_connection(remote_node_id, &SessionType::Chat, conn)
+            .await?
+        {
+            return Ok(());
+        }
+    }
+
+    run_connection(
+        Arc::clone(&client),
+        receivers,
+        remote_node_id,
+        SessionType::Chat,
+        connection_id,
+    )
+    .await;
+    Ok(())
+}
+
+//Lets the frontend know a connection it was waiting on won't happen, and ends the call it was
+//for. Anything other than a failed handshake, e.g. no audio device, is only logged
+async fn report_connection_failure(
+    client: &Arc<Mutex<Client>>,
+    remote_node_id: Option<NodeId>,
+    session_type: SessionType,
+    error: &anyhow::Error,
+) {
+    error!("Error connecting {:#}", error);
+    let Some(runtime_tx) = client.lock().await.runtime_tx.clone() else {
+        return;
+    };
+    if let (SessionType::Call | SessionType::Video, Some(remote_node_id)) =
+        (session_type, remote_node_id)
+    {
+        let _ = runtime_tx
+            .send(RunMessage::CallConnectionEnded(remote_node_id))
+            .await;
+    }
+    if let Some(failure) = error.downcast_ref::<HandshakeError>() {
+        let _ = runtime_tx
+            .send(RunMessage::ConnectionFailed(
+                remote_node_id,
+                session_type,
+                failure.reason,
+            ))
+            .await;
+    }
+}
+
+//Runs the webrtc handshake up to an open data channel. Answerers that don't know the peer take
+//whoever offers this kind of session next
+async fn open_connection(
+    client: Arc<Mutex<Client>>,
+    session_type: SessionType,
+    conn_type: ConnType,
+    remote_node_id: Option<NodeId>,
+) -> Result<(Connection, Vec<mpsc::Receiver<MessageType>>)> {
+    //Initialize the connection then drop the mutex on client
+    let (mut conn, audio, video, live_feed) = {
         let client = client.lock().await;
         let conn = Connection::new(
             &client.rtc_config.api,
             client.rtc_config.config.clone(),
-            ConnType::Offerer,
+            conn_type.clone(),
+            session_type,
             client.session_exchange.clone(),
         )
         .await;
-        conn
+        (
+            conn,
+            client.audio.clone(),
+            client.video.clone(),
+            client.live_feed.as_ref().map(|(feed, _)| feed.clone()),
+        )
     };
 
+    if let Some(remote_node_id) = remote_node_id {
+        conn.set_remote_node_id(remote_node_id).await?;
+    }
+
+    //Whatever goes wrong, the half open connection has to be closed so its tasks stop
+    match handshake(
+        &mut conn,
+        session_type,
+        conn_type,
+        remote_node_id.is_none(),
+        audio,
+        video,
+        live_feed,
+    )
+    .await
+    {
+        Ok(receivers) => Ok((conn, receivers)),
+        Err(e) => {
+            if let Err(close_error) = conn.close_connection().await {
+                error!("Error closing failed connection {}", close_error);
+            }
+            Err(e)
+        }
+    }
+}
+
+async fn handshake(
+    conn: &mut Connection,
+    session_type: SessionType,
+    conn_type: ConnType,
+    unknown_peer: bool,
+    audio: AudioContext,
+    video: VideoContext,
+    live_feed: Option<LiveFeed>,
+) -> Result<Vec<mpsc::Receiver<MessageType>>> {
     let mut receivers: Vec<mpsc::Receiver<MessageType>> = Vec::new();
 
     match session_type {
         SessionType::Idle => {}
         SessionType::Chat => {
-            let dc_rx = conn.register_data_channel().await;
+            let dc_rx = conn.create_data_channel().await;
             receivers.push(dc_rx);
-            info!("Registered data channel");
+            info!("Created data channel");
+        }
+        SessionType::Call | SessionType::VoiceChannel => {
+            //Data channel is kept alongside the audio track for in-call messages
+            let dc_rx = conn.create_data_channel().await;
+            receivers.push(dc_rx);
+            conn.init_track_handler(audio.clone(), video).await;
+            //Track has to be added before the handshake so it's included in our sdp
+            conn.init_audio_stream(audio).await?;
+            info!("Initialized audio stream");
+        }
+        //A call with our video on top
+        SessionType::Video => {
+            let dc_rx = conn.create_data_channel().await;
+            receivers.push(dc_rx);
+            conn.init_track_handler(audio.clone(), video.clone()).await;
+            conn.init_audio_stream(audio).await?;
+            conn.init_video_stream(&video).await?;
+            info!("Initialized audio and video streams");
+        }
+        //The streamer always offers, the viewer has nothing to send
+        SessionType::Stream => {
+            let dc_rx = conn.create_data_channel().await;
+            receivers.push(dc_rx);
+            if conn_type == ConnType::Offerer {
+                let live_feed = live_feed.ok_or_else(|| anyhow::anyhow!("Not streaming"))?;
+                conn.add_live_feed(&live_feed).await?;
+            } else {
+                conn.init_track_handler(audio, video).await;
+            }
         }
-        SessionType::Call => {}
-        SessionType::Video => {}
     }
 
+    //Typical WebRTC steps...
     conn.init_ice_handler().await;
-    info!("init ice handler");
+    info!("Listening for ice candidates");
+    if unknown_peer {
+        conn.retrieve_remote_node_id().await?;
+    }
+    //Listen before offering so an offer crossing ours is caught by the negotiation
     conn.init_remote_handler().await?;
-    info!("init remote handler");
-    conn.retrieve_remote_node_id().await?;
-    conn.answer().await?;
-    let conn_rx = conn.monitor_connection().await;
-    receivers.push(conn_rx);
+    info!("Succesfully created remote handler");
+    if conn_type == ConnType::Offerer {
+        conn.offer().await?;
+        info!("Created offer!");
+    }
+    conn.wait_for_description().await?;
 
+    let conn_rx = conn.monitor_connection().await?;
     info!("Connection is running");
-    conn.wait_for_data_channel().await;
+    receivers.push(conn_rx);
 
-    if let Ok(remote_node_id) = conn.get_remote_node_id().await {
-        let mut client = client.lock().await;
-        let remote_node_id_str = remote_node_id.to_string();
-        let display_name = client.get_display_name(remote_node_id_str)?;
-        let connections = &mut client.connections;
-        connections.insert(display_name, conn);
+    conn.wait_for_data_channel().await?;
+    Ok(receivers)
+}
+
+//Renegotiates a connection an ICE restart couldn't save from scratch. The impolite peer offers
+//again, the polite one closes its side and waits for the offer
+//Boxed since the connection it runs can end up back here
+fn reconnect(
+    client: Arc<Mutex<Client>>,
+    remote_node_id: NodeId,
+    session_type: SessionType,
+) -> BoxedFuture<()> {
+    Box::pin(async move {
+        let (polite, runtime_tx) = {
+            let mut client = client.lock().await;
+            //Closing drops its route, so the peer's new sessions reach the new connection
+            let connections = client.connections_for(&session_type);
+            if let Some(mut conn) = connections.remove(&remote_node_id) {
+                if let Err(e) = conn.close_connection().await {
+                    error!("Error closing failed connection {}", e);
+                }
+            }
+            let polite = negotiation::is_polite(&client.get_node_id(), &remote_node_id);
+            (polite, client.runtime_tx.clone())
+        };
+        let report = |status| {
+            let runtime_tx = runtime_tx.clone();
+            async move {
+                if let Some(runtime_tx) = runtime_tx {
+                    let _ = runtime_tx
+                        .send(RunMessage::ConnectionStatus(remote_node_id, status))
+                        .await;
+                }
+            }
+        };
+
+        let mut backoff = Backoff::default();
+        while let Some(delay) = backoff.next_delay() {
+            //The polite peer is already waiting through the handshake's deadlines
+            if !polite {
+                sleep(delay).await;
+            }
+            info!(
+                "Reconnecting to {}, attempt {}",
+                remote_node_id.fmt_short(),
+                backoff.attempt()
+            );
+            let conn_type = if polite {
+                ConnType::Answerer
+            } else {
+                ConnType::Offerer
+            };
+            let attempt = open_connection(
+                Arc::clone(&client),
+                session_type,
+                conn_type,
+                Some(remote_node_id),
+            )
+            .await;
+            let (conn, receivers) = match attempt {
+                Ok(opened) => opened,
+                Err(e) => {
+                    error!(
+                        "Error reconnecting to {}: {:#}",
+                        remote_node_id.fmt_short(),
+                        e
+                    );
+                    continue;
+                }
+            };
+            let connection_id = conn.id();
+
+            {
+                let mut client = client.lock().await;
+                match client
+                    .add_connection(remote_node_id, &session_type, conn)
+                    .await
+                {
+                    Ok(true) => {}
+                    Ok(false) => return,
+                    Err(e) => {
+                        error!("Error saving connection {}", e);
+                        return;
+                    }
+                }
+            }
+            report(ConnectionStatus::Reconnected).await;
+            run_connection(
+                client,
+                receivers,
+                remote_node_id,
+                session_type,
+                connection_id,
+            )
+            .await;
+            return;
+        }
+        error!(
+            "Giving up on reconnecting to {}",
+            remote_node_id.fmt_short()
+        );
+        report(ConnectionStatus::Disconnected).await;
+        //Torn down the same way as a connection the user closed
+        if let Some(runtime_tx) = runtime_tx {
+            let _ = runtime_tx
+                .send(RunMessage::ConnectionLost(remote_node_id, session_type))
+                .await;
+        }
+    })
+}
+
+async fn handle_voice_channel_signal(
+    client: &Arc<Mutex<Client>>,
+    node_id: NodeId,
+    channel: String,
+    channel_signal: VoiceChannelSignal,
+    data_tx: &mpsc::Sender<IPCResponse>,
+) -> Result<()> {
+    let mut locked = client.lock().await;
+    let voice_channel = match locked.voice_channel.as_mut() {
+        Some(voice_channel) if voice_channel.name() == channel => voice_channel,
+        _ => return Ok(()),
+    };
+
+    match channel_signal {
+        VoiceChannelSignal::Join | VoiceChannelSignal::Present => {
+            if !voice_channel.add_participant(node_id) {
+                return Ok(());
+            }
+            let live = voice_channel.is_live();
+            if channel_signal == VoiceChannelSignal::Join {
+                locked.notify_voice_channel(node_id, channel.clone(), VoiceChannelSignal::Present);
+            }
+            //Someone joining mid-stream still gets to see that we are live
+            if live {
+                locked.notify_voice_channel(
+                    node_id,
+                    channel.clone(),
+                    VoiceChannelSignal::StreamStarted,
+                );
+            }
+            //Both sides learn about each other at the same time, the polite side offers
+            let session_type = SessionType::VoiceChannel;
+            if negotiation::is_polite(&locked.get_node_id(), &node_id) {
+                tokio::spawn(init_connection(Arc::clone(client), node_id, session_type));
+            } else {
+                tokio::spawn(receive_call(Arc::clone(client), node_id, session_type));
+            }
+            let response = ParticipantResp { node_id, channel };
+            data_tx
+                .send(IPCResponse::ParticipantJoined(response))
+                .await?;
+        }
+        VoiceChannelSignal::Leave => {
+            for response in locked.remove_participant(node_id).await? {
+                data_tx.send(response).await?;
+            }
+        }
+        VoiceChannelSignal::StreamStarted => {
+            if !voice_channel.set_streaming(node_id, true) {
+                return Ok(());
+            }
+            let response = ParticipantResp { node_id, channel };
+            data_tx.send(IPCResponse::StreamStarted(response)).await?;
+        }
+        VoiceChannelSignal::StreamEnded => {
+            if !voice_channel.set_streaming(node_id, false) {
+                return Ok(());
+            }
+            locked.close_stream_connection(node_id).await?;
+            let response = ParticipantResp { node_id, channel };
+            data_tx.send(IPCResponse::StreamEnded(response)).await?;
+        }
+        //We offer the viewer a connection carrying the live feed
+        VoiceChannelSignal::Watch => {
+            if !voice_channel.add_viewer(node_id) {
+                return Ok(());
+            }
+            let viewers = stream_viewers(&locked.voice_channel);
+            tokio::spawn(init_connection(
+                Arc::clone(client),
+                node_id,
+                SessionType::Stream,
+            ));
+            data_tx.send(viewers).await?;
+        }
+        VoiceChannelSignal::Unwatch => {
+            if !voice_channel.remove_viewer(&node_id) {
+                return Ok(());
+            }
+            let viewers = stream_viewers(&locked.voice_channel);
+            locked.close_stream_connection(node_id).await?;
+            data_tx.send(viewers).await?;
+        }
     }
+    Ok(())
+}
 
-    run_connection(Arc::clone(&client), receivers).await;
+//Who is watching our stream, for the streamer's viewer list
+fn stream_viewers(voice_channel: &Option<VoiceChannel>) -> IPCResponse {
+    match voice_channel {
+        Some(voice_channel) if voice_channel.is_live() => {
+            IPCResponse::StreamViewers(StreamViewersResp {
+                channel: voice_channel.name().to_string(),
+                viewers: voice_channel.viewers(),
+            })
+        }
+        _ => IPCResponse::Error(IPCErrorType {
+            error: "Not streaming".to_string(),
+        }),
+    }
+}
+
+//Ends everything we have going with a peer: the call, their place in our voice channel and the
+//connection itself. Done when the user closes the connection and when reconnecting gives up
+async fn close_peer(
+    client: &Arc<Mutex<Client>>,
+    node_id: NodeId,
+    reason: CloseReason,
+    tx: &mpsc::Sender<RunMessage>,
+    data_tx: &mpsc::Sender<IPCResponse>,
+) -> Result<()> {
+    let requested = reason == CloseReason::Requested;
+    let mut locked = client.lock().await;
+    //A call has to end through the call manager so the peer hears about it
+    let call_actions = match locked.call.state() {
+        CallState::Active(peer) if peer == node_id && requested => locked.call.hang_up()?,
+        CallState::Active(peer) if peer == node_id => locked.call.on_connection_ended(node_id),
+        _ => Vec::new(),
+    };
+    //As far as we are concerned, a peer we can't reach has left our voice channel
+    let left = if requested {
+        Vec::new()
+    } else {
+        locked.remove_participant(node_id).await?
+    };
+    let closed = locked.close_connection_with(node_id).await?;
+    drop(locked);
+
+    apply_call_actions(client, call_actions, tx, data_tx).await?;
+    for response in left {
+        data_tx.send(response).await?;
+    }
+    //The connection is already gone once reconnecting gives up
+    if !closed && requested {
+        error!("No connection to {}", node_id.fmt_short());
+        return Ok(());
+    }
+    data_tx
+        .send(IPCResponse::ConnectionClosed(ConnectionClosedResp {
+            node_id,
+            reason,
+        }))
+        .await?;
+    Ok(())
+}
+
+//Carries out the side effects of a call state transition
+async fn apply_call_actions(
+    client: &Arc<Mutex<Client>>,
+    actions: Vec<CallAction>,
+    tx: &mpsc::Sender<RunMessage>,
+    data_tx: &mpsc::Sender<IPCResponse>,
+) -> Result<()> {
+    for action in actions {
+        match action {
+            CallAction::Signal(node_id, call_signal) => {
+                let signaler = Arc::clone(&client.lock().await.signaler);
+                tokio::spawn(async move {
+                    if let Err(e) = signaler.notify_call(node_id, call_signal).await {
+                        error!("Error sending {:?} to peer: {}", call_signal, e);
+                    }
+                });
+            }
+            CallAction::Offer(node_id, session_type) => {
+                tokio::spawn(init_connection(Arc::clone(client), node_id, session_type));
+            }
+            CallAction::Answer(node_id, session_type) => {
+                tokio::spawn(receive_call(Arc::clone(client), node_id, session_type));
+            }
+            CallAction::Close(node_id) => {
+                let mut client = client.lock().await;
+                if let Err(e) = client.close_connection_with(node_id).await {
+                    error!("Error closing call connection: {}", e);
+                }
+            }
+            CallAction::StartTimer(node_id) => {
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    sleep(Duration::from_secs(CALL_RING_TIMEOUT)).await;
+                    let _ = tx.send(RunMessage::CallTimeout(node_id)).await;
+                });
+            }
+            CallAction::Event(event) => {
+                let session_type = client.lock().await.call.session_type();
+                let call = |node_id| CallResp {
+                    node_id,
+                    session_type,
+                };
+                let response = match event {
+                    CallEvent::Ringing(node_id) => IPCResponse::CallRinging(call(node_id)),
+                    CallEvent::Incoming(node_id) => IPCResponse::IncomingCall(call(node_id)),
+                    CallEvent::Accepted(node_id) => IPCResponse::CallAccepted(call(node_id)),
+                    CallEvent::Ended(node_id, reason) => {
+                        IPCResponse::CallEnded(CallEndedResp { node_id, reason })
+                    }
+                };
+                data_tx.send(response).await?;
+            }
+        }
+    }
     Ok(())
 }
 
-pub async fn init_call(
+pub async fn receive_call(
     client: Arc<Mutex<Client>>,
     remote_node_id: NodeId,
-    sender: oneshot::Sender<usize>,
-) {
+    session_type: SessionType,
+) -> Result<()> {
+    receive_connection(client, session_type, Some(remote_node_id)).await
+}
+
+fn audio_processing_setting(processing: AudioProcessing) -> &'static str {
+    match processing {
+        AudioProcessing::EchoCancellation => ECHO_CANCELLATION_SETTING,
+        AudioProcessing::NoiseSuppression => NOISE_SUPPRESSION_SETTING,
+        AudioProcessing::AutoGainControl => AUTO_GAIN_CONTROL_SETTING,
+    }
+}
+
+//Downloads the audio right away so it can still be played once the sender goes offline
+async fn receive_voice_message(
+    client: Arc<Mutex<Client>>,
+    voice_message: VoiceMessage,
+    data_tx: mpsc::Sender<IPCResponse>,
+) -> Result<()> {
+    let iroh = client.lock().await.node.client().clone();
+    iroh.blobs()
+        .download(voice_message.hash, voice_message.sender.into())
+        .await?
+        .finish()
+        .await?;
+    client
+        .lock()
+        .await
+        .store_voice_message(&voice_message, true)?;
+    data_tx
+        .send(IPCResponse::VoiceMessageReceived(voice_message))
+        .await?;
+    Ok(())
+}
+
+async fn play_voice_message(client: Arc<Mutex<Client>>, hash: Hash) -> Result<()> {
+    let (iroh, audio) = {
+        let client = client.lock().await;
+        (client.node.client().clone(), client.audio.clone())
+    };
+    let bytes = iroh.blobs().read_to_bytes(hash).await?;
+    let samples = soundboard::decode_ogg(std::io::Cursor::new(bytes))?;
+    audio::play_samples(&audio, samples).await
 }
-pub async fn recieve_call() {}
 
 pub async fn run_connection(
     client: Arc<Mutex<Client>>,
     receivers: Vec<mpsc::Receiver<MessageType>>,
+    remote_node_id: NodeId,
+    session_type: SessionType,
+    connection_id: u64,
 ) {
     let streams: Vec<_> = receivers.into_iter().map(ReceiverStream::new).collect();
     let mut fused_streams = stream::select_all(streams);
+    let runtime_tx = client.lock().await.runtime_tx.clone();
+    let report = |status| {
+        let runtime_tx = runtime_tx.clone();
+        async move {
+            if let Some(runtime_tx) = runtime_tx {
+                let _ = runtime_tx
+                    .send(RunMessage::ConnectionStatus(remote_node_id, status))
+                    .await;
+            }
+        }
+    };
+    let mut reconnecting = false;
+    let mut close_reason = CloseReason::Closed;
+    //Set once the connection failed and we restarted ICE, it's renegotiated if this passes
+    let mut ice_restart_deadline: Option<Instant> = None;
 
     loop {
         tokio::select! {
@@ -496,13 +2312,98 @@ pub async fn run_connection(
                         let mut client = client.lock().await;
                         let _ = client.store_message(m);
                     },
-                    MessageType::ConnectionState(_) => info!("Connection state changed"),
+                    MessageType::VoiceMessage(voice_message) => {
+                        info!("Received voice message {}", voice_message.hash);
+                        let runtime_tx = client.lock().await.runtime_tx.clone();
+                        if let Some(runtime_tx) = runtime_tx {
+                            let _ = runtime_tx.send(RunMessage::ReceiveVoiceMessage(voice_message)).await;
+                        }
+                    },
+                    MessageType::ConnectionState(state) => {
+                        info!("Connection state changed to {}", state);
+                        match state {
+                            //Often comes back on its own, messages wait until it does
+                            RTCPeerConnectionState::Disconnected if !reconnecting => {
+                                reconnecting = true;
+                                report(ConnectionStatus::Reconnecting).await;
+                            }
+                            //Go-Live viewers can just watch again
+                            RTCPeerConnectionState::Failed if session_type == SessionType::Stream => {
+                                close_reason = CloseReason::Failed;
+                                break;
+                            }
+                            RTCPeerConnectionState::Failed if ice_restart_deadline.is_none() => {
+                                if !reconnecting {
+                                    reconnecting = true;
+                                    report(ConnectionStatus::Reconnecting).await;
+                                }
+                                if let Err(e) = restart_ice(&client, remote_node_id, &session_type).await {
+                                    error!("Error restarting ice {}", e);
+                                }
+                                ice_restart_deadline = Some(Instant::now() + Duration::from_secs(ICE_RESTART_TIMEOUT));
+                            }
+                            RTCPeerConnectionState::Connected if reconnecting => {
+                                reconnecting = false;
+                                ice_restart_deadline = None;
+                                report(ConnectionStatus::Reconnected).await;
+                            }
+                            RTCPeerConnectionState::Closed => break,
+                            _ => {}
+                        }
+                    },
                 }
             }
+            _ = sleep_until(ice_restart_deadline.unwrap_or_else(Instant::now)), if ice_restart_deadline.is_some() => {
+                info!("ICE restart didn't reconnect, renegotiating");
+                tokio::spawn(reconnect(Arc::clone(&client), remote_node_id, session_type));
+                return;
+            }
             else => {
             info!("Strems have closed");
             break;
         }
         }
     }
+
+    //Nothing is left to run it, so its tasks and the peer connection go too
+    let removed = client
+        .lock()
+        .await
+        .remove_connection(remote_node_id, &session_type, connection_id)
+        .await;
+    match removed {
+        Ok(true) => {
+            if let Some(runtime_tx) = runtime_tx {
+                //Nobody hung up, so the call would otherwise stay active
+                if matches!(session_type, SessionType::Call | SessionType::Video) {
+                    let _ = runtime_tx
+                        .send(RunMessage::CallConnectionEnded(remote_node_id))
+                        .await;
+                }
+                let _ = runtime_tx
+                    .send(RunMessage::ConnectionClosed(remote_node_id, close_reason))
+                    .await;
+            }
+        }
+        Ok(false) => {}
+        Err(e) => error!("Error closing connection {}", e),
+    }
+}
+
+//The impolite peer sends the restart, the polite one answers it through the negotiation
+async fn restart_ice(
+    client: &Arc<Mutex<Client>>,
+    remote_node_id: NodeId,
+    session_type: &SessionType,
+) -> Result<()> {
+    let mut client = client.lock().await;
+    let conn = client
+        .connections_for(session_type)
+        .get(&remote_node_id)
+        .ok_or_else(|| anyhow::anyhow!("No connection to {}", remote_node_id.fmt_short()))?;
+    if !conn.is_polite().await {
+        info!("Restarting ice with {}", remote_node_id.fmt_short());
+        conn.restart_ice().await?;
+    }
+    Ok(())
 }
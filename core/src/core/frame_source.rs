@@ -0,0 +1,483 @@
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind};
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use webrtc::media::io::{
+    h264_reader::{H264Reader, NalUnitType},
+    ivf_reader::IVFReader,
+};
+use webrtc::media::Error as MediaError;
+
+use crate::utils::enums::{VideoCodec, VideoSource};
+
+pub const DEFAULT_FRAME_RATE: u32 = 15;
+//Size of the generated test pattern, has to be a whole number of 16x16 macroblocks
+pub const TEST_PATTERN_WIDTH: usize = 320;
+pub const TEST_PATTERN_HEIGHT: usize = 240;
+//Seconds between keyframes, so a peer that missed one doesn't wait long for the picture
+const KEYFRAME_INTERVAL: u32 = 2;
+const H264_READ_BUFFER: usize = 1_048_576;
+pub const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+const MACROBLOCK_SIZE: usize = 16;
+//An uncompressed 4:2:0 macroblock, 16x16 luma followed by two 8x8 chroma blocks
+const PCM_MACROBLOCK_BYTES: usize = 256 + 64 * 2;
+const PATTERN_SQUARE_SIZE: usize = 48;
+//75% SMPTE colour bars as Y, Cb, Cr
+const COLOUR_BARS: [[u8; 3]; 8] = [
+    [180, 128, 128],
+    [162, 44, 142],
+    [131, 156, 44],
+    [112, 72, 58],
+    [84, 184, 198],
+    [65, 100, 212],
+    [35, 212, 114],
+    [16, 128, 128],
+];
+const PATTERN_SQUARE_COLOUR: [u8; 3] = [235, 128, 128];
+
+//NAL unit headers, all pictures are kept as references
+const NAL_SPS: u8 = 0x67;
+const NAL_PPS: u8 = 0x68;
+const NAL_IDR_SLICE: u8 = 0x65;
+const NAL_SLICE: u8 = 0x61;
+
+//One encoded frame. H264 frames are Annex B, every NAL unit starts with a start code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VideoFrame {
+    pub data: Vec<u8>,
+    //Time until the next frame is due
+    pub duration: Duration,
+    pub keyframe: bool,
+}
+
+//Produces encoded frames for an outgoing video track. The sender paces frames by their duration
+//so a source only has to hand back the next one when asked
+pub trait FrameSource: Send + Debug {
+    fn codec(&self) -> VideoCodec;
+
+    //None once there is nothing left to send
+    fn next_frame(&mut self) -> Result<Option<VideoFrame>>;
+
+    //The peer lost the picture. Sources that encode on the fly should start over with a keyframe
+    fn request_keyframe(&mut self) {}
+}
+
+pub fn open_source(source: &VideoSource) -> Result<Box<dyn FrameSource>> {
+    match source {
+        VideoSource::TestPattern => Ok(Box::new(TestPattern::new(
+            TEST_PATTERN_WIDTH,
+            TEST_PATTERN_HEIGHT,
+            DEFAULT_FRAME_RATE,
+        )?)),
+        VideoSource::File(path) => open_file(Path::new(path)),
+    }
+}
+
+//Picks a reader from the file extension
+pub fn open_file(path: &Path) -> Result<Box<dyn FrameSource>> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("ivf") => Ok(Box::new(IvfSource::open(path)?)),
+        Some("h264") | Some("264") => Ok(Box::new(H264FileSource::open(path, DEFAULT_FRAME_RATE)?)),
+        _ => Err(anyhow!("Unsupported video file {}", path.display())),
+    }
+}
+
+//Colour bars with a square moving across them. There's no video encoder to lean on, so frames are
+//H264 made of uncompressed I_PCM macroblocks. Only macroblocks that changed since the last frame
+//are sent, the rest are skipped
+#[derive(Debug)]
+pub struct TestPattern {
+    width: usize,
+    height: usize,
+    frame_rate: u32,
+    frame_count: u32,
+    frame_num: u32,
+    idr_pic_id: u32,
+    keyframe_requested: bool,
+    previous: Vec<Vec<u8>>,
+}
+
+impl TestPattern {
+    pub fn new(width: usize, height: usize, frame_rate: u32) -> Result<Self> {
+        if width == 0
+            || height == 0
+            || !width.is_multiple_of(MACROBLOCK_SIZE)
+            || !height.is_multiple_of(MACROBLOCK_SIZE)
+        {
+            return Err(anyhow!("Test pattern size has to be a multiple of 16"));
+        }
+        if frame_rate == 0 {
+            return Err(anyhow!("Test pattern frame rate can't be 0"));
+        }
+        Ok(Self {
+            width,
+            height,
+            frame_rate,
+            frame_count: 0,
+            frame_num: 0,
+            idr_pic_id: 0,
+            keyframe_requested: true,
+            previous: Vec::new(),
+        })
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let travel = self.width - PATTERN_SQUARE_SIZE.min(self.width);
+        let square_x = (self.frame_count as usize * 4) % (travel + 1);
+        let square_y = (self.height - PATTERN_SQUARE_SIZE.min(self.height)) / 2;
+        if (square_x..square_x + PATTERN_SQUARE_SIZE).contains(&x)
+            && (square_y..square_y + PATTERN_SQUARE_SIZE).contains(&y)
+        {
+            return PATTERN_SQUARE_COLOUR;
+        }
+        COLOUR_BARS[x * COLOUR_BARS.len() / self.width]
+    }
+
+    //I_PCM sample data for every macroblock in raster order
+    fn draw(&self) -> Vec<Vec<u8>> {
+        let mut macroblocks = Vec::new();
+        for mb_y in 0..self.height / MACROBLOCK_SIZE {
+            for mb_x in 0..self.width / MACROBLOCK_SIZE {
+                let (x0, y0) = (mb_x * MACROBLOCK_SIZE, mb_y * MACROBLOCK_SIZE);
+                let mut macroblock = Vec::with_capacity(PCM_MACROBLOCK_BYTES);
+                for y in 0..MACROBLOCK_SIZE {
+                    for x in 0..MACROBLOCK_SIZE {
+                        macroblock.push(self.pixel(x0 + x, y0 + y)[0]);
+                    }
+                }
+                for plane in 1..3 {
+                    for y in 0..MACROBLOCK_SIZE / 2 {
+                        for x in 0..MACROBLOCK_SIZE / 2 {
+                            macroblock.push(self.pixel(x0 + x * 2, y0 + y * 2)[plane]);
+                        }
+                    }
+                }
+                macroblocks.push(macroblock);
+            }
+        }
+        macroblocks
+    }
+
+    //Constrained baseline, level 3.1
+    fn sps(&self) -> Vec<u8> {
+        let mut w = BitWriter::default();
+        w.bits(66, 8);
+        w.bits(0xe0, 8);
+        w.bits(31, 8);
+        w.ue(0); //seq_parameter_set_id
+        w.ue(0); //log2_max_frame_num_minus4
+        w.ue(2); //pic_order_cnt_type, order follows frame_num
+        w.ue(1); //max_num_ref_frames
+        w.bit(false); //gaps_in_frame_num_value_allowed_flag
+        w.ue((self.width / MACROBLOCK_SIZE - 1) as u32);
+        w.ue((self.height / MACROBLOCK_SIZE - 1) as u32);
+        w.bit(true); //frame_mbs_only_flag
+        w.bit(true); //direct_8x8_inference_flag
+        w.bit(false); //frame_cropping_flag
+        w.bit(false); //vui_parameters_present_flag
+        w.finish()
+    }
+
+    fn pps(&self) -> Vec<u8> {
+        let mut w = BitWriter::default();
+        w.ue(0); //pic_parameter_set_id
+        w.ue(0); //seq_parameter_set_id
+        w.bit(false); //entropy_coding_mode_flag, CAVLC
+        w.bit(false); //bottom_field_pic_order_in_frame_present_flag
+        w.ue(0); //num_slice_groups_minus1
+        w.ue(0); //num_ref_idx_l0_default_active_minus1
+        w.ue(0); //num_ref_idx_l1_default_active_minus1
+        w.bit(false); //weighted_pred_flag
+        w.bits(0, 2); //weighted_bipred_idc
+        w.se(0); //pic_init_qp_minus26
+        w.se(0); //pic_init_qs_minus26
+        w.se(0); //chroma_qp_index_offset
+        w.bit(true); //deblocking_filter_control_present_flag
+        w.bit(false); //constrained_intra_pred_flag
+        w.bit(false); //redundant_pic_cnt_present_flag
+        w.finish()
+    }
+
+    //A single slice covering the picture. P slices skip macroblocks that didn't change, which
+    //copies them from the previous frame
+    fn slice(&self, macroblocks: &[Vec<u8>], idr: bool) -> Vec<u8> {
+        let mut w = BitWriter::default();
+        w.ue(0); //first_mb_in_slice
+        w.ue(if idr { 7 } else { 5 }); //slice_type, I or P for the whole picture
+        w.ue(0); //pic_parameter_set_id
+        w.bits(self.frame_num, 4);
+        if idr {
+            w.ue(self.idr_pic_id);
+        } else {
+            w.bit(false); //num_ref_idx_active_override_flag
+            w.bit(false); //ref_pic_list_modification_flag_l0
+        }
+        if idr {
+            w.bit(false); //no_output_of_prior_pics_flag
+            w.bit(false); //long_term_reference_flag
+        } else {
+            w.bit(false); //adaptive_ref_pic_marking_mode_flag
+        }
+        w.se(0); //slice_qp_delta
+        w.ue(1); //disable_deblocking_filter_idc
+
+        let mut skip_run = 0;
+        for (i, macroblock) in macroblocks.iter().enumerate() {
+            if !idr {
+                if self.previous.get(i) == Some(macroblock) {
+                    skip_run += 1;
+                    continue;
+                }
+                w.ue(skip_run);
+                skip_run = 0;
+            }
+            //I_PCM, its mb_type is offset by the 5 inter types in a P slice
+            w.ue(if idr { 25 } else { 30 });
+            w.align();
+            w.bytes(macroblock);
+        }
+        if skip_run > 0 {
+            w.ue(skip_run);
+        }
+        w.finish()
+    }
+}
+
+impl FrameSource for TestPattern {
+    fn codec(&self) -> VideoCodec {
+        VideoCodec::H264
+    }
+
+    fn next_frame(&mut self) -> Result<Option<VideoFrame>> {
+        let macroblocks = self.draw();
+        let keyframe = self.keyframe_requested
+            || self
+                .frame_count
+                .is_multiple_of(self.frame_rate * KEYFRAME_INTERVAL);
+        let mut data = Vec::new();
+        if keyframe {
+            self.frame_num = 0;
+            write_nal(&mut data, NAL_SPS, &self.sps());
+            write_nal(&mut data, NAL_PPS, &self.pps());
+            write_nal(&mut data, NAL_IDR_SLICE, &self.slice(&macroblocks, true));
+            //Back to back IDR pictures need different ids
+            self.idr_pic_id = (self.idr_pic_id + 1) % 2;
+            self.keyframe_requested = false;
+        } else {
+            write_nal(&mut data, NAL_SLICE, &self.slice(&macroblocks, false));
+        }
+        self.frame_num = (self.frame_num + 1) % 16;
+        self.frame_count = self.frame_count.wrapping_add(1);
+        self.previous = macroblocks;
+        Ok(Some(VideoFrame {
+            data,
+            duration: Duration::from_secs(1) / self.frame_rate,
+            keyframe,
+        }))
+    }
+
+    fn request_keyframe(&mut self) {
+        self.keyframe_requested = true;
+    }
+}
+
+//Plays back a VP8 IVF file
+pub struct IvfSource {
+    reader: IVFReader<BufReader<File>>,
+    timebase: (u32, u32),
+    last_timestamp: Option<u64>,
+}
+
+impl Debug for IvfSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "IVF video source")
+    }
+}
+
+impl IvfSource {
+    pub fn open(path: &Path) -> Result<Self> {
+        let (reader, header) = IVFReader::new(BufReader::new(File::open(path)?))?;
+        if &header.four_cc != b"VP80" {
+            return Err(anyhow!(
+                "Unsupported IVF codec {}",
+                String::from_utf8_lossy(&header.four_cc)
+            ));
+        }
+        Ok(Self {
+            reader,
+            timebase: (header.timebase_numerator, header.timebase_denominator),
+            last_timestamp: None,
+        })
+    }
+}
+
+impl FrameSource for IvfSource {
+    fn codec(&self) -> VideoCodec {
+        VideoCodec::Vp8
+    }
+
+    fn next_frame(&mut self) -> Result<Option<VideoFrame>> {
+        let (data, header) = match self.reader.parse_next_frame() {
+            Ok(frame) => frame,
+            Err(MediaError::Io(e)) if e.0.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        //IVF only stores when each frame starts, the gap since the last one is the best guess
+        let (numerator, denominator) = self.timebase;
+        let duration = match self.last_timestamp {
+            Some(last) if header.timestamp > last && denominator > 0 => Duration::from_secs_f64(
+                (header.timestamp - last) as f64 * numerator as f64 / denominator as f64,
+            ),
+            _ => Duration::from_secs(1) / DEFAULT_FRAME_RATE,
+        };
+        self.last_timestamp = Some(header.timestamp);
+        //Bit 0 of a VP8 frame tag is clear on keyframes
+        let keyframe = data.first().is_some_and(|tag| tag & 0x01 == 0);
+        Ok(Some(VideoFrame {
+            data: data.to_vec(),
+            duration,
+            keyframe,
+        }))
+    }
+}
+
+//Plays back an Annex B H264 file. The stream has no timing, so frames go out at a fixed rate and
+//every slice is treated as a whole picture
+pub struct H264FileSource {
+    reader: H264Reader<BufReader<File>>,
+    frame_duration: Duration,
+}
+
+impl Debug for H264FileSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "H264 video source")
+    }
+}
+
+impl H264FileSource {
+    pub fn open(path: &Path, frame_rate: u32) -> Result<Self> {
+        if frame_rate == 0 {
+            return Err(anyhow!("Frame rate can't be 0"));
+        }
+        Ok(Self {
+            reader: H264Reader::new(BufReader::new(File::open(path)?), H264_READ_BUFFER),
+            frame_duration: Duration::from_secs(1) / frame_rate,
+        })
+    }
+}
+
+impl FrameSource for H264FileSource {
+    fn codec(&self) -> VideoCodec {
+        VideoCodec::H264
+    }
+
+    fn next_frame(&mut self) -> Result<Option<VideoFrame>> {
+        let mut data = Vec::new();
+        let mut keyframe = false;
+        loop {
+            let nal = match self.reader.next_nal() {
+                Ok(nal) => nal,
+                Err(MediaError::ErrIoEOF) if data.is_empty() => return Ok(None),
+                Err(MediaError::ErrIoEOF) => break,
+                Err(e) => return Err(e.into()),
+            };
+            if nal.unit_type == NalUnitType::AUD {
+                continue;
+            }
+            data.extend_from_slice(&START_CODE);
+            data.extend_from_slice(&nal.data);
+            match nal.unit_type {
+                NalUnitType::CodedSliceIdr => {
+                    keyframe = true;
+                    break;
+                }
+                NalUnitType::CodedSliceNonIdr => break,
+                _ => {}
+            }
+        }
+        Ok(Some(VideoFrame {
+            data,
+            duration: self.frame_duration,
+            keyframe,
+        }))
+    }
+}
+
+//Writes an RBSP as a NAL unit, escaping anything that could be mistaken for a start code
+fn write_nal(out: &mut Vec<u8>, header: u8, rbsp: &[u8]) {
+    out.extend_from_slice(&START_CODE);
+    out.push(header);
+    let mut zeros = 0;
+    for &byte in rbsp {
+        if zeros >= 2 && byte <= 3 {
+            out.push(3);
+            zeros = 0;
+        }
+        out.push(byte);
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+    }
+}
+
+//MSB first bit writer with H264's Exp-Golomb codes
+#[derive(Debug, Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    current: u8,
+    filled: u8,
+}
+
+impl BitWriter {
+    fn bit(&mut self, bit: bool) {
+        self.current = (self.current << 1) | bit as u8;
+        self.filled += 1;
+        if self.filled == 8 {
+            self.bytes.push(self.current);
+            self.current = 0;
+            self.filled = 0;
+        }
+    }
+
+    fn bits(&mut self, value: u32, count: u32) {
+        for i in (0..count).rev() {
+            self.bit((value >> i) & 1 == 1);
+        }
+    }
+
+    fn ue(&mut self, value: u32) {
+        let value = value + 1;
+        let len = 32 - value.leading_zeros();
+        self.bits(0, len - 1);
+        self.bits(value, len);
+    }
+
+    fn se(&mut self, value: i32) {
+        let mapped = if value > 0 {
+            value as u32 * 2 - 1
+        } else {
+            value.unsigned_abs() * 2
+        };
+        self.ue(mapped);
+    }
+
+    fn align(&mut self) {
+        while self.filled != 0 {
+            self.bit(false);
+        }
+    }
+
+    //Only called when byte aligned
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    //Adds the rbsp stop bit
+    fn finish(mut self) -> Vec<u8> {
+        self.bit(true);
+        self.align();
+        self.bytes
+    }
+}
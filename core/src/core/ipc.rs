@@ -6,9 +6,14 @@ use tracing::{error, info};
 
 use anyhow::Result;
 
+use crate::core::backend::AudioDevices;
+use crate::core::stats::ConnectionStats;
 use crate::database::models::User;
-use crate::utils::enums::{RunMessage, UserStatus};
-use crate::utils::types::{NodeId, TextMessage};
+use crate::utils::enums::{
+    CallEndReason, CloseReason, ConnectionFailure, ConnectionStatus, RunMessage, SessionType,
+    UserStatus, VideoSource,
+};
+use crate::utils::types::{AudioProcessingSettings, NodeId, VoiceMessage, VoiceState};
 
 //Structs are public for UTs
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
@@ -27,6 +32,33 @@ pub enum IPCMessage {
 pub enum IPCResponse {
     SendUsers(SendUsersResp),
     SendUser(User),
+    SendAudioDevices(AudioDevices),
+    SendAudioProcessing(AudioProcessingSettings),
+    PeerVoiceState(PeerVoiceStateResp),
+    Speaking(SpeakingResp),
+    CallRinging(CallResp),
+    IncomingCall(CallResp),
+    CallAccepted(CallResp),
+    CallEnded(CallEndedResp),
+    ParticipantJoined(ParticipantResp),
+    ParticipantLeft(ParticipantResp),
+    SendVoiceParticipants(VoiceParticipantsResp),
+    RecordingStarted(RecordingResp),
+    RecordingStopped(RecordingResp),
+    SendSounds(SoundsResp),
+    VoiceMessageSent(VoiceMessage),
+    VoiceMessageReceived(VoiceMessage),
+    SendVoiceMessages(VoiceMessagesResp),
+    SendConnectionStats(ConnectionStats),
+    ConnectionStats(ConnectionStats),
+    ConnectionStatus(ConnectionStatusResp),
+    ConnectionClosed(ConnectionClosedResp),
+    ConnectionFailed(ConnectionFailedResp),
+    PeerUnreachable(PeerUnreachableResp),
+    SendVideoSource(VideoSource),
+    StreamStarted(ParticipantResp),
+    StreamEnded(ParticipantResp),
+    StreamViewers(StreamViewersResp),
     Error(IPCErrorType),
 }
 
@@ -57,10 +89,131 @@ pub struct UpdateStatusMsg {
     pub user_status: UserStatus,
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct PeerVoiceStateResp {
+    #[serde(rename = "nodeId")]
+    pub node_id: NodeId,
+    #[serde(rename = "voiceState")]
+    pub voice_state: VoiceState,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct SpeakingResp {
+    #[serde(rename = "nodeId")]
+    pub node_id: NodeId,
+    #[serde(rename = "speaking")]
+    pub speaking: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct CallResp {
+    #[serde(rename = "nodeId")]
+    pub node_id: NodeId,
+    //Call or Video
+    #[serde(rename = "sessionType")]
+    pub session_type: SessionType,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct CallEndedResp {
+    #[serde(rename = "nodeId")]
+    pub node_id: NodeId,
+    #[serde(rename = "reason")]
+    pub reason: CallEndReason,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ParticipantResp {
+    #[serde(rename = "nodeId")]
+    pub node_id: NodeId,
+    #[serde(rename = "channel")]
+    pub channel: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ParticipantInfo {
+    #[serde(rename = "nodeId")]
+    pub node_id: NodeId,
+    #[serde(rename = "volume")]
+    pub volume: u16,
+    #[serde(rename = "streaming")]
+    pub streaming: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct VoiceParticipantsResp {
+    #[serde(rename = "channel")]
+    pub channel: String,
+    #[serde(rename = "participants")]
+    pub participants: Vec<ParticipantInfo>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ConnectionStatusResp {
+    #[serde(rename = "nodeId")]
+    pub node_id: NodeId,
+    #[serde(rename = "status")]
+    pub status: ConnectionStatus,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ConnectionClosedResp {
+    #[serde(rename = "nodeId")]
+    pub node_id: NodeId,
+    #[serde(rename = "reason")]
+    pub reason: CloseReason,
+}
+
+//Node id is missing when we were waiting for someone to offer and nobody did
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ConnectionFailedResp {
+    #[serde(rename = "nodeId")]
+    pub node_id: Option<NodeId>,
+    #[serde(rename = "sessionType")]
+    pub session_type: SessionType,
+    #[serde(rename = "reason")]
+    pub reason: ConnectionFailure,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct PeerUnreachableResp {
+    #[serde(rename = "nodeId")]
+    pub node_id: NodeId,
+    //Still waiting for them, they go out whenever we next connect
+    #[serde(rename = "queuedMessages")]
+    pub queued_messages: usize,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct StreamViewersResp {
+    #[serde(rename = "channel")]
+    pub channel: String,
+    #[serde(rename = "viewers")]
+    pub viewers: Vec<NodeId>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct RecordingResp {
+    #[serde(rename = "path")]
+    pub path: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct SoundsResp {
+    #[serde(rename = "sounds")]
+    pub sounds: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct VoiceMessagesResp {
+    #[serde(rename = "voiceMessages")]
+    pub voice_messages: Vec<VoiceMessage>,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct SendMessageMsg {
     #[serde(rename = "nodeId")]
-    pub display_name: String,
+    pub node_id: NodeId,
     #[serde(rename = "content")]
     pub content: String,
 }
@@ -76,28 +229,36 @@ pub async fn listen(
     match listener.accept().await {
         Ok((mut socket, _)) => {
             let mut buf = vec![0; 1024];
+            //Responses and events from the runtime are written as soon as they arrive, so peer
+            //events don't have to wait for the next request
             loop {
-                let num_bytes = socket.read(&mut buf).await.expect("Error reading...");
-                buf = buf[0..num_bytes].to_vec();
-
-                let run_message = match serde_json::from_slice::<RunMessage>(&buf) {
-                    Ok(ipc_message) => ipc_message,
-                    Err(e) => {
-                        error!("Error deserializing IPC message: {e}");
-                        continue;
+                tokio::select! {
+                    num_bytes = socket.read(&mut buf) => {
+                        let num_bytes = num_bytes.expect("Error reading...");
+                        if num_bytes == 0 {
+                            info!("IPC socket closed");
+                            return Ok(());
+                        }
+
+                        let run_message = match serde_json::from_slice::<RunMessage>(&buf[0..num_bytes]) {
+                            Ok(ipc_message) => ipc_message,
+                            Err(e) => {
+                                error!("Error deserializing IPC message: {e}");
+                                continue;
+                            }
+                        };
+
+                        runtime_tx
+                            .send(run_message)
+                            .await
+                            .expect("Failed to send run message from listener");
+                        info!("Forwarded IPC message to runtime...");
+                    }
+                    Some(response) = rx.recv() => {
+                        let bytes = serde_json::to_vec(&response)?;
+                        info!("Num bytes in core/ipc: {}", bytes.len());
+                        socket.write_all(&bytes).await?;
                     }
-                };
-
-                runtime_tx
-                    .send(run_message)
-                    .await
-                    .expect("Failed to send run message from listener");
-                info!("Forwarded IPC message to runtime...");
-
-                if let Some(response) = rx.recv().await {
-                    let bytes = serde_json::to_vec(&response)?;
-                    info!("Num bytes in core/ipc: {}", bytes.len());
-                    socket.write(&bytes).await?;
                 }
             }
         }
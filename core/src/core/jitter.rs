@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+
+//Number of 20ms packets to buffer before starting playback
+pub const JITTER_BUFFER_DEPTH: usize = 3;
+//Anything beyond this is considered too far behind and gets skipped
+pub const JITTER_BUFFER_MAX: usize = 50;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum JitterOutput {
+    Packet(Vec<u8>),
+    //The expected packet never arrived, decoder should conceal the gap
+    Lost,
+    //Nothing buffered, either still filling up or the sender stopped
+    Empty,
+}
+
+//Reorders RTP payloads by sequence number and releases one packet per frame interval
+#[derive(Debug, Default)]
+pub struct JitterBuffer {
+    packets: BTreeMap<u64, Vec<u8>>,
+    next_seq: Option<u64>,
+    last_seq: Option<u64>,
+    playing: bool,
+}
+
+impl JitterBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    //Extends the 16 bit RTP sequence number so ordering survives wraparound
+    fn extend_seq(&mut self, seq: u16) -> u64 {
+        let extended = match self.last_seq {
+            None => seq as u64 + (1 << 16),
+            Some(last) => {
+                let cycle = last & !0xffff;
+                let candidates = [cycle.saturating_sub(1 << 16), cycle, cycle + (1 << 16)];
+                candidates
+                    .iter()
+                    .map(|c| c + seq as u64)
+                    .min_by_key(|c| c.abs_diff(last))
+                    .unwrap_or(cycle + seq as u64)
+            }
+        };
+        if self.last_seq.is_none_or(|last| extended > last) {
+            self.last_seq = Some(extended);
+        }
+        extended
+    }
+
+    pub fn push(&mut self, seq: u16, payload: Vec<u8>) {
+        let seq = self.extend_seq(seq);
+        //Packet arrived after we already played past it
+        if let Some(next) = self.next_seq {
+            if seq < next {
+                return;
+            }
+        }
+        self.packets.insert(seq, payload);
+
+        //Drop the oldest packets if the sender is running too far ahead
+        while self.packets.len() > JITTER_BUFFER_MAX {
+            if let Some((&oldest, _)) = self.packets.iter().next() {
+                self.packets.remove(&oldest);
+                self.next_seq = Some(oldest + 1);
+            }
+        }
+    }
+
+    pub fn pop(&mut self) -> JitterOutput {
+        if !self.playing {
+            if self.packets.len() < JITTER_BUFFER_DEPTH {
+                return JitterOutput::Empty;
+            }
+            self.playing = true;
+        }
+
+        let next = match self.next_seq {
+            Some(next) => next,
+            None => match self.packets.keys().next() {
+                Some(&first) => first,
+                None => return JitterOutput::Empty,
+            },
+        };
+
+        if self.packets.is_empty() {
+            //Refill before playing again so we don't stutter on every packet
+            self.playing = false;
+            return JitterOutput::Empty;
+        }
+
+        self.next_seq = Some(next + 1);
+        match self.packets.remove(&next) {
+            Some(payload) => JitterOutput::Packet(payload),
+            None => JitterOutput::Lost,
+        }
+    }
+}
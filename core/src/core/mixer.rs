@@ -0,0 +1,104 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::core::audio::{resample, upmix, FRAME_SIZE, SAMPLE_RATE};
+
+//Audio a source can have queued before the oldest samples are dropped to keep latency down
+pub const MAX_MIXER_BUFFER: usize = FRAME_SIZE * 10;
+//Samples below this level pass through the limiter untouched
+pub const SOFT_CLIP_THRESHOLD: f32 = 0.8;
+
+pub type SourceId = u64;
+
+#[derive(Debug)]
+struct MixerSource {
+    buffer: VecDeque<f32>,
+    gain: f32,
+}
+
+//Mixes any number of 48kHz mono sources into 20ms frames in the output's format
+#[derive(Debug)]
+pub struct Mixer {
+    sources: HashMap<SourceId, MixerSource>,
+    next_id: SourceId,
+    output_rate: u32,
+    output_channels: u16,
+}
+
+impl Mixer {
+    pub fn new(output_rate: u32, output_channels: u16) -> Self {
+        Self {
+            sources: HashMap::new(),
+            next_id: 0,
+            output_rate,
+            output_channels,
+        }
+    }
+
+    pub fn add_source(&mut self) -> SourceId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.sources.insert(
+            id,
+            MixerSource {
+                buffer: VecDeque::with_capacity(MAX_MIXER_BUFFER),
+                gain: 1.0,
+            },
+        );
+        id
+    }
+
+    pub fn remove_source(&mut self, id: SourceId) {
+        self.sources.remove(&id);
+    }
+
+    pub fn set_gain(&mut self, id: SourceId, gain: f32) {
+        if let Some(source) = self.sources.get_mut(&id) {
+            source.gain = gain.max(0.0);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    pub fn push(&mut self, id: SourceId, samples: &[f32]) {
+        if let Some(source) = self.sources.get_mut(&id) {
+            source.buffer.extend(samples);
+            let excess = source.buffer.len().saturating_sub(MAX_MIXER_BUFFER);
+            source.buffer.drain(..excess);
+        }
+    }
+
+    //Pulls the next 20ms from every source. Returns None when nobody has anything to play
+    pub fn mix(&mut self) -> Option<Vec<f32>> {
+        if self.sources.values().all(|source| source.buffer.is_empty()) {
+            return None;
+        }
+
+        let mut frame = vec![0f32; FRAME_SIZE];
+        for source in self.sources.values_mut() {
+            let len = source.buffer.len().min(FRAME_SIZE);
+            for (mixed, sample) in frame.iter_mut().zip(source.buffer.drain(..len)) {
+                *mixed += sample * source.gain;
+            }
+        }
+        frame
+            .iter_mut()
+            .for_each(|sample| *sample = soft_clip(*sample));
+
+        let frame = resample(&frame, SAMPLE_RATE, self.output_rate);
+        Some(upmix(&frame, self.output_channels))
+    }
+}
+
+//Leaves quiet samples alone and smoothly squashes anything louder so the sum never exceeds 1.0
+pub fn soft_clip(sample: f32) -> f32 {
+    let magnitude = sample.abs();
+    if magnitude <= SOFT_CLIP_THRESHOLD {
+        return sample;
+    }
+    let headroom = 1.0 - SOFT_CLIP_THRESHOLD;
+    let compressed =
+        SOFT_CLIP_THRESHOLD + headroom * ((magnitude - SOFT_CLIP_THRESHOLD) / headroom).tanh();
+    compressed.copysign(sample)
+}
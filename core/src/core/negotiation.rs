@@ -0,0 +1,60 @@
+use webrtc::peer_connection::signaling_state::RTCSignalingState;
+
+use crate::utils::types::NodeId;
+
+//What to do with a description the peer sent us
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemoteDescriptionAction {
+    Apply,
+    //Drop our own pending offer first, then apply theirs
+    Rollback,
+    Ignore,
+}
+
+//Perfect negotiation: either side may offer at any time, e.g. both connecting at once or adding a
+//track mid call. When offers collide the polite peer gives up its own and answers, while the
+//impolite peer ignores the incoming one and waits for its answer.
+//Connection runs every offer and remote description under one lock, so an offer we are still
+//making always shows up as HaveLocalOffer here
+#[derive(Debug)]
+pub struct Negotiation {
+    polite: bool,
+}
+
+impl Negotiation {
+    pub fn new(polite: bool) -> Self {
+        Self { polite }
+    }
+
+    pub fn is_polite(&self) -> bool {
+        self.polite
+    }
+
+    pub fn set_polite(&mut self, polite: bool) {
+        self.polite = polite;
+    }
+
+    pub fn on_remote_description(
+        &self,
+        is_offer: bool,
+        state: RTCSignalingState,
+    ) -> RemoteDescriptionAction {
+        if !is_offer {
+            //An answer only means something while our offer is waiting for one
+            return match state {
+                RTCSignalingState::HaveLocalOffer => RemoteDescriptionAction::Apply,
+                _ => RemoteDescriptionAction::Ignore,
+            };
+        }
+        match state {
+            RTCSignalingState::Stable => RemoteDescriptionAction::Apply,
+            RTCSignalingState::HaveLocalOffer if self.polite => RemoteDescriptionAction::Rollback,
+            _ => RemoteDescriptionAction::Ignore,
+        }
+    }
+}
+
+//Both peers work this out on their own, so they always agree on who backs down
+pub fn is_polite(local: &NodeId, remote: &NodeId) -> bool {
+    local.as_bytes() < remote.as_bytes()
+}
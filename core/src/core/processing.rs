@@ -0,0 +1,291 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::broadcast;
+
+use crate::core::audio::FRAME_SIZE;
+use crate::core::mixer::soft_clip;
+use crate::core::vad::rms;
+use crate::utils::enums::AudioProcessing;
+use crate::utils::types::AudioProcessingSettings;
+
+//Taps in the echo canceller's adaptive filter, about 21ms of echo tail at 48kHz
+pub const ECHO_FILTER_LENGTH: usize = 1024;
+//NLMS step size, higher converges faster but is noisier once converged
+const ECHO_STEP_SIZE: f32 = 0.5;
+//Played frames a capture can fall behind on before the oldest are dropped
+const MAX_REFERENCE_FRAMES: usize = 50;
+
+//Anything this much above the tracked noise floor is left alone
+const NOISE_OVER_SUBTRACTION: f32 = 2.0;
+//Never attenuate noise by more than 20dB so the result doesn't sound gated
+const NOISE_MIN_GAIN: f32 = 0.1;
+//The noise floor creeps up by about 1dB a second so it follows a noisier room
+const NOISE_FLOOR_RISE: f32 = 1.0023;
+
+//Level speech is brought to, in dBFS
+pub const AGC_TARGET_DB: f32 = -20.0;
+const AGC_MAX_GAIN_DB: f32 = 20.0;
+//Quieter frames are treated as silence and don't move the gain
+const AGC_MIN_LEVEL_DB: f32 = -50.0;
+//Fraction of the way the gain moves towards its target each frame
+const AGC_SMOOTHING: f32 = 0.1;
+
+//Which processing steps run on captured audio. Shared by every capture so changes apply live
+#[derive(Debug)]
+pub struct ProcessingControls {
+    echo_cancellation: AtomicBool,
+    noise_suppression: AtomicBool,
+    auto_gain_control: AtomicBool,
+}
+
+impl Default for ProcessingControls {
+    fn default() -> Self {
+        Self {
+            echo_cancellation: AtomicBool::new(true),
+            noise_suppression: AtomicBool::new(true),
+            auto_gain_control: AtomicBool::new(true),
+        }
+    }
+}
+
+impl ProcessingControls {
+    pub fn set(&self, processing: AudioProcessing, enabled: bool) {
+        self.flag(processing).store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self, processing: AudioProcessing) -> bool {
+        self.flag(processing).load(Ordering::Relaxed)
+    }
+
+    pub fn settings(&self) -> AudioProcessingSettings {
+        AudioProcessingSettings {
+            echo_cancellation: self.is_enabled(AudioProcessing::EchoCancellation),
+            noise_suppression: self.is_enabled(AudioProcessing::NoiseSuppression),
+            auto_gain_control: self.is_enabled(AudioProcessing::AutoGainControl),
+        }
+    }
+
+    fn flag(&self, processing: AudioProcessing) -> &AtomicBool {
+        match processing {
+            AudioProcessing::EchoCancellation => &self.echo_cancellation,
+            AudioProcessing::NoiseSuppression => &self.noise_suppression,
+            AudioProcessing::AutoGainControl => &self.auto_gain_control,
+        }
+    }
+}
+
+//Everything sent to the output device, so each capture can cancel it out of the microphone
+#[derive(Debug)]
+pub struct EchoReference {
+    frames_tx: broadcast::Sender<Arc<Vec<f32>>>,
+}
+
+impl Default for EchoReference {
+    fn default() -> Self {
+        let (frames_tx, _) = broadcast::channel(MAX_REFERENCE_FRAMES);
+        Self { frames_tx }
+    }
+}
+
+impl EchoReference {
+    pub fn push(&self, frame: &[f32]) {
+        //Nobody capturing is not an error
+        let _ = self.frames_tx.send(Arc::new(frame.to_vec()));
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Vec<f32>>> {
+        self.frames_tx.subscribe()
+    }
+}
+
+//Normalised LMS filter that learns the path from the speaker to the microphone and subtracts the
+//predicted echo from each captured sample
+#[derive(Debug)]
+pub struct EchoCanceller {
+    weights: Vec<f32>,
+    //Every reference sample is written twice so the latest window is always one contiguous slice
+    history: Vec<f32>,
+    position: usize,
+    energy: f32,
+}
+
+impl Default for EchoCanceller {
+    fn default() -> Self {
+        Self::new(ECHO_FILTER_LENGTH)
+    }
+}
+
+impl EchoCanceller {
+    pub fn new(filter_length: usize) -> Self {
+        Self {
+            weights: vec![0.0; filter_length],
+            history: vec![0.0; filter_length * 2],
+            position: 0,
+            energy: 0.0,
+        }
+    }
+
+    //reference is what was played while capture was recorded, both the same length
+    pub fn process(&mut self, capture: &mut [f32], reference: &[f32]) {
+        let length = self.weights.len();
+        for (sample, far) in capture.iter_mut().zip(reference) {
+            self.position = (self.position + length - 1) % length;
+            let oldest = self.history[self.position];
+            self.energy = (self.energy + far * far - oldest * oldest).max(0.0);
+            self.history[self.position] = *far;
+            self.history[self.position + length] = *far;
+
+            let window = &self.history[self.position..self.position + length];
+            let echo: f32 = self.weights.iter().zip(window).map(|(w, x)| w * x).sum();
+            let error = *sample - echo;
+            *sample = error;
+
+            if self.energy > f32::EPSILON {
+                let step = ECHO_STEP_SIZE * error / (self.energy + 1e-6);
+                for (weight, x) in self.weights.iter_mut().zip(window) {
+                    *weight += step * x;
+                }
+            }
+        }
+    }
+}
+
+//Tracks the background noise level and turns frames down the closer they are to it
+#[derive(Debug)]
+pub struct NoiseSuppressor {
+    noise_floor: Option<f32>,
+    gain: f32,
+}
+
+impl Default for NoiseSuppressor {
+    fn default() -> Self {
+        Self {
+            noise_floor: None,
+            gain: 1.0,
+        }
+    }
+}
+
+impl NoiseSuppressor {
+    pub fn process(&mut self, frame: &mut [f32]) {
+        let level = rms(frame);
+        let noise_floor = match self.noise_floor {
+            Some(floor) if level >= floor => floor * NOISE_FLOOR_RISE,
+            _ => level,
+        };
+        self.noise_floor = Some(noise_floor);
+
+        let noise = noise_floor * NOISE_OVER_SUBTRACTION;
+        let target = if level > noise {
+            (1.0 - (noise * noise) / (level * level)).sqrt()
+        } else {
+            0.0
+        };
+        let target = target.max(NOISE_MIN_GAIN);
+        apply_gain_ramp(frame, self.gain, target);
+        self.gain = target;
+    }
+}
+
+//Brings speech to a steady level so quiet and loud microphones sound the same to the peer
+#[derive(Debug)]
+pub struct AutomaticGainControl {
+    gain: f32,
+}
+
+impl Default for AutomaticGainControl {
+    fn default() -> Self {
+        Self { gain: 1.0 }
+    }
+}
+
+impl AutomaticGainControl {
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    pub fn process(&mut self, frame: &mut [f32]) {
+        let level = rms(frame);
+        let previous = self.gain;
+        if level > db_to_gain(AGC_MIN_LEVEL_DB) {
+            let target = (db_to_gain(AGC_TARGET_DB) / level).min(db_to_gain(AGC_MAX_GAIN_DB));
+            self.gain += (target - self.gain) * AGC_SMOOTHING;
+        }
+        apply_gain_ramp(frame, previous, self.gain);
+        frame
+            .iter_mut()
+            .for_each(|sample| *sample = soft_clip(*sample));
+    }
+}
+
+//Runs the enabled steps on every 20ms frame between capture and encoding
+#[derive(Debug)]
+pub struct AudioProcessor {
+    controls: Arc<ProcessingControls>,
+    reference_rx: broadcast::Receiver<Arc<Vec<f32>>>,
+    reference: VecDeque<f32>,
+    echo_canceller: EchoCanceller,
+    noise_suppressor: NoiseSuppressor,
+    gain_control: AutomaticGainControl,
+}
+
+impl AudioProcessor {
+    pub fn new(controls: Arc<ProcessingControls>, echo_reference: &EchoReference) -> Self {
+        Self {
+            controls,
+            reference_rx: echo_reference.subscribe(),
+            reference: VecDeque::with_capacity(FRAME_SIZE * MAX_REFERENCE_FRAMES),
+            echo_canceller: EchoCanceller::default(),
+            noise_suppressor: NoiseSuppressor::default(),
+            gain_control: AutomaticGainControl::default(),
+        }
+    }
+
+    pub fn process(&mut self, frame: &mut [f32]) {
+        //Always drain the reference so it stays in step with the capture while AEC is off
+        let reference = self.next_reference(frame.len());
+        if self.controls.is_enabled(AudioProcessing::EchoCancellation) {
+            self.echo_canceller.process(frame, &reference);
+        }
+        if self.controls.is_enabled(AudioProcessing::NoiseSuppression) {
+            self.noise_suppressor.process(frame);
+        }
+        if self.controls.is_enabled(AudioProcessing::AutoGainControl) {
+            self.gain_control.process(frame);
+        }
+    }
+
+    //Nothing played is the same as silence from the speaker
+    fn next_reference(&mut self, len: usize) -> Vec<f32> {
+        loop {
+            match self.reference_rx.try_recv() {
+                Ok(frame) => self.reference.extend(frame.iter()),
+                Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+                Err(_) => break,
+            }
+        }
+        let excess = self
+            .reference
+            .len()
+            .saturating_sub(FRAME_SIZE * MAX_REFERENCE_FRAMES);
+        self.reference.drain(..excess);
+        let available = self.reference.len().min(len);
+        let mut reference: Vec<f32> = self.reference.drain(..available).collect();
+        reference.resize(len, 0.0);
+        reference
+    }
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+//Moves linearly from the previous frame's gain to the new one to avoid clicks
+fn apply_gain_ramp(frame: &mut [f32], from: f32, to: f32) {
+    let len = frame.len().max(1) as f32;
+    for (i, sample) in frame.iter_mut().enumerate() {
+        *sample *= from + (to - from) * (i + 1) as f32 / len;
+    }
+}
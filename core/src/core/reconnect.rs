@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use crate::utils::constants::{RECONNECT_ATTEMPTS, RECONNECT_BASE_DELAY, RECONNECT_MAX_DELAY};
+
+//Exponential backoff between attempts at renegotiating a failed connection, so two peers that
+//can't reach each other don't keep hammering the relay
+#[derive(Debug)]
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    attempts: u32,
+    attempt: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(
+            Duration::from_secs(RECONNECT_BASE_DELAY),
+            Duration::from_secs(RECONNECT_MAX_DELAY),
+            RECONNECT_ATTEMPTS,
+        )
+    }
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration, attempts: u32) -> Self {
+        Self {
+            base,
+            max,
+            attempts,
+            attempt: 0,
+        }
+    }
+
+    //How long to wait before the next attempt, None once we should give up
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if self.attempt >= self.attempts {
+            return None;
+        }
+        let delay = self
+            .base
+            .saturating_mul(2u32.saturating_pow(self.attempt))
+            .min(self.max);
+        self.attempt += 1;
+        Some(delay)
+    }
+
+    //Attempts made so far
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+}
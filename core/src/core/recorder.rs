@@ -0,0 +1,361 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use tracing::{error, info};
+use webrtc::media::io::{
+    h264_writer::H264Writer, ivf_reader::IVFFileHeader, ivf_writer::IVFWriter,
+    ogg_writer::OggWriter, Writer,
+};
+use webrtc::rtp::{header::Header, packet::Packet};
+
+use crate::core::audio::{CHANNELS, FRAME_SIZE, SAMPLE_RATE};
+use crate::core::frame_source::DEFAULT_FRAME_RATE;
+use crate::utils::enums::VideoCodec;
+
+//Opt-in call recording. While a recording is running our track and every peer's are written to
+//separate Ogg/Opus files in the recording's directory, and every received video track is dumped
+//alongside them
+#[derive(Debug, Default)]
+pub struct Recording {
+    session: Mutex<Option<(u64, PathBuf)>>,
+}
+
+impl Recording {
+    //Creates a new timestamped directory under root for this recording's tracks
+    pub fn start(&self, root: &Path) -> Result<PathBuf> {
+        let mut session = self.lock()?;
+        if session.is_some() {
+            return Err(anyhow!("Already recording"));
+        }
+        let started = chrono::Utc::now();
+        let directory = root.join(started.format("%Y%m%d-%H%M%S").to_string());
+        fs::create_dir_all(&directory)?;
+        let id = started.timestamp_millis() as u64;
+        *session = Some((id, directory.clone()));
+        Ok(directory)
+    }
+
+    pub fn stop(&self) -> Result<PathBuf> {
+        let mut session = self.lock()?;
+        match session.take() {
+            Some((_, directory)) => Ok(directory),
+            None => Err(anyhow!("Not recording")),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.current().is_some()
+    }
+
+    fn current(&self) -> Option<(u64, PathBuf)> {
+        self.lock().ok().and_then(|session| session.clone())
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Option<(u64, PathBuf)>>> {
+        self.session
+            .lock()
+            .map_err(|_| anyhow!("Recording lock poisoned"))
+    }
+}
+
+//Writes opus packets for a single track to an Ogg file, one 20ms frame at a time
+pub struct TrackRecorder {
+    writer: OggWriter<BufWriter<File>>,
+    timestamp: u32,
+    sequence_number: u16,
+    closed: bool,
+}
+
+impl std::fmt::Debug for TrackRecorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Ogg track recorder")
+    }
+}
+
+impl TrackRecorder {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        let writer = OggWriter::new(file, SAMPLE_RATE, CHANNELS as u8)?;
+        Ok(Self {
+            writer,
+            timestamp: 0,
+            sequence_number: 0,
+            closed: false,
+        })
+    }
+
+    pub fn write(&mut self, payload: &[u8]) -> Result<()> {
+        let packet = Packet {
+            header: Header {
+                timestamp: self.timestamp,
+                sequence_number: self.sequence_number,
+                ..Default::default()
+            },
+            payload: payload.to_vec().into(),
+        };
+        self.writer.write_rtp(&packet)?;
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+        self.skip();
+        Ok(())
+    }
+
+    //Nothing was sent or received for this frame, e.g. silence or a lost packet
+    pub fn skip(&mut self) {
+        self.timestamp = self.timestamp.wrapping_add(FRAME_SIZE as u32);
+    }
+
+    //Writes the end of stream page. Also done on drop if it wasn't called
+    pub fn close(&mut self) -> Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+        self.writer.close()?;
+        Ok(())
+    }
+}
+
+impl Drop for TrackRecorder {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+//Follows the shared recording state from inside an audio task, opening a file for the track when
+//a recording starts and closing it when it stops
+#[derive(Debug)]
+pub struct TrackRecording {
+    name: String,
+    recorder: Option<(u64, TrackRecorder)>,
+}
+
+impl TrackRecording {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            recorder: None,
+        }
+    }
+
+    //Called once per 20ms frame, None when there was no packet for the frame
+    pub fn record(&mut self, recording: &Recording, payload: Option<&[u8]>) {
+        let current = recording.current();
+        let is_current = matches!(
+            (&self.recorder, &current),
+            (Some((id, _)), Some((current_id, _))) if id == current_id
+        );
+        if !is_current {
+            self.recorder = None;
+            if let Some((id, directory)) = current {
+                let path = directory.join(format!("{}.ogg", self.name));
+                match TrackRecorder::create(&path) {
+                    Ok(recorder) => {
+                        info!("Recording {} to {}", self.name, path.display());
+                        self.recorder = Some((id, recorder));
+                    }
+                    Err(e) => error!("Error creating recording {}: {}", path.display(), e),
+                }
+            }
+        }
+
+        if let Some((_, recorder)) = self.recorder.as_mut() {
+            let result = match payload {
+                Some(payload) => recorder.write(payload),
+                None => {
+                    recorder.skip();
+                    Ok(())
+                }
+            };
+            if let Err(e) = result {
+                error!("Error writing recording: {}", e);
+            }
+        }
+    }
+}
+
+//Every connection encodes our microphone on its own, so in a voice channel several captures see
+//the same audio. Only one of them writes the local track at a time, another picks the file up
+//once that capture stops
+#[derive(Debug)]
+pub struct LocalTrackRecording {
+    writer: Mutex<(Option<u64>, TrackRecording)>,
+    next_id: AtomicU64,
+}
+
+impl Default for LocalTrackRecording {
+    fn default() -> Self {
+        Self {
+            writer: Mutex::new((None, TrackRecording::new("local".to_string()))),
+            next_id: AtomicU64::new(0),
+        }
+    }
+}
+
+impl LocalTrackRecording {
+    //One per capture, gives up writing when dropped
+    pub fn writer(self: &Arc<Self>) -> LocalTrackWriter {
+        LocalTrackWriter {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            local: Arc::clone(self),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LocalTrackWriter {
+    id: u64,
+    local: Arc<LocalTrackRecording>,
+}
+
+impl LocalTrackWriter {
+    //Frames from any capture but the one writing are dropped
+    pub fn record(&self, recording: &Recording, payload: Option<&[u8]>) {
+        let Ok(mut writer) = self.local.writer.lock() else {
+            return;
+        };
+        let (owner, track_recording) = &mut *writer;
+        if *owner.get_or_insert(self.id) == self.id {
+            track_recording.record(recording, payload);
+        }
+    }
+}
+
+impl Drop for LocalTrackWriter {
+    fn drop(&mut self) {
+        if let Ok(mut writer) = self.local.writer.lock() {
+            if writer.0 == Some(self.id) {
+                writer.0 = None;
+            }
+        }
+    }
+}
+
+//Writes a peer's video RTP as it arrives, IVF for VP8/VP9 and Annex B for H264. The writers drop
+//everything before the first keyframe so the file always starts with a decodable picture
+pub struct VideoTrackRecorder {
+    writer: Box<dyn Writer + Send>,
+    closed: bool,
+}
+
+impl std::fmt::Debug for VideoTrackRecorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Video track recorder")
+    }
+}
+
+impl VideoTrackRecorder {
+    pub fn create(path: &Path, codec: VideoCodec) -> Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        let writer: Box<dyn Writer + Send> = match codec {
+            VideoCodec::Vp8 | VideoCodec::Vp9 => {
+                let header = IVFFileHeader {
+                    signature: *b"DKIF",
+                    version: 0,
+                    header_size: 32,
+                    four_cc: if codec == VideoCodec::Vp8 {
+                        *b"VP80"
+                    } else {
+                        *b"VP90"
+                    },
+                    //Decoders take the picture size from the keyframe
+                    width: 0,
+                    height: 0,
+                    //The writer numbers frames rather than using RTP time
+                    timebase_denominator: DEFAULT_FRAME_RATE,
+                    timebase_numerator: 1,
+                    num_frames: 0,
+                    unused: 0,
+                };
+                Box::new(IVFWriter::new(file, &header)?)
+            }
+            VideoCodec::H264 => Box::new(H264Writer::new(file)),
+        };
+        Ok(Self {
+            writer,
+            closed: false,
+        })
+    }
+
+    pub fn extension(codec: VideoCodec) -> &'static str {
+        match codec {
+            VideoCodec::Vp8 | VideoCodec::Vp9 => "ivf",
+            VideoCodec::H264 => "h264",
+        }
+    }
+
+    pub fn write(&mut self, packet: &Packet) -> Result<()> {
+        self.writer.write_rtp(packet)?;
+        Ok(())
+    }
+
+    //Fills in the frame count for IVF and flushes. Also done on drop if it wasn't called
+    pub fn close(&mut self) -> Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+        self.writer.close()?;
+        Ok(())
+    }
+}
+
+impl Drop for VideoTrackRecorder {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+//Follows the shared recording state from inside a video receive task, the same way TrackRecording
+//does for audio
+#[derive(Debug)]
+pub struct VideoTrackRecording {
+    name: String,
+    codec: VideoCodec,
+    recorder: Option<(u64, VideoTrackRecorder)>,
+}
+
+impl VideoTrackRecording {
+    pub fn new(name: String, codec: VideoCodec) -> Self {
+        Self {
+            name,
+            codec,
+            recorder: None,
+        }
+    }
+
+    //Called with every RTP packet received on the track
+    pub fn record(&mut self, recording: &Recording, packet: &Packet) {
+        let current = recording.current();
+        let is_current = matches!(
+            (&self.recorder, &current),
+            (Some((id, _)), Some((current_id, _))) if id == current_id
+        );
+        if !is_current {
+            self.recorder = None;
+            if let Some((id, directory)) = current {
+                let path = directory.join(format!(
+                    "{}-video.{}",
+                    self.name,
+                    VideoTrackRecorder::extension(self.codec)
+                ));
+                match VideoTrackRecorder::create(&path, self.codec) {
+                    Ok(recorder) => {
+                        info!("Recording {} video to {}", self.name, path.display());
+                        self.recorder = Some((id, recorder));
+                    }
+                    Err(e) => error!("Error creating recording {}: {}", path.display(), e),
+                }
+            }
+        }
+
+        if let Some((_, recorder)) = self.recorder.as_mut() {
+            if let Err(e) = recorder.write(packet) {
+                error!("Error writing video recording: {}", e);
+            }
+        }
+    }
+}
@@ -1,34 +1,54 @@
+use crate::core::audio::{self, AudioContext, AudioStream, RemoteAudio, Volume};
+use crate::core::bitrate::{BitrateController, EncoderSettings};
+use crate::core::negotiation::{self, Negotiation, RemoteDescriptionAction};
 use crate::core::signal::{Session, SessionExchange};
+use crate::core::stats::{self, ConnectionStats, StatsSampler};
+use crate::core::video::{self, LiveFeed, VideoContext, VideoStream};
 use crate::utils::{
-    constants::{SEND_SESSION_DELAY, SEND_SESSION_TIMEOUT},
-    enums::{ConnType, MessageType},
-    types::TextMessage,
+    constants::{
+        DATA_CHANNEL_TIMEOUT, DESCRIPTION_TIMEOUT, ICE_CONNECT_TIMEOUT, OFFER_TIMEOUT,
+        SEND_SESSION_DELAY, SEND_SESSION_TIMEOUT,
+    },
+    enums::{ConnType, ConnectionFailure, HandshakePhase, MessageType, SessionType},
+    errors::HandshakeError,
+    types::{TextMessage, VoiceMessage},
 };
 
 use anyhow::{anyhow, Context, Result};
 use chrono::Utc;
 use iroh::net::NodeId;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex, Notify};
+use tokio::sync::{mpsc, watch, Mutex, Notify};
 use tokio::task::JoinHandle;
-use tokio::time::{sleep, timeout, Duration};
+use tokio::time::{sleep, timeout, Duration, Instant};
 use tracing::{error, info};
 use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
 use webrtc::{
-    api::{
-        media_engine::{MIME_TYPE_H264, MIME_TYPE_OPUS},
-        API,
-    },
+    api::{media_engine::MIME_TYPE_OPUS, API},
     data_channel::data_channel_message::DataChannelMessage,
-    data_channel::RTCDataChannel,
+    data_channel::{
+        data_channel_init::RTCDataChannelInit, data_channel_state::RTCDataChannelState,
+        RTCDataChannel,
+    },
     ice_transport::ice_candidate::RTCIceCandidate,
-    media::{io::ogg_reader::OggReader, Sample},
     peer_connection::{
-        configuration::RTCConfiguration, peer_connection_state::RTCPeerConnectionState,
-        sdp::session_description::RTCSessionDescription, RTCPeerConnection,
+        configuration::RTCConfiguration,
+        offer_answer_options::RTCOfferOptions,
+        peer_connection_state::RTCPeerConnectionState,
+        sdp::{sdp_type::RTCSdpType, session_description::RTCSessionDescription},
+        RTCPeerConnection,
+    },
+    rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication,
+    rtp_transceiver::{
+        rtp_codec::{RTCRtpCodecCapability, RTPCodecType},
+        rtp_receiver::RTCRtpReceiver,
+        RTCRtpTransceiver,
+    },
+    track::{
+        track_local::{track_local_static_sample::TrackLocalStaticSample, TrackLocal},
+        track_remote::TrackRemote,
     },
-    rtp_transceiver::rtp_codec::RTCRtpCodecCapability,
-    track::track_local::{track_local_static_sample::TrackLocalStaticSample, TrackLocal},
 };
 
 pub struct APIWrapper(pub API);
@@ -53,18 +73,51 @@ impl std::fmt::Debug for RTCDataChannelWrapper {
     }
 }
 
+//Stream id of the data channel every connection carries
+const MESSAGING_CHANNEL_ID: u16 = 0;
+
+//Tells a connection apart from a newer one with the same peer that replaced it
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
+
+//How far the remote handler got with the peer's first description
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DescriptionState {
+    Waiting,
+    Applied,
+    Failed(ConnectionFailure),
+}
+
 #[derive(Debug)]
 pub struct Connection {
+    id: u64,
     pub peer_connection: Arc<RTCPeerConnection>,
     pub conn_type: ConnType,
+    session_type: SessionType,
     candidates: Arc<Mutex<Vec<RTCIceCandidate>>>,
-    sdp_notify: Arc<Notify>,
+    description: watch::Sender<DescriptionState>,
+    negotiation: Arc<Mutex<Negotiation>>,
     signaler: Arc<SessionExchange>,
+    //The offer and candidates an answerer received while finding out who the peer is
+    pending_sessions: Option<mpsc::UnboundedReceiver<Session>>,
     task_handles: Vec<JoinHandle<()>>,
     data_channel: Option<RTCDataChannelWrapper>,
     remote_node_id: Arc<Mutex<Option<NodeId>>>,
     data_channel_notify: Arc<Notify>,
+    //Last message sent or received over the data channel
+    last_activity: Arc<Mutex<Instant>>,
     audio_track: Option<Arc<TrackLocalStaticSample>>,
+    audio_stream: Option<AudioStream>,
+    remote_audio: Arc<Mutex<Vec<RemoteAudio>>>,
+    audio: Option<AudioContext>,
+    volume: Arc<Volume>,
+    //Updated from the peer's RTCP feedback, survives capture restarts
+    encoder_settings: Option<watch::Receiver<EncoderSettings>>,
+    //Not part of webrtc's stats yet, so it is taken from the peer's receiver reports
+    reported_jitter: Arc<Mutex<Option<u32>>>,
+    stats_sampler: Mutex<StatsSampler>,
+    video_track: Option<Arc<TrackLocalStaticSample>>,
+    video_stream: Option<VideoStream>,
+    remote_video: Arc<Mutex<Vec<VideoStream>>>,
 }
 
 impl Connection {
@@ -72,6 +125,7 @@ impl Connection {
         api: &APIWrapper,
         config: RTCConfigurationWrapper,
         conn_type: ConnType,
+        session_type: SessionType,
         signaler: Arc<SessionExchange>,
     ) -> Self {
         let api = &api.0;
@@ -81,20 +135,49 @@ impl Connection {
             .await
             .expect("Failed to establish pc");
         Self {
+            id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
             peer_connection: Arc::new(peer_connection),
             conn_type,
+            session_type,
             candidates: Arc::new(Mutex::new(Vec::new())),
-            sdp_notify: Arc::new(Notify::new()),
+            description: watch::Sender::new(DescriptionState::Waiting),
+            negotiation: Arc::new(Mutex::new(Negotiation::new(false))),
             signaler,
+            pending_sessions: None,
             task_handles: Vec::new(),
             data_channel: None,
             remote_node_id: Arc::new(Mutex::new(None)),
             data_channel_notify: Arc::new(Notify::new()),
+            last_activity: Arc::new(Mutex::new(Instant::now())),
             audio_track: None,
+            audio_stream: None,
+            remote_audio: Arc::new(Mutex::new(Vec::new())),
+            audio: None,
+            volume: Arc::new(Volume::default()),
+            encoder_settings: None,
+            reported_jitter: Arc::new(Mutex::new(None)),
+            stats_sampler: Mutex::new(StatsSampler::default()),
+            video_track: None,
+            video_stream: None,
+            remote_video: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
-    pub async fn monitor_connection(&mut self) -> mpsc::Receiver<MessageType> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn session_type(&self) -> &SessionType {
+        &self.session_type
+    }
+
+    //Time since anything went over the data channel
+    pub async fn idle_for(&self) -> Duration {
+        self.last_activity.lock().await.elapsed()
+    }
+
+    //Waits for ICE to connect us, the receiver then keeps reporting the connection's state
+    pub async fn monitor_connection(&mut self) -> Result<mpsc::Receiver<MessageType>> {
         let (tx, mut rx) = mpsc::channel(1);
         let pc = Arc::clone(&self.peer_connection);
         pc.on_peer_connection_state_change(Box::new(move |s: RTCPeerConnectionState| {
@@ -129,46 +212,56 @@ impl Connection {
             })
         }));
 
-        //Busy wait until we are connected
-        while let Some(state) = rx.recv().await {
-            if state == MessageType::ConnectionState(RTCPeerConnectionState::Connected) {
-                break;
+        //Busy wait until we are connected, unless that happened before the handler was set
+        let connected = async {
+            if pc.connection_state() == RTCPeerConnectionState::Connected {
+                return true;
+            }
+            while let Some(state) = rx.recv().await {
+                match state {
+                    MessageType::ConnectionState(RTCPeerConnectionState::Connected) => return true,
+                    MessageType::ConnectionState(
+                        RTCPeerConnectionState::Failed | RTCPeerConnectionState::Closed,
+                    ) => return false,
+                    _ => {}
+                }
             }
+            false
+        };
+        match timeout(Duration::from_secs(ICE_CONNECT_TIMEOUT), connected).await {
+            Ok(true) => Ok(rx),
+            _ => Err(
+                HandshakeError::new(HandshakePhase::Connecting, ConnectionFailure::IceFailed)
+                    .into(),
+            ),
         }
-        rx
     }
 
     pub async fn init_ice_handler(&self) {
         let candidates = Arc::clone(&self.candidates);
         let pc = Arc::clone(&self.peer_connection);
         let signaler = Arc::clone(&self.signaler);
-        let pc2 = Arc::downgrade(&self.peer_connection);
         let remote_node_id = Arc::clone(&self.remote_node_id);
+        let session_type = self.session_type;
         pc.on_ice_candidate(Box::new(move |c: Option<RTCIceCandidate>| {
             let candidates = Arc::clone(&candidates);
-            let pc = pc2.clone();
             let signaler = Arc::clone(&signaler);
             let remote_node_id = Arc::clone(&remote_node_id);
+            let session_type = session_type;
 
             Box::pin(async move {
                 if let Some(candidate) = c {
                     let mut candidates = candidates.lock().await;
                     candidates.push(candidate.clone());
-                    let sdp = match pc.upgrade() {
-                        Some(pc) => pc.local_description().await,
-                        None => None,
-                    };
                     if let Some(remote_node_id) = remote_node_id.lock().await.as_ref() {
-                        //Send ice candidate and sdp to peer 2
-                        if sdp.is_some() {
-                            info!("Sending new sdp");
-                        }
+                        //Descriptions are sent on their own by the negotiation
                         match signaler
                             .send_session(
                                 *remote_node_id,
                                 Session {
+                                    session_type,
                                     ice_candidate: Some(candidate.clone()),
-                                    sdp,
+                                    sdp: None,
                                 },
                             )
                             .await
@@ -182,14 +275,20 @@ impl Connection {
         }));
     }
 
-    //NOT a gettter method. It uses the signlar to retreive the peers node_id
-    pub async fn retrieve_remote_node_id(&self) -> Result<()> {
-        let signaler = Arc::clone(&self.signaler);
-
-        //Retrieve remote node id so we can send back our ice candidatse + sdps
-        let (tx, mut rx) = mpsc::channel(1);
-        signaler.init_id_sender(tx.clone()).await;
-        let remote_node_id = rx.recv().await.expect("Failed to retreive remote node id");
+    //NOT a gettter method. It waits for the next peer to offer us this kind of session
+    pub async fn retrieve_remote_node_id(&mut self) -> Result<()> {
+        let (remote_node_id, sessions) = timeout(
+            Duration::from_secs(OFFER_TIMEOUT),
+            self.signaler.wait_for_offer(self.session_type),
+        )
+        .await
+        .map_err(|_| {
+            HandshakeError::new(
+                HandshakePhase::WaitingForOffer,
+                ConnectionFailure::PeerUnreachable,
+            )
+        })??;
+        self.pending_sessions = Some(sessions);
 
         let mut gaurd = self.remote_node_id.lock().await;
         *gaurd = Some(remote_node_id);
@@ -211,78 +310,126 @@ impl Connection {
         }
     }
 
-    //Initiates the webrtc handshake by using the signaler to send the remote peer our sdp
+    //Initiates the webrtc handshake by using the signaler to send the remote peer our sdp. Needs
+    //the remote handler running so an offer colliding with ours is resolved
     pub async fn offer(&self) -> Result<()> {
+        let remote_node_id = self.get_remote_node_id().await?;
+        make_offer(
+            &self.peer_connection,
+            &self.negotiation,
+            &self.signaler,
+            remote_node_id,
+            self.session_type,
+            None,
+        )
+        .await
+    }
+
+    //Offers the peer fresh ICE credentials so a failed connection can find a new path without
+    //starting over. Runs in the background since the offer may take a while to reach them
+    pub async fn restart_ice(&self) -> Result<()> {
+        let remote_node_id = self.get_remote_node_id().await?;
         let pc = Arc::clone(&self.peer_connection);
+        let negotiation = Arc::clone(&self.negotiation);
         let signaler = Arc::clone(&self.signaler);
-        let remote_node_id = Arc::clone(&self.remote_node_id);
-
-        let offer = pc.create_offer(None).await.expect("Error creating offer");
-
-        if let Err(e) = pc.set_local_description(offer).await {
-            panic!("Error setting local offer {}", e);
-        }
-
-        let offer = pc
-            .local_description()
-            .await
-            .context("Failed to retreive local sdp from offerer")?;
-
-        if let Some(remote_node_id) = remote_node_id.lock().await.as_ref() {
-            match timeout(Duration::from_secs(SEND_SESSION_TIMEOUT), async {
-                loop {
-                    let session = Session {
-                        sdp: Some(offer.clone()),
-                        ice_candidate: None,
-                    };
-                    match signaler.send_session(*remote_node_id, session).await {
-                        Ok(()) => return Ok(()),
-                        Err(e) => {
-                            error!("Error sending our answer, trying again... Err Msg: {}", e);
-                            sleep(Duration::from_secs(SEND_SESSION_DELAY)).await;
-                        }
-                    }
-                }
-            })
+        let session_type = self.session_type;
+        tokio::spawn(async move {
+            let options = RTCOfferOptions {
+                ice_restart: true,
+                ..Default::default()
+            };
+            if let Err(e) = make_offer(
+                &pc,
+                &negotiation,
+                &signaler,
+                remote_node_id,
+                session_type,
+                Some(options),
+            )
             .await
             {
-                Ok(result) => {
-                    info!("Successfuly sent our answer.");
-                    return result;
-                }
-                Err(e) => return Err(anyhow::anyhow!("Error sending our offer {}", e)),
-            };
-        }
-        Err(anyhow::anyhow!("Failed to send our sdp"))
+                error!("Error restarting ice {e}");
+            }
+        });
+        Ok(())
+    }
+
+    //Only the impolite peer restarts a failed connection, the polite one waits for its offer
+    pub async fn is_polite(&self) -> bool {
+        self.negotiation.lock().await.is_polite()
     }
 
-    //Initializes a listener that receives remote SDPs and ICE candidates
+    //Initializes a listener that receives the peer's SDPs and ICE candidates and answers their
+    //offers, whoever started the connection. Needs the peer's node id, so answerers retrieve it
+    //first
     pub async fn init_remote_handler(&mut self) -> Result<()> {
-        let signaler = Arc::clone(&self.signaler);
         let pc = Arc::clone(&self.peer_connection);
-        let notify = Arc::clone(&self.sdp_notify);
+        let description = self.description.clone();
+        let negotiation = Arc::clone(&self.negotiation);
+        let signaler = Arc::clone(&self.signaler);
+        let session_type = self.session_type;
+        let remote_node_id = self.get_remote_node_id().await?;
 
-        let (tx, mut rx) = mpsc::channel::<Session>(10);
-        signaler.init_session_sender(tx.clone()).await;
+        let polite = negotiation::is_polite(&signaler.node_id(), &remote_node_id);
+        negotiation.lock().await.set_polite(polite);
+
+        let mut rx = match self.pending_sessions.take() {
+            Some(rx) => rx,
+            None => self.signaler.register(remote_node_id, session_type).await,
+        };
+
+        self.init_negotiation_handler(remote_node_id);
 
         //Spawn a listener to retreive session from remote
         let handle = tokio::spawn(async move {
             info!("Listening for remote response");
-            //Continue listening for incoming sdps incase connection is reset
+            //Candidates can't be added until there is a remote description to add them to
+            let mut candidates = Vec::new();
+            //Continue listening for incoming sdps incase connection is renegotiated
             while let Some(session) = rx.recv().await {
                 info!("Recieved session from peer");
                 if let Some(sdp) = session.sdp {
-                    info!("Setting remote sdp");
-                    if let Err(e) = pc.set_remote_description(sdp).await {
-                        error!("Error setting sdp {e}");
-                    }
-                    notify.notify_waiters();
+                    let state = match apply_remote_description(&pc, &negotiation, sdp).await {
+                        Ok(Some(answer)) => {
+                            match send_description(&signaler, remote_node_id, session_type, answer)
+                                .await
+                            {
+                                Ok(()) => DescriptionState::Applied,
+                                Err(e) => {
+                                    error!("Error sending our answer {e}");
+                                    DescriptionState::Failed(ConnectionFailure::PeerUnreachable)
+                                }
+                            }
+                        }
+                        //Either the peer's answer or one we ignored
+                        Ok(None) if pc.remote_description().await.is_some() => {
+                            DescriptionState::Applied
+                        }
+                        Ok(None) => DescriptionState::Waiting,
+                        Err(e) => {
+                            error!("Error setting sdp {e}");
+                            DescriptionState::Failed(ConnectionFailure::Rejected)
+                        }
+                    };
+                    //Only the first outcome counts, later ones are renegotiations
+                    description.send_if_modified(|current| {
+                        let first = *current == DescriptionState::Waiting;
+                        if first {
+                            *current = state;
+                        }
+                        first
+                    });
                 }
                 if let Some(candidate) = session.ice_candidate {
-                    let c = candidate.to_string();
+                    candidates.push(candidate);
+                }
+                if pc.remote_description().await.is_none() {
+                    continue;
+                }
+                for candidate in candidates.drain(..) {
                     if let Err(e) = pc
                         .add_ice_candidate(RTCIceCandidateInit {
-                            candidate: c,
+                            candidate: candidate.to_string(),
                             ..Default::default()
                         })
                         .await
@@ -297,104 +444,112 @@ impl Connection {
         Ok(())
     }
 
-    pub async fn answer(&self) -> Result<()> {
-        let pc = Arc::clone(&self.peer_connection);
-        let remote_node_id = Arc::clone(&self.remote_node_id);
+    //Offers again whenever the connection changes after the first handshake, e.g. a track is added
+    fn init_negotiation_handler(&self, remote_node_id: NodeId) {
+        let pc = Arc::downgrade(&self.peer_connection);
+        let negotiation = Arc::clone(&self.negotiation);
         let signaler = Arc::clone(&self.signaler);
-        let notify = Arc::clone(&self.sdp_notify);
-
-        //Ensure that we recieved remote sdp before creating a response
-        notify.notified().await;
-
-        let answer: RTCSessionDescription = pc
-            .create_answer(None)
-            .await
-            .expect("Failed to create answer");
-
-        if let Err(e) = pc.set_local_description(answer.clone()).await {
-            panic!("Error setting local desc: {}", e);
-        }
+        let session_type = self.session_type;
+        self.peer_connection
+            .on_negotiation_needed(Box::new(move || {
+                let pc = pc.clone();
+                let negotiation = Arc::clone(&negotiation);
+                let signaler = Arc::clone(&signaler);
+                let session_type = session_type;
+                Box::pin(async move {
+                    let Some(pc) = pc.upgrade() else {
+                        return;
+                    };
+                    //The first offer is sent by whoever connects
+                    if pc.remote_description().await.is_none() {
+                        return;
+                    }
+                    info!("Renegotiating connection");
+                    if let Err(e) = make_offer(
+                        &pc,
+                        &negotiation,
+                        &signaler,
+                        remote_node_id,
+                        session_type,
+                        None,
+                    )
+                    .await
+                    {
+                        error!("Error renegotiating {e}");
+                    }
+                })
+            }));
+    }
 
-        if let Some(remote_node_id) = remote_node_id.lock().await.as_ref() {
-            let session = Session {
-                sdp: Some(answer),
-                ice_candidate: None,
-            };
-            signaler.send_session(*remote_node_id, session).await?;
+    //The remote handler does the answering, this waits until it has applied the peer's offer or
+    //answer to ours
+    pub async fn wait_for_description(&self) -> Result<()> {
+        let mut description = self.description.subscribe();
+        let state = timeout(
+            Duration::from_secs(DESCRIPTION_TIMEOUT),
+            description.wait_for(|state| *state != DescriptionState::Waiting),
+        )
+        .await
+        .map(|state| state.map(|state| *state));
+        match state {
+            Ok(Ok(DescriptionState::Applied)) => Ok(()),
+            Ok(Ok(DescriptionState::Failed(reason))) => {
+                Err(HandshakeError::new(HandshakePhase::WaitingForDescription, reason).into())
+            }
+            _ => Err(HandshakeError::new(
+                HandshakePhase::WaitingForDescription,
+                ConnectionFailure::PeerUnreachable,
+            )
+            .into()),
         }
-        Ok(())
     }
 
+    //Both peers create the same pre-negotiated channel, so it opens no matter who ends up offering
     pub async fn create_data_channel(&mut self) -> mpsc::Receiver<MessageType> {
         let pc = Arc::clone(&self.peer_connection);
         let data_channel = pc
-            .create_data_channel("messaging", None)
+            .create_data_channel(
+                "messaging",
+                Some(RTCDataChannelInit {
+                    negotiated: Some(MESSAGING_CHANNEL_ID),
+                    ..Default::default()
+                }),
+            )
             .await
             .expect("Error creating data channel");
 
         let dc = Arc::clone(&data_channel);
         let notify = Arc::clone(&self.data_channel_notify);
         data_channel.on_open(Box::new(move || {
-            notify.notify_waiters();
+            notify.notify_one();
             info!("Data channel {} {} is now open", dc.label(), dc.id());
             Box::pin(async move {})
         }));
 
         let d_label = data_channel.label().to_owned();
         let (tx, rx) = mpsc::channel(1);
+        let last_activity = Arc::clone(&self.last_activity);
         data_channel.on_message(Box::new(move |msg: DataChannelMessage| {
-            let message = String::from_utf8(msg.data.to_vec()).expect("Error parsing message");
-            info!("Message from peer, {}: {}", d_label, message);
             let tx = tx.clone();
+            let last_activity = Arc::clone(&last_activity);
+            let message = match parse_dc_message(msg) {
+                Ok(message) => message,
+                Err(e) => {
+                    error!("Error parsing message from peer, {}: {}", d_label, e);
+                    return Box::pin(async {});
+                }
+            };
+            info!("Message from peer, {}: {:?}", d_label, message);
             Box::pin(async move {
-                let text_message = TextMessage {
-                    timestamp: Utc::now(),
-                    content: message,
-                };
-                let _ = tx.send(MessageType::Message(text_message)).await;
+                *last_activity.lock().await = Instant::now();
+                let _ = tx.send(message).await;
             })
         }));
         self.data_channel = Some(RTCDataChannelWrapper(Arc::clone(&data_channel)));
         rx
     }
 
-    pub async fn register_data_channel(&self) -> mpsc::Receiver<MessageType> {
-        let pc = Arc::clone(&self.peer_connection);
-        let notify = Arc::clone(&self.data_channel_notify);
-
-        let (tx, rx) = mpsc::channel(1);
-        pc.on_data_channel(Box::new(move |d: Arc<RTCDataChannel>| {
-            notify.notify_waiters();
-            info!("Data channel established from answerer");
-            let d2 = Arc::clone(&d);
-            let tx = tx.clone();
-            Box::pin(async move {
-                d.on_open(Box::new(move || {
-                    info!("Data channel {} {} is now open", d2.label(), d2.id());
-                    Box::pin(async move {})
-                }));
-
-                d.on_message(Box::new(move |msg: DataChannelMessage| {
-                    let tx = tx.clone();
-                    let message =
-                        String::from_utf8(msg.data.to_vec()).expect("Error parsing message");
-                    info!("Message from peer: {}", message);
-
-                    let text_message = TextMessage {
-                        timestamp: chrono::Utc::now(),
-                        content: message,
-                    };
-
-                    Box::pin(async move {
-                        let _ = tx.send(MessageType::Message(text_message));
-                    })
-                }));
-            })
-        }));
-        rx
-    }
-
-    pub async fn init_audio_stream(&mut self) -> Result<()> {
+    pub async fn init_audio_stream(&mut self, audio: AudioContext) -> Result<()> {
         let pc = Arc::clone(&self.peer_connection);
         let audio_track = Arc::new(TrackLocalStaticSample::new(
             RTCRtpCodecCapability {
@@ -413,20 +568,211 @@ impl Connection {
         // Read incoming RTCP packets
         // Before these packets are returned they are processed by interceptors. For things
         // like NACK this needs to be called.
-        tokio::spawn(async move {
+        //Receiver reports and REMB from the peer drive the encoder's bitrate and FEC
+        let (settings_tx, settings_rx) = watch::channel(EncoderSettings::default());
+        let reported_jitter = Arc::clone(&self.reported_jitter);
+        let handle = tokio::spawn(async move {
             let mut rtcp_buf = vec![0u8; 1500];
-            while let Ok((_, _)) = rtp_sender.read(&mut rtcp_buf).await {}
-            Result::<()>::Ok(())
+            let mut controller = BitrateController::default();
+            while let Ok((packets, _)) = rtp_sender.read(&mut rtcp_buf).await {
+                if let Some(jitter) = stats::reported_jitter(&packets) {
+                    *reported_jitter.lock().await = Some(jitter);
+                }
+                if controller.on_rtcp(&packets) {
+                    let settings = controller.settings();
+                    settings_tx.send_if_modified(|current| {
+                        let modified = *current != settings;
+                        *current = settings;
+                        modified
+                    });
+                }
+            }
         });
+        self.task_handles.push(handle);
+
+        //Start feeding opus frames from the backend's input into the track
+        let audio_stream =
+            audio::start_capture(&audio, Arc::clone(&audio_track), settings_rx.clone()).await?;
+        self.audio_stream = Some(audio_stream);
+        self.encoder_settings = Some(settings_rx);
         self.audio_track = Some(audio_track);
+        self.audio = Some(audio);
 
         Ok(())
     }
 
-    //Helper function to allow client to sleep until data channel is opened
-    pub async fn wait_for_data_channel(&self) {
-        let notify = Arc::clone(&self.data_channel_notify);
-        notify.notified().await;
+    //Sends video from the context's selected source. Like audio, this has to happen before the
+    //offer or answer so the track is part of our sdp
+    pub async fn init_video_stream(&mut self, video: &VideoContext) -> Result<()> {
+        let source = video.open_source()?;
+        let video_track = Arc::new(TrackLocalStaticSample::new(
+            RTCRtpCodecCapability {
+                mime_type: source.codec().mime_type().to_owned(),
+                ..Default::default()
+            },
+            "video".to_owned(),
+            "webrtc-rs".to_owned(),
+        ));
+        let rtp_sender = self
+            .peer_connection
+            .add_track(Arc::clone(&video_track) as Arc<dyn TrackLocal + Send + Sync>)
+            .await?;
+        self.video_stream = Some(video::start_sending(
+            source,
+            Arc::clone(&video_track),
+            rtp_sender,
+        ));
+        self.video_track = Some(video_track);
+        Ok(())
+    }
+
+    //Sends a Go-Live feed to the viewer on the other end. The track is shared with every other
+    //viewer and keeps running when this connection closes
+    pub async fn add_live_feed(&mut self, feed: &LiveFeed) -> Result<()> {
+        let rtp_sender = self
+            .peer_connection
+            .add_track(feed.track() as Arc<dyn TrackLocal + Send + Sync>)
+            .await?;
+        self.video_stream = Some(feed.watch_viewer(rtp_sender));
+        Ok(())
+    }
+
+    //Plays back any audio tracks the peer adds to the connection and passes on their video
+    pub async fn init_track_handler(&self, audio: AudioContext, video: VideoContext) {
+        let pc = Arc::clone(&self.peer_connection);
+        let weak_pc = Arc::downgrade(&self.peer_connection);
+        let remote_audio = Arc::clone(&self.remote_audio);
+        let remote_video = Arc::clone(&self.remote_video);
+        let remote_node_id = Arc::clone(&self.remote_node_id);
+        let volume = Arc::clone(&self.volume);
+        pc.on_track(Box::new(
+            move |track: Arc<TrackRemote>, _: Arc<RTCRtpReceiver>, _: Arc<RTCRtpTransceiver>| {
+                let pc = weak_pc.clone();
+                let remote_audio = Arc::clone(&remote_audio);
+                let remote_video = Arc::clone(&remote_video);
+                let remote_node_id = Arc::clone(&remote_node_id);
+                let volume = Arc::clone(&volume);
+                let audio = audio.clone();
+                let video = video.clone();
+                Box::pin(async move {
+                    info!(
+                        "Received remote {} track {} ({})",
+                        track.kind(),
+                        track.id(),
+                        track.codec().capability.mime_type
+                    );
+                    //Needed to attribute speaking events, always known once media is flowing
+                    let node_id = match *remote_node_id.lock().await {
+                        Some(node_id) => node_id,
+                        None => {
+                            error!("Received a track before the peer's node id");
+                            return;
+                        }
+                    };
+                    match track.kind() {
+                        RTPCodecType::Audio => {
+                            match audio::start_playback(&audio, track, node_id, volume.get()).await
+                            {
+                                Ok(playback) => remote_audio.lock().await.push(playback),
+                                Err(e) => error!("Error starting audio playback: {}", e),
+                            }
+                        }
+                        RTPCodecType::Video => {
+                            let media_ssrc = track.ssrc();
+                            match video::start_receiving(
+                                &video,
+                                Arc::clone(&audio.recording),
+                                track,
+                                node_id,
+                            ) {
+                                Ok(video_stream) => remote_video.lock().await.push(video_stream),
+                                Err(e) => {
+                                    error!("Error receiving video: {}", e);
+                                    return;
+                                }
+                            }
+                            //Ask for a keyframe straight away instead of waiting for the next one
+                            if let Some(pc) = pc.upgrade() {
+                                let pli = PictureLossIndication {
+                                    sender_ssrc: 0,
+                                    media_ssrc,
+                                };
+                                if let Err(e) = pc.write_rtcp(&[Box::new(pli)]).await {
+                                    error!("Error requesting keyframe: {}", e);
+                                }
+                            }
+                        }
+                        _ => info!("Ignoring remote {} track", track.kind()),
+                    }
+                })
+            },
+        ));
+    }
+
+    //Restarts capture on the backend's currently selected input device
+    pub async fn restart_capture(&mut self) -> Result<()> {
+        if let (Some(audio), Some(audio_track), Some(encoder_settings)) =
+            (&self.audio, &self.audio_track, &self.encoder_settings)
+        {
+            if let Some(mut audio_stream) = self.audio_stream.take() {
+                audio_stream.stop();
+            }
+            let audio_stream =
+                audio::start_capture(audio, Arc::clone(audio_track), encoder_settings.clone())
+                    .await?;
+            self.audio_stream = Some(audio_stream);
+        }
+        Ok(())
+    }
+
+    //Gain applied to everything the peer sends us, 1.0 is unchanged
+    pub async fn set_volume(&self, volume: f32) {
+        self.volume.set(volume);
+        for playback in self.remote_audio.lock().await.iter() {
+            playback.set_volume(volume);
+        }
+    }
+
+    pub fn has_audio(&self) -> bool {
+        self.audio_track.is_some()
+    }
+
+    pub fn has_video(&self) -> bool {
+        self.video_track.is_some()
+    }
+
+    pub async fn get_connection_stats(&self) -> Result<ConnectionStats> {
+        let node_id = self.get_remote_node_id().await?;
+        let report = self.peer_connection.get_stats().await;
+        let jitter = *self.reported_jitter.lock().await;
+        let stats = self.stats_sampler.lock().await.summarize(
+            node_id,
+            &report,
+            jitter,
+            std::time::Instant::now(),
+        );
+        Ok(stats)
+    }
+
+    //Helper function to allow client to sleep until data channel is opened. It may have opened
+    //before anyone was waiting
+    pub async fn wait_for_data_channel(&self) -> Result<()> {
+        let Some(data_channel) = &self.data_channel else {
+            return Ok(());
+        };
+        let opened = self.data_channel_notify.notified();
+        if data_channel.0.ready_state() == RTCDataChannelState::Open {
+            return Ok(());
+        }
+        timeout(Duration::from_secs(DATA_CHANNEL_TIMEOUT), opened)
+            .await
+            .map_err(|_| {
+                HandshakeError::new(
+                    HandshakePhase::OpeningDataChannel,
+                    ConnectionFailure::IceFailed,
+                )
+            })?;
+        Ok(())
     }
 
     pub async fn add_ice_candidate(&mut self, candidate: RTCIceCandidate) {
@@ -435,13 +781,32 @@ impl Connection {
         c.push(candidate);
     }
 
-    pub async fn close_connection(&self) -> Result<()> {
+    pub async fn close_connection(&mut self) -> Result<()> {
         let pc = Arc::clone(&self.peer_connection);
+        if let Some(mut audio_stream) = self.audio_stream.take() {
+            audio_stream.stop();
+        }
+        for mut playback in self.remote_audio.lock().await.drain(..) {
+            playback.stop();
+        }
+        if let Some(mut video_stream) = self.video_stream.take() {
+            video_stream.stop();
+        }
+        for mut video_stream in self.remote_video.lock().await.drain(..) {
+            video_stream.stop();
+        }
+        if let Some(audio) = &self.audio {
+            audio.playback.stop_if_idle().await;
+        }
         if let Some(data_channel) = &self.data_channel {
             let dc = Arc::clone(&data_channel.0);
             let _ = dc.close().await;
         }
-        pc.close().await.expect("Error closing peer connection");
+        //Also drops our route in the session exchange
+        for handle in self.task_handles.drain(..) {
+            handle.abort();
+        }
+        pc.close().await?;
         Ok(())
     }
 
@@ -454,9 +819,128 @@ impl Connection {
         if let Some(dc) = &self.data_channel {
             let data_channel = dc.0.clone();
             data_channel.send_text(message).await?;
+            *self.last_activity.lock().await = Instant::now();
         } else {
             error!("Data channel has not been set");
         }
         Ok(())
     }
+
+    //Voice messages go out as binary so they can't be mistaken for text
+    pub async fn send_voice_message(&self, voice_message: &VoiceMessage) -> Result<()> {
+        let data_channel = match &self.data_channel {
+            Some(dc) => dc.0.clone(),
+            None => return Err(anyhow!("Data channel has not been set")),
+        };
+        let data = serde_json::to_vec(voice_message)?;
+        data_channel.send(&data.into()).await?;
+        *self.last_activity.lock().await = Instant::now();
+        Ok(())
+    }
+}
+
+//Sends an offer once it is our local description. Holds the negotiation so a remote offer can't
+//slip in between
+async fn make_offer(
+    pc: &RTCPeerConnection,
+    negotiation: &Mutex<Negotiation>,
+    signaler: &SessionExchange,
+    remote_node_id: NodeId,
+    session_type: SessionType,
+    options: Option<RTCOfferOptions>,
+) -> Result<()> {
+    let offer = {
+        let _negotiation = negotiation.lock().await;
+        let offer = pc.create_offer(options).await?;
+        pc.set_local_description(offer).await?;
+        pc.local_description()
+            .await
+            .context("Failed to retreive local sdp from offerer")?
+    };
+    send_description(signaler, remote_node_id, session_type, offer).await
+}
+
+//Returns the answer to send back if the peer made us an offer
+async fn apply_remote_description(
+    pc: &RTCPeerConnection,
+    negotiation: &Mutex<Negotiation>,
+    sdp: RTCSessionDescription,
+) -> Result<Option<RTCSessionDescription>> {
+    let negotiation = negotiation.lock().await;
+    let is_offer = sdp.sdp_type == RTCSdpType::Offer;
+    match negotiation.on_remote_description(is_offer, pc.signaling_state()) {
+        RemoteDescriptionAction::Ignore => {
+            info!("Ignoring sdp from peer in {} state", pc.signaling_state());
+            return Ok(None);
+        }
+        RemoteDescriptionAction::Rollback => {
+            info!("Offers collided, rolling back ours");
+            //webrtc-rs wants a parseable sdp even for a rollback
+            let mut rollback = pc
+                .pending_local_description()
+                .await
+                .context("No local offer to roll back")?;
+            rollback.sdp_type = RTCSdpType::Rollback;
+            pc.set_local_description(rollback).await?;
+        }
+        RemoteDescriptionAction::Apply => {}
+    }
+    info!("Setting remote sdp");
+    pc.set_remote_description(sdp).await?;
+    if !is_offer {
+        return Ok(None);
+    }
+    let answer = pc.create_answer(None).await?;
+    pc.set_local_description(answer).await?;
+    Ok(pc.local_description().await)
+}
+
+//Keeps trying since the peer may not be listening for sessions yet
+async fn send_description(
+    signaler: &SessionExchange,
+    remote_node_id: NodeId,
+    session_type: SessionType,
+    sdp: RTCSessionDescription,
+) -> Result<()> {
+    match timeout(Duration::from_secs(SEND_SESSION_TIMEOUT), async {
+        loop {
+            let session = Session {
+                session_type,
+                sdp: Some(sdp.clone()),
+                ice_candidate: None,
+            };
+            match signaler.send_session(remote_node_id, session).await {
+                Ok(()) => return,
+                Err(e) => {
+                    error!("Error sending our sdp, trying again... Err Msg: {}", e);
+                    sleep(Duration::from_secs(SEND_SESSION_DELAY)).await;
+                }
+            }
+        }
+    })
+    .await
+    {
+        Ok(()) => {
+            info!("Successfuly sent our sdp.");
+            Ok(())
+        }
+        Err(_) => Err(HandshakeError::new(
+            HandshakePhase::SendingDescription,
+            ConnectionFailure::PeerUnreachable,
+        )
+        .into()),
+    }
+}
+
+//Text arrives as a string message, anything binary is a voice message
+fn parse_dc_message(msg: DataChannelMessage) -> Result<MessageType> {
+    if msg.is_string {
+        let content = String::from_utf8(msg.data.to_vec())?;
+        return Ok(MessageType::Message(TextMessage {
+            timestamp: Utc::now(),
+            content,
+        }));
+    }
+    let voice_message: VoiceMessage = serde_json::from_slice(&msg.data)?;
+    Ok(MessageType::VoiceMessage(voice_message))
 }
@@ -3,33 +3,169 @@ use iroh::net::endpoint::get_remote_node_id;
 use iroh::net::Endpoint;
 use iroh::node::ProtocolHandler;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::{mpsc, oneshot, Mutex};
-use tracing::{debug, error, info};
+use tracing::{error, info};
 use webrtc::ice_transport::ice_candidate::RTCIceCandidate;
-use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::sdp::{
+    sdp_type::RTCSdpType, session_description::RTCSessionDescription,
+};
 
-use crate::utils::enums::{MessageType, RunMessage, SessionType, SignalMessage, UserStatus};
-use crate::utils::types::NodeId;
+use crate::utils::enums::{
+    CallSignal, RunMessage, SessionType, SignalMessage, UserStatus, VoiceChannelSignal,
+};
+use crate::utils::types::{NodeId, VoiceState};
 use crate::utils::{
-    constants::{SDP_ALPN, SIGNAL_ALPN},
+    constants::{
+        SDP_ALPN, SESSION_MAX_SIZE, SIGNAL_ALPN, SIGNAL_MESSAGE_MAX_SIZE, UNCLAIMED_SESSION_TIMEOUT,
+    },
     types::BoxedFuture,
 };
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Session {
+    //Which of our connections with the peer this is for, e.g. a call and a stream can be
+    //negotiating at the same time
+    pub session_type: SessionType,
     pub ice_candidate: Option<RTCIceCandidate>,
     pub sdp: Option<RTCSessionDescription>,
 }
 
+impl Session {
+    pub fn is_offer(&self) -> bool {
+        self.sdp
+            .as_ref()
+            .is_some_and(|sdp| sdp.sdp_type == RTCSdpType::Offer)
+    }
+}
+
+type RouteKey = (NodeId, SessionType);
+
+//Someone offering to a peer that isn't waiting for them yet
+pub type PendingSessions = (NodeId, mpsc::UnboundedReceiver<Session>);
+
+//Hands every incoming session to the negotiation with the peer that sent it, so any number of
+//connections can be set up at once
+#[derive(Debug, Default)]
+pub struct SessionRouter {
+    routes: HashMap<RouteKey, mpsc::UnboundedSender<Session>>,
+    //Sessions nobody has registered for yet along with when the last one arrived
+    unclaimed: HashMap<RouteKey, (Instant, Vec<Session>)>,
+    //Answerers that take whichever peer offers them their kind of session next
+    waiting: VecDeque<(SessionType, oneshot::Sender<PendingSessions>)>,
+}
+
+impl SessionRouter {
+    //Anything the peer already sent comes through first. Replaces an earlier negotiation with the
+    //peer for the same kind of session
+    pub fn register(
+        &mut self,
+        node_id: NodeId,
+        session_type: SessionType,
+    ) -> mpsc::UnboundedReceiver<Session> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let key = (node_id, session_type);
+        if let Some((_, sessions)) = self.unclaimed.remove(&key) {
+            for session in sessions {
+                let _ = tx.send(session);
+            }
+        }
+        self.routes.insert(key, tx);
+        rx
+    }
+
+    pub fn route(&mut self, node_id: NodeId, session: Session) {
+        self.forget_stale();
+        let key = (node_id, session.session_type);
+        let session = match self.routes.get(&key) {
+            Some(tx) => match tx.send(session) {
+                Ok(()) => return,
+                //The connection it was for has closed
+                Err(mpsc::error::SendError(session)) => {
+                    self.routes.remove(&key);
+                    session
+                }
+            },
+            None => session,
+        };
+        let (received, sessions) = self
+            .unclaimed
+            .entry(key)
+            .or_insert_with(|| (Instant::now(), Vec::new()));
+        *received = Instant::now();
+        sessions.push(session);
+        self.hand_off(key);
+    }
+
+    //For answerers that can't know who will connect. Resolves once a peer sends an offer for the
+    //session type
+    pub fn wait_for_offer(
+        &mut self,
+        session_type: SessionType,
+    ) -> oneshot::Receiver<PendingSessions> {
+        self.forget_stale();
+        let (tx, rx) = oneshot::channel();
+        self.waiting.push_back((session_type, tx));
+        //Someone may have offered before we were ready
+        let offered = self
+            .unclaimed
+            .iter()
+            .find(|((_, unclaimed_type), (_, sessions))| {
+                *unclaimed_type == session_type && sessions.iter().any(Session::is_offer)
+            })
+            .map(|(key, _)| *key);
+        if let Some(key) = offered {
+            self.hand_off(key);
+        }
+        rx
+    }
+
+    //Registers the peer for the first answerer waiting on their kind of session. Candidates alone
+    //don't count, they could be left over from a connection that already closed
+    fn hand_off(&mut self, key: RouteKey) {
+        let offered = self
+            .unclaimed
+            .get(&key)
+            .is_some_and(|(_, sessions)| sessions.iter().any(Session::is_offer));
+        if !offered {
+            return;
+        }
+        while let Some(position) = self
+            .waiting
+            .iter()
+            .position(|(session_type, _)| *session_type == key.1)
+        {
+            let Some((_, waiter)) = self.waiting.remove(position) else {
+                return;
+            };
+            let sessions = self.register(key.0, key.1);
+            match waiter.send((key.0, sessions)) {
+                Ok(()) => return,
+                //They stopped waiting, keep the sessions for the next one
+                Err((_, mut sessions)) => {
+                    self.routes.remove(&key);
+                    let sessions = std::iter::from_fn(|| sessions.try_recv().ok()).collect();
+                    self.unclaimed.insert(key, (Instant::now(), sessions));
+                }
+            }
+        }
+    }
+
+    fn forget_stale(&mut self) {
+        let timeout = Duration::from_secs(UNCLAIMED_SESSION_TIMEOUT);
+        self.unclaimed
+            .retain(|_, (received, _)| received.elapsed() < timeout);
+        self.waiting.retain(|(_, waiter)| !waiter.is_closed());
+    }
+}
+
 //Used to send SDP and ICE candidates to peer
-//TODO: maybe change mpsc to a oneshot channel? May need to rework this design.
 #[derive(Debug)]
 pub struct SessionExchange {
     endpoint: Endpoint,
-    session_tx: Mutex<Option<mpsc::Sender<Session>>>,
-    node_id_tx: Mutex<Option<mpsc::Sender<NodeId>>>,
-    has_remote_id: Mutex<bool>,
+    router: Mutex<SessionRouter>,
 }
 
 impl ProtocolHandler for SessionExchange {
@@ -37,32 +173,20 @@ impl ProtocolHandler for SessionExchange {
         Box::pin(async move {
             //Open a connection to peer
             let connection = conn.await?;
+            let remote_node_id = get_remote_node_id(&connection)?;
 
             let (mut _send, mut recv) = connection.accept_bi().await?;
 
-            //Set remote node id
-            let mut has_remote_id = self.has_remote_id.lock().await;
-            if !*has_remote_id {
-                let remote_node_id = get_remote_node_id(&connection)?;
-                let tx = self.node_id_tx.lock().await;
-
-                if let Some(tx) = tx.as_ref() {
-                    tx.send(remote_node_id).await?;
-                }
-                *has_remote_id = true;
-            }
-
             //Read session info
-            match recv.read_to_end(2000).await {
+            match recv.read_to_end(SESSION_MAX_SIZE).await {
                 Ok(buf) => {
                     info!("Receieved data!");
                     match bincode::deserialize(&buf) {
                         Ok(remote_session) => {
-                            //Notify that sdp/ice candidate has been found to signal client struct
-                            let tx = self.session_tx.lock().await;
-                            if let Some(tx) = tx.as_ref() {
-                                tx.send(remote_session).await?;
-                            }
+                            self.router
+                                .lock()
+                                .await
+                                .route(remote_node_id, remote_session);
                         }
                         Err(e) => error!("Error deserializing session: {}", e),
                     }
@@ -79,22 +203,27 @@ impl SessionExchange {
     pub fn new(endpoint: Endpoint) -> Arc<Self> {
         Arc::new(Self {
             endpoint,
-            session_tx: Mutex::new(None),
-            node_id_tx: Mutex::new(None),
-            has_remote_id: Mutex::new(false),
+            router: Mutex::new(SessionRouter::default()),
         })
     }
 
-    //Used to set the channel to Some() value
-    pub async fn init_session_sender(&self, session_sender: mpsc::Sender<Session>) {
-        let mut tx = self.session_tx.lock().await;
-        *tx = Some(session_sender);
+    pub fn node_id(&self) -> NodeId {
+        self.endpoint.node_id()
     }
 
-    //Used to set the channel to Some() value
-    pub async fn init_id_sender(&self, id_sender: mpsc::Sender<NodeId>) {
-        let mut tx = self.node_id_tx.lock().await;
-        *tx = Some(id_sender);
+    //Sessions the peer sends for this kind of connection
+    pub async fn register(
+        &self,
+        node_id: NodeId,
+        session_type: SessionType,
+    ) -> mpsc::UnboundedReceiver<Session> {
+        self.router.lock().await.register(node_id, session_type)
+    }
+
+    //Waits for whoever offers us this kind of connection next
+    pub async fn wait_for_offer(&self, session_type: SessionType) -> Result<PendingSessions> {
+        let offer = self.router.lock().await.wait_for_offer(session_type);
+        Ok(offer.await?)
     }
 
     pub async fn send_session(&self, node_id: NodeId, session: Session) -> Result<()> {
@@ -116,8 +245,9 @@ impl ProtocolHandler for Signaler {
     fn accept(self: Arc<Self>, conn: iroh::net::endpoint::Connecting) -> BoxedFuture<Result<()>> {
         Box::pin(async move {
             let connection = conn.await?;
+            let remote_node_id = get_remote_node_id(&connection)?;
             let (_send, mut recv) = connection.accept_bi().await?;
-            let buf = recv.read_to_end(64).await?;
+            let buf = recv.read_to_end(SIGNAL_MESSAGE_MAX_SIZE).await?;
 
             let status = bincode::deserialize::<SignalMessage>(&buf)?;
 
@@ -134,6 +264,25 @@ impl ProtocolHandler for Signaler {
                             .send(RunMessage::UpdateStatus(node_id, user_status))
                             .await;
                     }
+                    SignalMessage::VoiceState(voice_state) => {
+                        let _ = sender
+                            .send(RunMessage::PeerVoiceState(remote_node_id, voice_state))
+                            .await;
+                    }
+                    SignalMessage::Call(call_signal) => {
+                        let _ = sender
+                            .send(RunMessage::CallSignal(remote_node_id, call_signal))
+                            .await;
+                    }
+                    SignalMessage::VoiceChannel(channel, channel_signal) => {
+                        let _ = sender
+                            .send(RunMessage::VoiceChannelSignal(
+                                remote_node_id,
+                                channel,
+                                channel_signal,
+                            ))
+                            .await;
+                    }
                 }
             }
 
@@ -178,8 +327,53 @@ impl Signaler {
             .await?;
         let (mut send, _recv) = conn.open_bi().await?;
         let buf = bincode::serialize(&SignalMessage::SendConnection(session_type))?;
-        send.write_all(&buf).await;
-        send.finish().await;
+        send.write_all(&buf).await?;
+        send.finish().await?;
+        Ok(())
+    }
+
+    pub async fn notify_voice_state(
+        &self,
+        remote_node_id: NodeId,
+        voice_state: VoiceState,
+    ) -> Result<()> {
+        let conn = &self
+            .endpoint
+            .connect_by_node_id(remote_node_id, SIGNAL_ALPN)
+            .await?;
+        let (mut send, _recv) = conn.open_bi().await?;
+        let buf = bincode::serialize(&SignalMessage::VoiceState(voice_state))?;
+        send.write_all(&buf).await?;
+        send.finish().await?;
+        Ok(())
+    }
+
+    pub async fn notify_call(&self, remote_node_id: NodeId, call_signal: CallSignal) -> Result<()> {
+        let conn = &self
+            .endpoint
+            .connect_by_node_id(remote_node_id, SIGNAL_ALPN)
+            .await?;
+        let (mut send, _recv) = conn.open_bi().await?;
+        let buf = bincode::serialize(&SignalMessage::Call(call_signal))?;
+        send.write_all(&buf).await?;
+        send.finish().await?;
+        Ok(())
+    }
+
+    pub async fn notify_voice_channel(
+        &self,
+        remote_node_id: NodeId,
+        channel: String,
+        channel_signal: VoiceChannelSignal,
+    ) -> Result<()> {
+        let conn = &self
+            .endpoint
+            .connect_by_node_id(remote_node_id, SIGNAL_ALPN)
+            .await?;
+        let (mut send, _recv) = conn.open_bi().await?;
+        let buf = bincode::serialize(&SignalMessage::VoiceChannel(channel, channel_signal))?;
+        send.write_all(&buf).await?;
+        send.finish().await?;
         Ok(())
     }
 
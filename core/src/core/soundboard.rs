@@ -0,0 +1,145 @@
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use audiopus::{coder::Decoder, packet::Packet, Channels, MutSignals, SampleRate};
+use tokio::sync::broadcast;
+use tracing::error;
+use webrtc::media::io::ogg_reader::{OggReader, COMMENT_PAGE_SIGNATURE};
+
+use crate::core::audio::{downmix, FRAME_SIZE};
+use crate::core::mixer::soft_clip;
+
+//Clips that can be waiting to start on a capture before the oldest are dropped
+const MAX_QUEUED_SOUNDS: usize = 16;
+//Opus packets can hold up to 120ms of audio per channel
+const MAX_DECODED_FRAME_SIZE: usize = FRAME_SIZE * 6 * 2;
+
+pub type Sound = Arc<Vec<f32>>;
+
+//Sends clips to every running capture so they are mixed into each outgoing audio track
+#[derive(Debug)]
+pub struct Soundboard {
+    sounds_tx: broadcast::Sender<Sound>,
+}
+
+impl Default for Soundboard {
+    fn default() -> Self {
+        let (sounds_tx, _) = broadcast::channel(MAX_QUEUED_SOUNDS);
+        Self { sounds_tx }
+    }
+}
+
+impl Soundboard {
+    //Returns false if there is no call to play the sound into
+    pub fn play(&self, sound: Vec<f32>) -> bool {
+        self.sounds_tx.send(Arc::new(sound)).is_ok()
+    }
+
+    pub fn subscribe(&self) -> SoundMixer {
+        SoundMixer {
+            sounds_rx: self.sounds_tx.subscribe(),
+            playing: Vec::new(),
+        }
+    }
+}
+
+//Held by a single capture, keeps track of how far into each clip it is
+#[derive(Debug)]
+pub struct SoundMixer {
+    sounds_rx: broadcast::Receiver<Sound>,
+    playing: Vec<(Sound, usize)>,
+}
+
+impl SoundMixer {
+    //Adds the next part of every playing clip on top of the microphone frame
+    pub fn mix_into(&mut self, frame: &mut [f32]) {
+        loop {
+            match self.sounds_rx.try_recv() {
+                Ok(sound) => self.playing.push((sound, 0)),
+                Err(broadcast::error::TryRecvError::Lagged(skipped)) => {
+                    error!("Soundboard skipped {} sounds", skipped);
+                }
+                Err(_) => break,
+            }
+        }
+        if self.playing.is_empty() {
+            return;
+        }
+
+        for (sound, position) in self.playing.iter_mut() {
+            let samples = &sound[*position..];
+            let len = samples.len().min(frame.len());
+            for (mixed, sample) in frame.iter_mut().zip(&samples[..len]) {
+                *mixed += sample;
+            }
+            *position += len;
+        }
+        self.playing
+            .retain(|(sound, position)| *position < sound.len());
+        frame
+            .iter_mut()
+            .for_each(|sample| *sample = soft_clip(*sample));
+    }
+
+    pub fn is_playing(&self) -> bool {
+        !self.playing.is_empty()
+    }
+}
+
+//Names of the .ogg clips in the soundboard directory, without the extension
+pub fn list_sounds(directory: &Path) -> Result<Vec<String>> {
+    if !directory.exists() {
+        return Ok(Vec::new());
+    }
+    let mut sounds: Vec<String> = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "ogg"))
+        .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+        .collect();
+    sounds.sort();
+    Ok(sounds)
+}
+
+pub fn load_sound(path: &Path) -> Result<Vec<f32>> {
+    decode_ogg(BufReader::new(File::open(path)?))
+}
+
+//Decodes an Ogg/Opus stream into 48kHz mono samples. The reader hands back whole pages, so each
+//page is expected to hold a single opus packet like the files our recorder writes
+pub fn decode_ogg<R: Read>(reader: R) -> Result<Vec<f32>> {
+    let (mut reader, header) = OggReader::new(reader, true)?;
+    let channels = match header.channels {
+        1 => Channels::Mono,
+        2 => Channels::Stereo,
+        channels => return Err(anyhow!("Unsupported channel count {}", channels)),
+    };
+    let mut decoder = Decoder::new(SampleRate::Hz48000, channels)?;
+
+    let mut samples = Vec::new();
+    let mut pcm = vec![0f32; MAX_DECODED_FRAME_SIZE];
+    let mut granule_position = 0;
+    while let Ok((payload, page_header)) = reader.parse_next_page() {
+        //A page that doesn't move the granule position on holds no new audio, e.g. the comment
+        //header or the end of stream page that repeats the last packet
+        if payload.is_empty()
+            || payload.starts_with(COMMENT_PAGE_SIGNATURE)
+            || page_header.granule_position == granule_position
+        {
+            continue;
+        }
+        granule_position = page_header.granule_position;
+        let packet = Packet::try_from(&payload[..])?;
+        let len = decoder.decode_float(Some(packet), MutSignals::try_from(&mut pcm[..])?, false)?;
+        samples.extend_from_slice(&pcm[..len * header.channels as usize]);
+    }
+
+    let mut samples = downmix(&samples, header.channels as u16);
+    //The encoder's priming samples at the start of the stream aren't part of the clip
+    let pre_skip = (header.pre_skip as usize).min(samples.len());
+    samples.drain(..pre_skip);
+    Ok(samples)
+}
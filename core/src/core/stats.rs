@@ -0,0 +1,155 @@
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use webrtc::ice::candidate::{CandidatePairState, CandidateType};
+use webrtc::rtcp::{packet::Packet, receiver_report::ReceiverReport, sender_report::SenderReport};
+use webrtc::stats::{ICECandidatePairStats, ICECandidateStats, StatsReport, StatsReportType};
+
+use crate::core::audio::SAMPLE_RATE;
+use crate::utils::types::NodeId;
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct CandidateInfo {
+    #[serde(rename = "ip")]
+    pub ip: String,
+    #[serde(rename = "port")]
+    pub port: u16,
+    #[serde(rename = "candidateType")]
+    pub candidate_type: String,
+}
+
+//Snapshot of a connection's quality. Loss and jitter are for our audio as the peer receives it
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ConnectionStats {
+    #[serde(rename = "nodeId")]
+    pub node_id: NodeId,
+    #[serde(rename = "roundTripTimeMs")]
+    pub round_trip_time_ms: Option<u32>,
+    #[serde(rename = "jitterMs")]
+    pub jitter_ms: Option<u32>,
+    #[serde(rename = "packetsLost")]
+    pub packets_lost: Option<i64>,
+    #[serde(rename = "packetLossPercent")]
+    pub packet_loss_percent: Option<u8>,
+    //Bits per second since the previous snapshot
+    #[serde(rename = "outgoingBitrate")]
+    pub outgoing_bitrate: Option<u64>,
+    #[serde(rename = "incomingBitrate")]
+    pub incoming_bitrate: Option<u64>,
+    #[serde(rename = "localCandidate")]
+    pub local_candidate: Option<CandidateInfo>,
+    #[serde(rename = "remoteCandidate")]
+    pub remote_candidate: Option<CandidateInfo>,
+    #[serde(rename = "relayed")]
+    pub relayed: bool,
+}
+
+//Remembers the byte counters from the last snapshot so bitrates can be worked out
+#[derive(Debug, Default)]
+pub struct StatsSampler {
+    last: Option<(Instant, u64, u64)>,
+}
+
+impl StatsSampler {
+    pub fn summarize(
+        &mut self,
+        node_id: NodeId,
+        report: &StatsReport,
+        jitter: Option<u32>,
+        now: Instant,
+    ) -> ConnectionStats {
+        let pair = selected_candidate_pair(report);
+        let local_candidate = pair.and_then(|pair| candidate(report, &pair.local_candidate_id));
+        let remote_candidate = pair.and_then(|pair| candidate(report, &pair.remote_candidate_id));
+        let relayed = [local_candidate, remote_candidate]
+            .iter()
+            .flatten()
+            .any(|candidate| candidate.candidate_type == CandidateType::Relay);
+
+        let remote_inbound = report.reports.values().find_map(|stats| match stats {
+            StatsReportType::RemoteInboundRTP(stats) => Some(stats),
+            _ => None,
+        });
+        let round_trip_time = remote_inbound
+            .and_then(|stats| stats.round_trip_time)
+            .or_else(|| pair.map(|pair| pair.current_round_trip_time))
+            .filter(|rtt| *rtt > 0.0);
+
+        let (outgoing_bitrate, incoming_bitrate) = match pair {
+            Some(pair) => {
+                let bitrates = self.last.and_then(|(last, sent, received)| {
+                    let elapsed = now.duration_since(last).as_secs_f64();
+                    if elapsed <= 0.0 {
+                        return None;
+                    }
+                    let bitrate = |bytes: u64, previous: u64| {
+                        (bytes.saturating_sub(previous) as f64 * 8.0 / elapsed) as u64
+                    };
+                    Some((
+                        bitrate(pair.bytes_sent, sent),
+                        bitrate(pair.bytes_received, received),
+                    ))
+                });
+                self.last = Some((now, pair.bytes_sent, pair.bytes_received));
+                (bitrates.map(|b| b.0), bitrates.map(|b| b.1))
+            }
+            None => (None, None),
+        };
+
+        ConnectionStats {
+            node_id,
+            round_trip_time_ms: round_trip_time.map(|rtt| (rtt * 1000.0).round() as u32),
+            jitter_ms: jitter.map(|jitter| jitter * 1000 / SAMPLE_RATE),
+            packets_lost: remote_inbound.map(|stats| stats.packets_lost),
+            packet_loss_percent: remote_inbound
+                .map(|stats| (stats.fraction_lost * 100.0).round().clamp(0.0, 100.0) as u8),
+            outgoing_bitrate,
+            incoming_bitrate,
+            local_candidate: local_candidate.map(candidate_info),
+            remote_candidate: remote_candidate.map(candidate_info),
+            relayed,
+        }
+    }
+}
+
+//Latest interarrival jitter the peer reported for our stream, in RTP timestamp units
+pub fn reported_jitter(packets: &[Box<dyn Packet + Send + Sync>]) -> Option<u32> {
+    packets.iter().rev().find_map(|packet| {
+        let packet = packet.as_any();
+        let reports = if let Some(report) = packet.downcast_ref::<ReceiverReport>() {
+            &report.reports
+        } else if let Some(report) = packet.downcast_ref::<SenderReport>() {
+            &report.reports
+        } else {
+            return None;
+        };
+        reports.last().map(|report| report.jitter)
+    })
+}
+
+//The nominated pair traffic is flowing over, falling back to the busiest pair that works
+fn selected_candidate_pair(report: &StatsReport) -> Option<&ICECandidatePairStats> {
+    let pairs = report.reports.values().filter_map(|stats| match stats {
+        StatsReportType::CandidatePair(pair) if pair.state == CandidatePairState::Succeeded => {
+            Some(pair)
+        }
+        _ => None,
+    });
+    pairs.max_by_key(|pair| (pair.nominated, pair.bytes_sent + pair.bytes_received))
+}
+
+fn candidate<'a>(report: &'a StatsReport, id: &str) -> Option<&'a ICECandidateStats> {
+    match report.reports.get(id)? {
+        StatsReportType::LocalCandidate(candidate)
+        | StatsReportType::RemoteCandidate(candidate) => Some(candidate),
+        _ => None,
+    }
+}
+
+fn candidate_info(candidate: &ICECandidateStats) -> CandidateInfo {
+    CandidateInfo {
+        ip: candidate.ip.clone(),
+        port: candidate.port,
+        candidate_type: candidate.candidate_type.to_string(),
+    }
+}
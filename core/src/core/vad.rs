@@ -0,0 +1,62 @@
+//Frames quieter than this (in dBFS) are considered silence
+pub const VAD_THRESHOLD_DB: f32 = -45.0;
+//Keep reporting speech for 300ms after the last loud frame so pauses between words don't flicker
+pub const VAD_HANGOVER_FRAMES: usize = 15;
+
+//Energy based voice activity detector working on 20ms frames
+#[derive(Debug)]
+pub struct VoiceActivityDetector {
+    threshold: f32,
+    hangover_frames: usize,
+    silent_frames: usize,
+    speaking: bool,
+}
+
+impl Default for VoiceActivityDetector {
+    fn default() -> Self {
+        Self::new(VAD_THRESHOLD_DB)
+    }
+}
+
+impl VoiceActivityDetector {
+    pub fn new(threshold_db: f32) -> Self {
+        Self {
+            threshold: 10f32.powf(threshold_db / 20.0),
+            hangover_frames: VAD_HANGOVER_FRAMES,
+            silent_frames: 0,
+            speaking: false,
+        }
+    }
+
+    pub fn is_speaking(&self) -> bool {
+        self.speaking
+    }
+
+    //Feeds the next frame through the detector. Returns the new state when speech starts or stops.
+    //An empty frame counts as silence, e.g. when nothing was received from the peer.
+    pub fn update(&mut self, frame: &[f32]) -> Option<bool> {
+        let was_speaking = self.speaking;
+        if rms(frame) >= self.threshold {
+            self.silent_frames = 0;
+            self.speaking = true;
+        } else if self.speaking {
+            self.silent_frames += 1;
+            if self.silent_frames > self.hangover_frames {
+                self.speaking = false;
+            }
+        }
+
+        if self.speaking != was_speaking {
+            Some(self.speaking)
+        } else {
+            None
+        }
+    }
+}
+
+pub fn rms(frame: &[f32]) -> f32 {
+    if frame.is_empty() {
+        return 0.0;
+    }
+    (frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt()
+}
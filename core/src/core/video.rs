@@ -0,0 +1,356 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep_until, Instant};
+use tracing::{error, info};
+use webrtc::{
+    media::{io::sample_builder::SampleBuilder, Sample},
+    rtcp::payload_feedbacks::{
+        full_intra_request::FullIntraRequest, picture_loss_indication::PictureLossIndication,
+    },
+    rtp::{
+        self,
+        codecs::{h264::H264Packet, vp8::Vp8Packet, vp9::Vp9Packet},
+        packetizer::Depacketizer,
+    },
+    rtp_transceiver::{rtp_codec::RTCRtpCodecCapability, rtp_sender::RTCRtpSender},
+    track::{
+        track_local::track_local_static_sample::TrackLocalStaticSample, track_remote::TrackRemote,
+    },
+};
+
+use crate::core::frame_source::{self, FrameSource, VideoFrame};
+use crate::core::recorder::{Recording, VideoTrackRecording};
+use crate::utils::enums::{VideoCodec, VideoSource};
+use crate::utils::types::NodeId;
+
+//Every video codec in WebRTC uses a 90kHz RTP clock
+pub const VIDEO_CLOCK_RATE: u32 = 90000;
+//Packets a frame can wait on before it's given up as lost
+const MAX_LATE_PACKETS: u16 = 256;
+//Received frames a slow consumer can fall behind on before the oldest are dropped
+const MAX_QUEUED_FRAMES: usize = 64;
+//Frames a source can read ahead of the one being sent
+const READ_AHEAD_FRAMES: usize = 2;
+
+//A frame as it arrived from a peer. H264 is Annex B
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceivedFrame {
+    pub node_id: NodeId,
+    pub codec: VideoCodec,
+    //RTP timestamp on the 90kHz clock
+    pub timestamp: u32,
+    pub data: Arc<Vec<u8>>,
+}
+
+//Everything a call needs to send and receive video, shared by all of the client's connections
+#[derive(Debug, Clone)]
+pub struct VideoContext {
+    pub source: Arc<Mutex<VideoSource>>,
+    pub frames_tx: broadcast::Sender<ReceivedFrame>,
+}
+
+impl Default for VideoContext {
+    fn default() -> Self {
+        let (frames_tx, _) = broadcast::channel(MAX_QUEUED_FRAMES);
+        Self {
+            source: Arc::new(Mutex::new(VideoSource::default())),
+            frames_tx,
+        }
+    }
+}
+
+impl VideoContext {
+    pub fn source(&self) -> VideoSource {
+        self.source
+            .lock()
+            .map(|source| source.clone())
+            .unwrap_or_default()
+    }
+
+    //Takes effect the next time a video track is started
+    pub fn set_source(&self, source: VideoSource) {
+        if let Ok(mut current) = self.source.lock() {
+            *current = source;
+        }
+    }
+
+    pub fn open_source(&self) -> Result<Box<dyn FrameSource>> {
+        frame_source::open_source(&self.source())
+    }
+
+    //Frames received from every peer
+    pub fn subscribe(&self) -> broadcast::Receiver<ReceivedFrame> {
+        self.frames_tx.subscribe()
+    }
+}
+
+//Handle to the tasks sending or receiving a video track. Dropping it stops them
+#[derive(Debug, Default)]
+pub struct VideoStream {
+    task_handles: Vec<JoinHandle<()>>,
+}
+
+impl VideoStream {
+    pub fn push_task(&mut self, handle: JoinHandle<()>) {
+        self.task_handles.push(handle);
+    }
+
+    pub fn stop(&mut self) {
+        for handle in self.task_handles.drain(..) {
+            handle.abort();
+        }
+    }
+}
+
+impl Drop for VideoStream {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+//Writes the source's frames to the track in real time and restarts the picture whenever the peer
+//reports it lost one
+pub fn start_sending(
+    source: Box<dyn FrameSource>,
+    track: Arc<TrackLocalStaticSample>,
+    rtp_sender: Arc<RTCRtpSender>,
+) -> VideoStream {
+    let keyframe_requested = Arc::new(AtomicBool::new(false));
+    let mut video_stream = VideoStream::default();
+    video_stream.push_task(watch_picture_loss(
+        rtp_sender,
+        Arc::clone(&keyframe_requested),
+    ));
+    video_stream.push_task(tokio::spawn(send_frames(source, track, keyframe_requested)));
+    video_stream
+}
+
+//Flags a keyframe request whenever the peer behind the sender reports a lost picture
+fn watch_picture_loss(
+    rtp_sender: Arc<RTCRtpSender>,
+    keyframe_requested: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut rtcp_buf = vec![0u8; 1500];
+        while let Ok((packets, _)) = rtp_sender.read(&mut rtcp_buf).await {
+            let lost_picture = packets.iter().any(|packet| {
+                let packet = packet.as_any();
+                packet.is::<PictureLossIndication>() || packet.is::<FullIntraRequest>()
+            });
+            if lost_picture {
+                keyframe_requested.store(true, Ordering::Relaxed);
+            }
+        }
+    })
+}
+
+//A Go-Live stream. One source is sent to any number of viewers by adding the same track to each of
+//their connections, so a viewer joining late picks the feed up where it is
+#[derive(Debug, Clone)]
+pub struct LiveFeed {
+    track: Arc<TrackLocalStaticSample>,
+    keyframe_requested: Arc<AtomicBool>,
+}
+
+impl LiveFeed {
+    //The feed runs until the returned stream is stopped, whether or not anyone is watching
+    pub fn start(source: Box<dyn FrameSource>) -> (Self, VideoStream) {
+        let track = Arc::new(TrackLocalStaticSample::new(
+            RTCRtpCodecCapability {
+                mime_type: source.codec().mime_type().to_owned(),
+                ..Default::default()
+            },
+            "live".to_owned(),
+            "webrtc-rs".to_owned(),
+        ));
+        let keyframe_requested = Arc::new(AtomicBool::new(false));
+        let mut video_stream = VideoStream::default();
+        video_stream.push_task(tokio::spawn(send_frames(
+            source,
+            Arc::clone(&track),
+            Arc::clone(&keyframe_requested),
+        )));
+        (
+            Self {
+                track,
+                keyframe_requested,
+            },
+            video_stream,
+        )
+    }
+
+    pub fn track(&self) -> Arc<TrackLocalStaticSample> {
+        Arc::clone(&self.track)
+    }
+
+    //New viewers can't decode anything until the next keyframe, so their picture loss reports
+    //restart the whole feed's picture
+    pub fn watch_viewer(&self, rtp_sender: Arc<RTCRtpSender>) -> VideoStream {
+        let mut video_stream = VideoStream::default();
+        video_stream.push_task(watch_picture_loss(
+            rtp_sender,
+            Arc::clone(&self.keyframe_requested),
+        ));
+        video_stream
+    }
+}
+
+async fn send_frames(
+    source: Box<dyn FrameSource>,
+    track: Arc<TrackLocalStaticSample>,
+    keyframe_requested: Arc<AtomicBool>,
+) {
+    info!("Sending {:?} video from {:?}", source.codec(), source);
+    //Sources read files or draw whole frames, so they run on the blocking pool and only the
+    //pacing happens here
+    let (frame_tx, mut frame_rx) = mpsc::channel(READ_AHEAD_FRAMES);
+    tokio::task::spawn_blocking(move || read_frames(source, frame_tx, keyframe_requested));
+
+    //Frames are scheduled from a fixed start so slow writes don't make the video drift
+    let mut next_frame_at = Instant::now();
+    while let Some(frame) = frame_rx.recv().await {
+        let sample = Sample {
+            data: frame.data.into(),
+            duration: frame.duration,
+            ..Default::default()
+        };
+        if let Err(e) = track.write_sample(&sample).await {
+            error!("Error sending video sample: {:?}", e);
+        }
+        next_frame_at += frame.duration;
+        sleep_until(next_frame_at).await;
+    }
+    info!("Video source finished");
+}
+
+//Runs until the source runs out or the sending task is gone
+fn read_frames(
+    mut source: Box<dyn FrameSource>,
+    frame_tx: mpsc::Sender<VideoFrame>,
+    keyframe_requested: Arc<AtomicBool>,
+) {
+    loop {
+        if keyframe_requested.swap(false, Ordering::Relaxed) {
+            source.request_keyframe();
+        }
+        let frame = match source.next_frame() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break,
+            Err(e) => {
+                error!("Error reading video frame: {}", e);
+                break;
+            }
+        };
+        if frame_tx.blocking_send(frame).is_err() {
+            break;
+        }
+    }
+}
+
+//Puts RTP packets back together into whole frames
+pub enum FrameAssembler {
+    Vp8(SampleBuilder<Vp8Packet>),
+    Vp9(SampleBuilder<Vp9Packet>),
+    H264(SampleBuilder<H264Packet>),
+}
+
+impl std::fmt::Debug for FrameAssembler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Frame assembler for {:?}", self.codec())
+    }
+}
+
+impl FrameAssembler {
+    pub fn new(codec: VideoCodec) -> Self {
+        match codec {
+            VideoCodec::Vp8 => FrameAssembler::Vp8(SampleBuilder::new(
+                MAX_LATE_PACKETS,
+                Vp8Packet::default(),
+                VIDEO_CLOCK_RATE,
+            )),
+            VideoCodec::Vp9 => FrameAssembler::Vp9(SampleBuilder::new(
+                MAX_LATE_PACKETS,
+                Vp9Packet::default(),
+                VIDEO_CLOCK_RATE,
+            )),
+            VideoCodec::H264 => FrameAssembler::H264(SampleBuilder::new(
+                MAX_LATE_PACKETS,
+                H264Packet::default(),
+                VIDEO_CLOCK_RATE,
+            )),
+        }
+    }
+
+    pub fn codec(&self) -> VideoCodec {
+        match self {
+            FrameAssembler::Vp8(_) => VideoCodec::Vp8,
+            FrameAssembler::Vp9(_) => VideoCodec::Vp9,
+            FrameAssembler::H264(_) => VideoCodec::H264,
+        }
+    }
+
+    //Returns every frame the packet completed along with its RTP timestamp
+    pub fn push(&mut self, packet: rtp::packet::Packet) -> Vec<(u32, Vec<u8>)> {
+        match self {
+            FrameAssembler::Vp8(builder) => drain_frames(builder, packet),
+            FrameAssembler::Vp9(builder) => drain_frames(builder, packet),
+            FrameAssembler::H264(builder) => drain_frames(builder, packet),
+        }
+    }
+}
+
+fn drain_frames<T: Depacketizer>(
+    builder: &mut SampleBuilder<T>,
+    packet: rtp::packet::Packet,
+) -> Vec<(u32, Vec<u8>)> {
+    builder.push(packet);
+    let mut frames = Vec::new();
+    while let Some((sample, timestamp)) = builder.pop_with_timestamp() {
+        frames.push((timestamp, sample.data.to_vec()));
+    }
+    frames
+}
+
+//Reassembles the peer's video track and hands every frame to the context's subscribers. The raw
+//packets are also written out while a call recording is running
+pub fn start_receiving(
+    video: &VideoContext,
+    recording: Arc<Recording>,
+    track: Arc<TrackRemote>,
+    node_id: NodeId,
+) -> Result<VideoStream> {
+    let mime_type = track.codec().capability.mime_type;
+    let codec = VideoCodec::from_mime_type(&mime_type)
+        .ok_or_else(|| anyhow::anyhow!("Unsupported video codec {}", mime_type))?;
+    let frames_tx = video.frames_tx.clone();
+    let handle = tokio::spawn(async move {
+        let mut assembler = FrameAssembler::new(codec);
+        let mut track_recording = VideoTrackRecording::new(node_id.fmt_short(), codec);
+        let mut received = 0u64;
+        while let Ok((packet, _)) = track.read_rtp().await {
+            track_recording.record(&recording, &packet);
+            for (timestamp, data) in assembler.push(packet) {
+                received += 1;
+                //Nobody watching is not an error
+                let _ = frames_tx.send(ReceivedFrame {
+                    node_id,
+                    codec,
+                    timestamp,
+                    data: Arc::new(data),
+                });
+            }
+        }
+        info!(
+            "Video from {} stopped after {} frames",
+            node_id.fmt_short(),
+            received
+        );
+    });
+    let mut video_stream = VideoStream::default();
+    video_stream.push_task(handle);
+    Ok(video_stream)
+}
@@ -0,0 +1,115 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use webrtc::media::io::ogg_reader::DEFAULT_PRE_SKIP;
+
+use crate::core::audio::{new_encoder, AudioStream, FRAME_SIZE, MAX_OPUS_PACKET_SIZE, SAMPLE_RATE};
+use crate::core::backend::{read_wav, AudioBackend};
+use crate::core::recorder::TrackRecorder;
+use crate::core::soundboard::load_sound;
+use crate::core::vad::rms;
+
+//Longest voice message in seconds, anything recorded past this is dropped
+pub const VOICE_MESSAGE_MAX_DURATION: u64 = 120;
+//Number of bars in the waveform shown before the message is played
+pub const WAVEFORM_BARS: usize = 64;
+
+const MAX_VOICE_MESSAGE_SAMPLES: usize = (SAMPLE_RATE as u64 * VOICE_MESSAGE_MAX_DURATION) as usize;
+
+//Collects audio from the input device until the user stops or cancels the voice message
+#[derive(Debug)]
+pub struct VoiceNoteRecorder {
+    audio_stream: AudioStream,
+    samples: Arc<Mutex<Vec<f32>>>,
+}
+
+impl VoiceNoteRecorder {
+    pub async fn start(backend: Arc<dyn AudioBackend>) -> Result<Self> {
+        let (mut audio_stream, mut frame_rx) = backend.start_input().await?;
+        let samples = Arc::new(Mutex::new(Vec::new()));
+        let collected = Arc::clone(&samples);
+        let handle = tokio::spawn(async move {
+            while let Some(frame) = frame_rx.recv().await {
+                let Ok(mut samples) = collected.lock() else {
+                    break;
+                };
+                if samples.len() >= MAX_VOICE_MESSAGE_SAMPLES {
+                    break;
+                }
+                samples.extend(frame);
+            }
+        });
+        audio_stream.push_task(handle);
+        Ok(Self {
+            audio_stream,
+            samples,
+        })
+    }
+
+    //Stops the input and hands back everything recorded so far
+    pub fn finish(mut self) -> Vec<f32> {
+        self.audio_stream.stop();
+        let mut samples = self
+            .samples
+            .lock()
+            .map(|mut samples| std::mem::take(&mut *samples))
+            .unwrap_or_default();
+        samples.truncate(MAX_VOICE_MESSAGE_SAMPLES);
+        samples
+    }
+}
+
+//Loads an existing WAV or Ogg/Opus file to send as a voice message
+pub fn import_samples(path: &Path) -> Result<Vec<f32>> {
+    let mut samples = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("wav") => read_wav(&path.to_path_buf())?,
+        Some("ogg") => load_sound(path)?,
+        _ => return Err(anyhow!("Unsupported voice message file {}", path.display())),
+    };
+    samples.truncate(MAX_VOICE_MESSAGE_SAMPLES);
+    Ok(samples)
+}
+
+//Encodes 48kHz mono samples into an Ogg/Opus file
+pub fn write_voice_message(samples: &[f32], path: &Path) -> Result<()> {
+    if samples.is_empty() {
+        return Err(anyhow!("Voice message is empty"));
+    }
+    let encoder = new_encoder()?;
+    let mut recorder = TrackRecorder::create(path)?;
+    let mut packet = vec![0u8; MAX_OPUS_PACKET_SIZE];
+
+    //The Ogg header tells players to drop this much from the start, so pad with silence
+    let mut pcm = vec![0f32; DEFAULT_PRE_SKIP as usize];
+    pcm.extend_from_slice(samples);
+    let padding = (FRAME_SIZE - pcm.len() % FRAME_SIZE) % FRAME_SIZE;
+    pcm.resize(pcm.len() + padding, 0.0);
+
+    for frame in pcm.chunks(FRAME_SIZE) {
+        let len = encoder.encode_float(frame, &mut packet)?;
+        recorder.write(&packet[..len])?;
+    }
+    recorder.close()
+}
+
+pub fn duration_ms(samples: &[f32]) -> u64 {
+    samples.len() as u64 * 1000 / SAMPLE_RATE as u64
+}
+
+//Loudness of each slice of the message scaled so the loudest bar is 255
+pub fn waveform(samples: &[f32]) -> Vec<u8> {
+    if samples.is_empty() {
+        return Vec::new();
+    }
+    let bar_size = samples.len().div_ceil(WAVEFORM_BARS);
+    let levels: Vec<f32> = samples.chunks(bar_size).map(rms).collect();
+    let peak = levels.iter().cloned().fold(0f32, f32::max);
+    if peak == 0.0 {
+        return vec![0; levels.len()];
+    }
+    levels
+        .iter()
+        .map(|level| (level / peak * 255.0).round() as u8)
+        .collect()
+}
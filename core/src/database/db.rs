@@ -1,9 +1,9 @@
-use crate::database::models::{Message, User};
+use crate::database::models::{FromRow, Message, User, VoiceMessageRecord};
 use crate::utils::enums::UserStatus;
 use crate::utils::types::NodeId;
 
 use anyhow::Result;
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OptionalExtension};
 use tracing::{error, info, warn};
 
 #[derive(Debug)]
@@ -83,6 +83,33 @@ impl Database {
         Ok(())
     }
 
+    pub fn write_voice_message(&self, voice_message: VoiceMessageRecord) -> Result<()> {
+        let conn = &self.conn;
+        conn.execute(
+            "insert into voice_messages (sender_node_id, blob_hash, duration_ms, waveform, sent_ts, received_ts) values (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                &voice_message.sender_node_id,
+                &voice_message.blob_hash,
+                &voice_message.duration_ms,
+                &voice_message.waveform,
+                &voice_message.sent_ts,
+                &voice_message.received_ts,
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_voice_messages(&self, sender_node_id: &str) -> Result<Vec<VoiceMessageRecord>> {
+        let conn = &self.conn;
+        let mut statement = conn.prepare(
+            "select * from voice_messages where sender_node_id = ?1 order by voice_message_id",
+        )?;
+        let voice_messages = statement
+            .query_map([sender_node_id], VoiceMessageRecord::from_row)?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(voice_messages)
+    }
+
     pub fn update_status(&mut self, node_id: NodeId, user_status: UserStatus) -> Result<()> {
         let conn = &self.conn;
         let node_id = serde_json::to_string(&node_id)?;
@@ -109,6 +136,25 @@ impl Database {
         Ok(node_ids.collect())
     }
 
+    pub fn write_setting(&self, key: &str, value: Option<&str>) -> Result<()> {
+        let conn = &self.conn;
+        conn.execute(
+            "insert into settings (key, value) values (?1, ?2) on conflict(key) do update set value = ?2",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_setting(&self, key: &str) -> Result<Option<String>> {
+        let conn = &self.conn;
+        let value = conn
+            .query_row("select value from settings where key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(value.flatten())
+    }
+
     pub fn get_conn(&self) -> &Connection {
         &self.conn
     }
@@ -119,6 +165,10 @@ impl Database {
         info!("Dropped table messages");
         conn.execute_batch("drop table if exists messages;")?;
         info!("Dropped table users");
+        conn.execute_batch("drop table if exists settings;")?;
+        info!("Dropped table settings");
+        conn.execute_batch("drop table if exists voice_messages;")?;
+        info!("Dropped table voice_messages");
         Ok(())
     }
 }
@@ -13,3 +13,18 @@ CREATE TABLE IF NOT EXISTS messages (
     sent_ts TEXT,
     read_ts TEXT
 );
+
+CREATE TABLE IF NOT EXISTS voice_messages (
+    voice_message_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    sender_node_id TEXT NOT NULL,
+    blob_hash TEXT NOT NULL,
+    duration_ms INTEGER NOT NULL,
+    waveform BLOB,
+    sent_ts TEXT,
+    received_ts TEXT
+);
+
+CREATE TABLE IF NOT EXISTS settings (
+    key TEXT PRIMARY KEY,
+    value TEXT
+);
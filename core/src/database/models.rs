@@ -82,3 +82,32 @@ impl FromRow for Message {
         "messages"
     }
 }
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct VoiceMessageRecord {
+    pub voice_message_id: i32,
+    pub sender_node_id: String,
+    pub blob_hash: String,
+    pub duration_ms: i64,
+    pub waveform: Vec<u8>,
+    pub sent_ts: Option<String>,
+    pub received_ts: Option<String>,
+}
+
+impl FromRow for VoiceMessageRecord {
+    type Model = VoiceMessageRecord;
+    fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<VoiceMessageRecord> {
+        Ok(Self {
+            voice_message_id: row.get("voice_message_id")?,
+            sender_node_id: row.get("sender_node_id")?,
+            blob_hash: row.get("blob_hash")?,
+            duration_ms: row.get("duration_ms")?,
+            waveform: row.get("waveform")?,
+            sent_ts: row.get("sent_ts")?,
+            received_ts: row.get("received_ts")?,
+        })
+    }
+    fn table_name() -> &'static str {
+        "voice_messages"
+    }
+}
@@ -8,10 +8,26 @@ pub mod utils {
 }
 pub mod core {
     pub mod audio;
+    pub mod backend;
+    pub mod bitrate;
+    pub mod call;
+    pub mod channel;
     pub mod client;
+    pub mod frame_source;
     pub mod ipc;
+    pub mod jitter;
+    pub mod mixer;
+    pub mod negotiation;
+    pub mod processing;
+    pub mod reconnect;
+    pub mod recorder;
     pub mod rtc;
     pub mod signal;
+    pub mod soundboard;
+    pub mod stats;
+    pub mod vad;
+    pub mod video;
+    pub mod voice_message;
 }
 pub mod database {
     pub mod db;
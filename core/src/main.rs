@@ -7,10 +7,27 @@ mod utils {
     pub mod types;
 }
 mod core {
+    pub mod audio;
+    pub mod backend;
+    pub mod bitrate;
+    pub mod call;
+    pub mod channel;
     pub mod client;
+    pub mod frame_source;
     pub mod ipc;
+    pub mod jitter;
+    pub mod mixer;
+    pub mod negotiation;
+    pub mod processing;
+    pub mod reconnect;
+    pub mod recorder;
     pub mod rtc;
     pub mod signal;
+    pub mod soundboard;
+    pub mod stats;
+    pub mod vad;
+    pub mod video;
+    pub mod voice_message;
 }
 mod database {
     pub mod db;
@@ -18,14 +35,51 @@ mod database {
 }
 use core::ipc;
 
+use crate::core::backend::{AudioBackend, CpalBackend, FileBackend, SyntheticBackend};
 use crate::core::client::{run, Client};
 use crate::utils::logger;
 use anyhow::Result;
+use clap::{Arg, ArgMatches, Command};
+use std::path::PathBuf;
+use std::sync::Arc;
 use tokio::sync::mpsc;
 
+fn parse_args() -> ArgMatches {
+    Command::new("discard")
+        .arg(
+            Arg::new("audio-backend")
+                .long("audio-backend")
+                .help("Where call audio is captured from and played to")
+                .value_parser(["cpal", "silence", "sine", "file"])
+                .default_value("cpal"),
+        )
+        .arg(
+            Arg::new("audio-input")
+                .long("audio-input")
+                .help("WAV file used as the microphone with the file backend"),
+        )
+        .arg(
+            Arg::new("audio-output")
+                .long("audio-output")
+                .help("WAV file that received audio is written to with the file backend"),
+        )
+        .get_matches()
+}
+
+fn audio_backend(args: &ArgMatches) -> Arc<dyn AudioBackend> {
+    let path = |name: &str| args.get_one::<String>(name).map(PathBuf::from);
+    match args.get_one::<String>("audio-backend").map(String::as_str) {
+        Some("silence") => Arc::new(SyntheticBackend::silence()),
+        Some("sine") => Arc::new(SyntheticBackend::sine(440.0)),
+        Some("file") => Arc::new(FileBackend::new(path("audio-input"), path("audio-output"))),
+        _ => Arc::new(CpalBackend::new()),
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     logger::init_tracing();
+    let args = parse_args();
     let (tx, rx) = mpsc::channel(100);
     //Used to send data back out through the socket
     let (data_tx, data_rx) = mpsc::channel(100);
@@ -33,7 +87,8 @@ async fn main() -> Result<()> {
     let runtime_tx = tx.clone();
     tokio::spawn(async move { ipc::listen(data_rx, runtime_tx, "7878".to_string()).await });
 
-    let client = Client::new("./").await;
+    let mut client = Client::new("./").await;
+    client.set_audio_backend(audio_backend(&args));
     run(client, tx, rx, data_tx).await?;
     Ok(())
 }
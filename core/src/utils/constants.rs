@@ -4,13 +4,55 @@ pub const STUN_SERVERS: [&str; 1] = ["stun:stun.l.google.com:19302"];
 //Signal Config
 pub const SDP_ALPN: &[u8] = b"discard/sdp-exchange";
 pub const SIGNAL_ALPN: &[u8] = b"discard/signal";
+//Largest signal message we are willing to read, e.g. a voice channel name
+pub const SIGNAL_MESSAGE_MAX_SIZE: usize = 1024;
+//Largest offer or answer we are willing to read, one with audio, video and its candidates is
+//several KB
+pub const SESSION_MAX_SIZE: usize = 16 * 1024;
 
 //Time in seconds
 pub const SEND_SESSION_DELAY: u64 = 2;
 pub const SEND_SESSION_TIMEOUT: u64 = 60;
+//Sessions from a peer nobody is negotiating with are dropped after this many seconds
+pub const UNCLAIMED_SESSION_TIMEOUT: u64 = 60;
 
 pub const SEND_TEXT_MESSAGE_DELAY: u64 = 1;
 pub const SEND_TEXT_MESSAGE_TIMEOUT: u64 = 10;
 
+//Seconds each handshake phase gets before the connection is given up on
+pub const OFFER_TIMEOUT: u64 = 30;
+pub const DESCRIPTION_TIMEOUT: u64 = 30;
+pub const ICE_CONNECT_TIMEOUT: u64 = 30;
+pub const DATA_CHANNEL_TIMEOUT: u64 = 10;
+
+//Seconds an unanswered call keeps ringing
+pub const CALL_RING_TIMEOUT: u64 = 30;
+//Seconds an ICE restart gets to bring a failed connection back before it is renegotiated
+pub const ICE_RESTART_TIMEOUT: u64 = 15;
+//Full renegotiations after that, each waiting twice as long as the last before trying
+pub const RECONNECT_ATTEMPTS: u32 = 5;
+pub const RECONNECT_BASE_DELAY: u64 = 1;
+pub const RECONNECT_MAX_DELAY: u64 = 30;
+//Chats with nothing sent either way for this many seconds are closed, checked every interval
+pub const IDLE_CONNECTION_TIMEOUT: u64 = 600;
+pub const IDLE_CHECK_INTERVAL: u64 = 60;
+//Seconds between connection stats events sent to the frontend during calls
+pub const STATS_INTERVAL: u64 = 5;
+
+//Recordings are written under the client's root directory
+pub const RECORDINGS_DIR: &str = "recordings";
+//Ogg/Opus clips for the soundboard, also under the root directory
+pub const SOUNDBOARD_DIR: &str = "sounds";
+//Voice messages are encoded here before being added to the blob store
+pub const VOICE_MESSAGES_DIR: &str = "voice_messages";
+
 //Test
 pub const TEST_DB_ROOT: &str = "./test-db";
+
+//Settings keys
+pub const INPUT_DEVICE_SETTING: &str = "audio_input_device";
+pub const OUTPUT_DEVICE_SETTING: &str = "audio_output_device";
+pub const ECHO_CANCELLATION_SETTING: &str = "echo_cancellation";
+pub const NOISE_SUPPRESSION_SETTING: &str = "noise_suppression";
+pub const AUTO_GAIN_CONTROL_SETTING: &str = "auto_gain_control";
+pub const VIDEO_SOURCE_SETTING: &str = "video_source";
@@ -2,15 +2,152 @@ use core::fmt;
 use std::str::FromStr;
 
 use crate::utils::errors::ParseEnumError;
-use crate::utils::types::{NodeId, TextMessage};
+use crate::utils::types::{NodeId, TextMessage, VoiceMessage, VoiceState};
+use iroh::blobs::Hash;
 use serde::{Deserialize, Serialize};
+use webrtc::api::media_engine::{MIME_TYPE_H264, MIME_TYPE_VP8, MIME_TYPE_VP9};
 use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
-#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
 pub enum SessionType {
+    #[default]
     Idle,
     Chat,
     Video,
     Call,
+    //Watching someone's Go-Live stream in a voice channel
+    Stream,
+    //Audio with someone in our voice channel, kept apart from any chat or call with them
+    VoiceChannel,
+}
+
+//Reported to the frontend while a dropped connection is being restored
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    Reconnecting,
+    Reconnected,
+    //Every attempt failed, the peer has to be connected to again
+    Disconnected,
+}
+
+//Why a connection went away for good
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    //We asked for it with CloseConn
+    Requested,
+    //Nothing was sent either way for too long
+    Idle,
+    Closed,
+    Failed,
+}
+
+//Why a connection couldn't be set up
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionFailure {
+    //Our sessions never reached them or theirs never reached us
+    PeerUnreachable,
+    //We agreed on a connection but no path between us worked
+    IceFailed,
+    //A description we got couldn't be applied
+    Rejected,
+}
+
+//Steps of the webrtc handshake, each with its own deadline
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakePhase {
+    WaitingForOffer,
+    SendingDescription,
+    WaitingForDescription,
+    Connecting,
+    OpeningDataChannel,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoCodec {
+    Vp8,
+    Vp9,
+    H264,
+}
+
+impl VideoCodec {
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            VideoCodec::Vp8 => MIME_TYPE_VP8,
+            VideoCodec::Vp9 => MIME_TYPE_VP9,
+            VideoCodec::H264 => MIME_TYPE_H264,
+        }
+    }
+
+    pub fn from_mime_type(mime_type: &str) -> Option<Self> {
+        if mime_type.eq_ignore_ascii_case(MIME_TYPE_VP8) {
+            Some(VideoCodec::Vp8)
+        } else if mime_type.eq_ignore_ascii_case(MIME_TYPE_VP9) {
+            Some(VideoCodec::Vp9)
+        } else if mime_type.eq_ignore_ascii_case(MIME_TYPE_H264) {
+            Some(VideoCodec::H264)
+        } else {
+            None
+        }
+    }
+}
+
+//Where our side of a video call comes from
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(tag = "type", content = "path")]
+pub enum VideoSource {
+    #[default]
+    TestPattern,
+    //An IVF (VP8) or Annex B H264 file
+    File(String),
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub enum AudioDirection {
+    Input,
+    Output,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioProcessing {
+    EchoCancellation,
+    NoiseSuppression,
+    AutoGainControl,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallSignal {
+    //Carries whether it is a voice or a video call, the accept echoes it back
+    Ring(SessionType),
+    Accept(SessionType),
+    Decline,
+    Busy,
+    Cancel,
+    HangUp,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallEndReason {
+    Declined,
+    Busy,
+    Cancelled,
+    HungUp,
+    Missed,
+    //The call's connection failed or dropped
+    ConnectionLost,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoiceChannelSignal {
+    //Sent to every known peer when we join a channel
+    Join,
+    //Reply from peers already in the channel
+    Present,
+    Leave,
+    //Sent to the whole channel when we go live or stop, and to anyone joining while we are live
+    StreamStarted,
+    StreamEnded,
+    //Sent to a streamer to start or stop watching their stream
+    Watch,
+    Unwatch,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
@@ -61,6 +198,10 @@ pub enum SignalMessageType {
 pub enum SignalMessage {
     Online(NodeId, UserStatus),
     SendConnection(SessionType),
+    //The sender is whoever the connection is authenticated as, never part of the message
+    VoiceState(VoiceState),
+    Call(CallSignal),
+    VoiceChannel(String, VoiceChannelSignal),
 }
 
 //Signals what the client should prepare for. E.g., ReceiveMessage will signal the client to
@@ -70,12 +211,62 @@ pub enum SignalMessage {
 pub enum RunMessage {
     UpdateStatus(NodeId, UserStatus),
     Adduser(NodeId, String),
-    InitConn(SessionType, String),
+    InitConn(SessionType, NodeId),
+    CloseConn(NodeId),
     RecvConn(SessionType),
     GetUsers,
     Shutdown,
-    SendMessage(String, String),
-    GetUser(String),
+    SendMessage(NodeId, String),
+    GetUser(NodeId),
+    GetAudioDevices,
+    SetAudioDevice(AudioDirection, Option<String>),
+    SetMuted(bool),
+    SetDeafened(bool),
+    SetPushToTalk(bool),
+    PushToTalkDown,
+    PushToTalkUp,
+    PeerVoiceState(NodeId, VoiceState),
+    Speaking(NodeId, bool),
+    AcceptCall,
+    DeclineCall,
+    HangUp,
+    CallSignal(NodeId, CallSignal),
+    CallTimeout(NodeId),
+    JoinVoiceChannel(String),
+    LeaveVoiceChannel,
+    GetVoiceParticipants,
+    SetParticipantVolume(NodeId, u16),
+    VoiceChannelSignal(NodeId, String, VoiceChannelSignal),
+    StartRecording,
+    StopRecording,
+    GetSounds,
+    PlaySound(String),
+    RecordVoiceMessage,
+    CancelVoiceMessage,
+    SendVoiceMessage(NodeId),
+    ImportVoiceMessage(NodeId, String),
+    ReceiveVoiceMessage(VoiceMessage),
+    PlayVoiceMessage(Hash),
+    GetVoiceMessages(NodeId),
+    GetAudioProcessing,
+    SetAudioProcessing(AudioProcessing, bool),
+    GetConnectionStats(NodeId),
+    PublishConnectionStats,
+    ConnectionStatus(NodeId, ConnectionStatus),
+    ConnectionClosed(NodeId, CloseReason),
+    //A call connection that failed to open or ended without a hang up
+    CallConnectionEnded(NodeId),
+    //Reconnecting gave up, everything going on with the peer is ended
+    ConnectionLost(NodeId, SessionType),
+    ConnectionFailed(Option<NodeId>, SessionType, ConnectionFailure),
+    CloseIdleConnections,
+    GetVideoSource,
+    SetVideoSource(VideoSource),
+    StartStream(VideoSource),
+    StopStream,
+    WatchStream(NodeId),
+    UnwatchStream(NodeId),
+    GetStreamViewers,
 }
 
 #[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
@@ -89,5 +280,6 @@ pub enum RunMessageType {
 #[derive(PartialEq, Clone, Debug)]
 pub enum MessageType {
     Message(TextMessage),
+    VoiceMessage(VoiceMessage),
     ConnectionState(RTCPeerConnectionState),
 }
@@ -1,6 +1,8 @@
 use std::error::Error;
 use std::fmt;
 
+use crate::utils::enums::{ConnectionFailure, HandshakePhase};
+
 #[derive(Debug)]
 pub struct TimeoutError {
     pub operation: String,
@@ -17,6 +19,27 @@ impl fmt::Display for TimeoutError {
     }
 }
 
+//A handshake phase that failed or ran out of time, the reason is passed on to the frontend
+#[derive(Debug)]
+pub struct HandshakeError {
+    pub phase: HandshakePhase,
+    pub reason: ConnectionFailure,
+}
+
+impl HandshakeError {
+    pub fn new(phase: HandshakePhase, reason: ConnectionFailure) -> Self {
+        Self { phase, reason }
+    }
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Handshake failed at {:?}: {:?}", self.phase, self.reason)
+    }
+}
+
+impl Error for HandshakeError {}
+
 #[derive(Debug)]
 pub enum ParseEnumError {
     InvalidVariant,
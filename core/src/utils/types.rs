@@ -1,4 +1,5 @@
 use chrono::{DateTime, Utc};
+use iroh::blobs::Hash;
 use iroh::net::key::PublicKey;
 use serde::{Deserialize, Serialize};
 use std::boxed::Box;
@@ -16,6 +17,44 @@ pub struct TextMessage {
     pub timestamp: DateTime<Utc>,
 }
 
+//Mute/deafen/recording state shown next to a peer in a call
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct VoiceState {
+    #[serde(rename = "muted")]
+    pub muted: bool,
+    #[serde(rename = "deafened")]
+    pub deafened: bool,
+    #[serde(rename = "recording")]
+    pub recording: bool,
+}
+
+//Sent over the data channel as a binary message. The audio stays in the sender's blob store until
+//the receiver downloads it
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct VoiceMessage {
+    #[serde(rename = "sender")]
+    pub sender: NodeId,
+    #[serde(rename = "hash")]
+    pub hash: Hash,
+    #[serde(rename = "durationMs")]
+    pub duration_ms: u64,
+    #[serde(rename = "waveform")]
+    pub waveform: Vec<u8>,
+    #[serde(rename = "timestamp")]
+    pub timestamp: DateTime<Utc>,
+}
+
+//Which processing steps are applied to the microphone before it is encoded
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub struct AudioProcessingSettings {
+    #[serde(rename = "echoCancellation")]
+    pub echo_cancellation: bool,
+    #[serde(rename = "noiseSuppression")]
+    pub noise_suppression: bool,
+    #[serde(rename = "autoGainControl")]
+    pub auto_gain_control: bool,
+}
+
 impl std::fmt::Display for TextMessage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "TextMessage: {} {}", self.content, self.timestamp)
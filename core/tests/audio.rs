@@ -0,0 +1,248 @@
+use discard::core::audio::{self, VoiceControls, FRAME_SIZE, MAX_OPUS_PACKET_SIZE, SAMPLE_RATE};
+use discard::core::backend::{self, AudioBackend, FileBackend, SyntheticBackend};
+use discard::core::jitter::{JitterBuffer, JitterOutput, JITTER_BUFFER_DEPTH};
+use discard::core::vad::{rms, VoiceActivityDetector, VAD_HANGOVER_FRAMES};
+use discard::utils::enums::AudioDirection;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::time::{sleep, Duration};
+
+#[test]
+fn test_resample_to_opus_rate() {
+    //10ms of audio at 44.1kHz should become 10ms at 48kHz
+    let input = vec![0.5; 441];
+    let output = audio::resample(&input, 44100, SAMPLE_RATE);
+    assert_eq!(output.len(), 480);
+    assert!(output.iter().all(|s| (s - 0.5).abs() < f32::EPSILON));
+
+    let output = audio::resample(&input, SAMPLE_RATE, SAMPLE_RATE);
+    assert_eq!(output, input);
+}
+
+#[test]
+fn test_resampler_keeps_position_across_chunks() {
+    //Callbacks rarely line up with the rate ratio, rounding each chunk on its own drifts
+    let input: Vec<f32> = (0..44100).map(|i| i as f32 / 44100.0).collect();
+    let mut resampler = audio::Resampler::new(44100, SAMPLE_RATE);
+    let output: Vec<f32> = input
+        .chunks(100)
+        .flat_map(|chunk| resampler.process(chunk))
+        .collect();
+    assert!(output.len().abs_diff(SAMPLE_RATE as usize) <= 1);
+
+    //A ramp stays a ramp, nothing is repeated or skipped at the chunk boundaries
+    let step = 44100.0 / SAMPLE_RATE as f32 / 44100.0;
+    assert!(output
+        .windows(2)
+        .all(|pair| (pair[1] - pair[0] - step).abs() < 1e-5));
+}
+
+#[test]
+fn test_downmix_stereo() {
+    let stereo = vec![1.0, 0.0, 0.5, 0.5];
+    assert_eq!(audio::downmix(&stereo, 2), vec![0.5, 0.5]);
+    assert_eq!(audio::downmix(&stereo, 1), stereo);
+}
+
+#[test]
+fn test_opus_round_trip() {
+    let encoder = audio::new_encoder().expect("Failed to create encoder");
+    let mut decoder = audio::new_decoder().expect("Failed to create decoder");
+    let frame: Vec<f32> = (0..FRAME_SIZE)
+        .map(|i| (i as f32 * 440.0 * 2.0 * std::f32::consts::PI / SAMPLE_RATE as f32).sin())
+        .collect();
+    let mut packet = vec![0u8; MAX_OPUS_PACKET_SIZE];
+    let len = encoder
+        .encode_float(&frame, &mut packet)
+        .expect("Failed to encode frame");
+    assert!(len > 0 && len <= MAX_OPUS_PACKET_SIZE);
+
+    let pcm =
+        audio::decode_packet(&mut decoder, Some(&packet[..len])).expect("Failed to decode packet");
+    assert_eq!(pcm.len(), FRAME_SIZE);
+
+    //Packet loss concealment still produces a full frame
+    let pcm = audio::decode_packet(&mut decoder, None).expect("Failed to conceal loss");
+    assert_eq!(pcm.len(), FRAME_SIZE);
+}
+
+#[test]
+fn test_dtx_silence_and_speech_onset() {
+    let mut encoder = audio::new_encoder().expect("Failed to create encoder");
+    encoder.set_dtx(true).expect("Failed to enable DTX");
+    let mut packet = vec![0u8; MAX_OPUS_PACKET_SIZE];
+
+    //Every silent frame still produces a packet to send, they just shrink once DTX kicks in
+    let silence = vec![0.0; FRAME_SIZE];
+    let sizes: Vec<usize> = (0..50)
+        .map(|_| encoder.encode_float(&silence, &mut packet).unwrap())
+        .collect();
+    assert!(sizes.iter().all(|len| *len > 0));
+    assert!(sizes.iter().any(|len| *len <= 2));
+
+    //The first frame of speech is encoded in full
+    let speech: Vec<f32> = (0..FRAME_SIZE)
+        .map(|i| 0.5 * (i as f32 * 440.0 * 2.0 * std::f32::consts::PI / SAMPLE_RATE as f32).sin())
+        .collect();
+    let len = encoder.encode_float(&speech, &mut packet).unwrap();
+    assert!(len > 2);
+}
+
+#[test]
+fn test_jitter_buffer_reorders_packets() {
+    let mut jitter_buffer = JitterBuffer::new();
+    jitter_buffer.push(2, vec![2]);
+    jitter_buffer.push(1, vec![1]);
+    assert_eq!(jitter_buffer.pop(), JitterOutput::Empty);
+
+    jitter_buffer.push(4, vec![4]);
+    assert_eq!(jitter_buffer.len(), JITTER_BUFFER_DEPTH);
+    assert_eq!(jitter_buffer.pop(), JitterOutput::Packet(vec![1]));
+    assert_eq!(jitter_buffer.pop(), JitterOutput::Packet(vec![2]));
+    //Packet 3 never arrived
+    assert_eq!(jitter_buffer.pop(), JitterOutput::Lost);
+    //A late packet that was already concealed is dropped
+    jitter_buffer.push(3, vec![3]);
+    assert_eq!(jitter_buffer.pop(), JitterOutput::Packet(vec![4]));
+    assert_eq!(jitter_buffer.pop(), JitterOutput::Empty);
+}
+
+#[test]
+fn test_jitter_buffer_sequence_wraparound() {
+    let mut jitter_buffer = JitterBuffer::new();
+    jitter_buffer.push(0, vec![3]);
+    jitter_buffer.push(u16::MAX, vec![2]);
+    jitter_buffer.push(u16::MAX - 1, vec![1]);
+    assert_eq!(jitter_buffer.pop(), JitterOutput::Packet(vec![1]));
+    assert_eq!(jitter_buffer.pop(), JitterOutput::Packet(vec![2]));
+    assert_eq!(jitter_buffer.pop(), JitterOutput::Packet(vec![3]));
+}
+
+#[tokio::test]
+async fn test_synthetic_backend_loopback() {
+    let input = Arc::new(SyntheticBackend::sine(440.0));
+    let output = Arc::new(SyntheticBackend::silence());
+
+    let (_input_stream, mut frame_rx) = Arc::clone(&input)
+        .start_input()
+        .await
+        .expect("Failed to start synthetic input");
+    let (_output_stream, frame_tx) = Arc::clone(&output)
+        .start_output()
+        .await
+        .expect("Failed to start synthetic output");
+
+    //Push a few frames through the same encode/decode path a call uses
+    let encoder = audio::new_encoder().expect("Failed to create encoder");
+    let mut decoder = audio::new_decoder().expect("Failed to create decoder");
+    let mut packet = vec![0u8; MAX_OPUS_PACKET_SIZE];
+    for _ in 0..3 {
+        let frame = frame_rx.recv().await.expect("Synthetic input ended");
+        assert_eq!(frame.len(), FRAME_SIZE);
+        assert!(frame.iter().any(|s| s.abs() > 0.1));
+
+        let len = encoder.encode_float(&frame, &mut packet).unwrap();
+        let pcm = audio::decode_packet(&mut decoder, Some(&packet[..len])).unwrap();
+        frame_tx.send(pcm).unwrap();
+    }
+
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(output.played_samples(), FRAME_SIZE * 3);
+}
+
+#[tokio::test]
+async fn test_file_backend() {
+    let input_path = PathBuf::from("./test_file_backend_in.wav");
+    let output_path = PathBuf::from("./test_file_backend_out.wav");
+
+    //40ms of 16 bit audio at 24kHz, gets resampled to two 48kHz frames
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: 24000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(&input_path, spec).unwrap();
+    for _ in 0..960 {
+        writer.write_sample(i16::MAX / 2).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    let samples = backend::read_wav(&input_path).expect("Failed to read wav");
+    assert_eq!(samples.len(), FRAME_SIZE * 2);
+
+    let file_backend = Arc::new(FileBackend::new(
+        Some(input_path.clone()),
+        Some(output_path.clone()),
+    ));
+    let (_input_stream, mut frame_rx) = Arc::clone(&file_backend).start_input().await.unwrap();
+    let (mut output_stream, frame_tx) = Arc::clone(&file_backend).start_output().await.unwrap();
+    while let Some(frame) = frame_rx.recv().await {
+        assert_eq!(frame.len(), FRAME_SIZE);
+        frame_tx.send(frame).unwrap();
+    }
+    drop(frame_tx);
+    sleep(Duration::from_millis(50)).await;
+    output_stream.stop();
+
+    let written = backend::read_wav(&output_path).expect("Failed to read output wav");
+    assert_eq!(written.len(), FRAME_SIZE * 2);
+    assert!((written[FRAME_SIZE] - 0.5).abs() < 0.01);
+
+    let _ = std::fs::remove_file(input_path);
+    let _ = std::fs::remove_file(output_path);
+}
+
+#[test]
+fn test_synthetic_backend_has_no_devices() {
+    let synthetic = SyntheticBackend::silence();
+    let devices = synthetic.list_devices().unwrap();
+    assert!(devices.inputs.is_empty() && devices.outputs.is_empty());
+    assert!(synthetic
+        .select_device(AudioDirection::Input, Some("mic".to_string()))
+        .is_err());
+}
+
+#[test]
+fn test_voice_controls() {
+    let controls = VoiceControls::default();
+    assert!(controls.is_transmitting());
+
+    controls.set_muted(true);
+    assert!(!controls.is_transmitting());
+    assert!(controls.state().muted);
+    controls.set_muted(false);
+
+    //Deafening mutes as well
+    controls.set_deafened(true);
+    assert!(!controls.is_transmitting());
+    assert!(controls.state().deafened);
+    controls.set_deafened(false);
+
+    controls.set_push_to_talk(true);
+    assert!(!controls.is_transmitting());
+    controls.set_talking(true);
+    assert!(controls.is_transmitting());
+    controls.set_talking(false);
+    assert!(!controls.is_transmitting());
+}
+
+#[test]
+fn test_vad_speech_and_hangover() {
+    let mut vad = VoiceActivityDetector::default();
+    let silence = vec![0.0; FRAME_SIZE];
+    let speech = vec![0.1; FRAME_SIZE];
+
+    assert_eq!(vad.update(&silence), None);
+    assert_eq!(vad.update(&speech), Some(true));
+    assert_eq!(vad.update(&speech), None);
+
+    //Short pauses are bridged by the hangover
+    for _ in 0..VAD_HANGOVER_FRAMES {
+        assert_eq!(vad.update(&silence), None);
+        assert!(vad.is_speaking());
+    }
+    //Nothing received counts as silence
+    assert_eq!(vad.update(&[]), Some(false));
+    assert!(rms(&speech) > rms(&silence));
+}
@@ -0,0 +1,88 @@
+use audiopus::Bitrate;
+use discard::core::audio::{apply_encoder_settings, new_encoder};
+use discard::core::bitrate::{
+    BitrateController, EncoderSettings, DEFAULT_OPUS_BITRATE, MAX_OPUS_BITRATE, MIN_OPUS_BITRATE,
+};
+use webrtc::rtcp::{
+    packet::Packet,
+    payload_feedbacks::receiver_estimated_maximum_bitrate::ReceiverEstimatedMaximumBitrate,
+    receiver_report::ReceiverReport, reception_report::ReceptionReport,
+};
+
+fn receiver_report(fraction_lost: u8) -> Box<dyn Packet + Send + Sync> {
+    Box::new(ReceiverReport {
+        reports: vec![ReceptionReport {
+            fraction_lost,
+            ..Default::default()
+        }],
+        ..Default::default()
+    })
+}
+
+#[test]
+fn test_bitrate_backs_off_on_loss() {
+    let mut controller = BitrateController::default();
+    assert_eq!(controller.settings(), EncoderSettings::default());
+
+    //25% loss
+    for _ in 0..20 {
+        controller.on_loss(64);
+    }
+    let settings = controller.settings();
+    assert_eq!(settings.bitrate, MIN_OPUS_BITRATE);
+    assert!(settings.fec);
+    assert_eq!(settings.packet_loss_perc, 25);
+
+    //A clean link slowly earns the bitrate back and drops FEC
+    for _ in 0..50 {
+        controller.on_loss(0);
+    }
+    let settings = controller.settings();
+    assert_eq!(settings.bitrate, MAX_OPUS_BITRATE);
+    assert!(!settings.fec);
+    assert_eq!(settings.packet_loss_perc, 0);
+}
+
+#[test]
+fn test_moderate_loss_holds_bitrate() {
+    let mut controller = BitrateController::default();
+    for _ in 0..20 {
+        //About 5%, enough to want FEC but not a sign of congestion
+        controller.on_loss(13);
+    }
+    let settings = controller.settings();
+    assert_eq!(settings.bitrate, DEFAULT_OPUS_BITRATE);
+    assert!(settings.fec);
+    assert_eq!(settings.packet_loss_perc, 5);
+}
+
+#[test]
+fn test_rtcp_feedback() {
+    let mut controller = BitrateController::default();
+    let packets: Vec<Box<dyn Packet + Send + Sync>> =
+        vec![Box::new(ReceiverEstimatedMaximumBitrate {
+            bitrate: 20_000.0,
+            ..Default::default()
+        })];
+    assert!(controller.on_rtcp(&packets));
+    for _ in 0..20 {
+        controller.on_rtcp(&[receiver_report(0)]);
+    }
+    //The peer's estimate caps how far a clean link can push the bitrate
+    assert_eq!(controller.settings().bitrate, 20_000);
+    assert!(!controller.on_rtcp(&[]));
+}
+
+#[test]
+fn test_apply_encoder_settings() {
+    let mut encoder = new_encoder().unwrap();
+    let settings = EncoderSettings {
+        bitrate: 16_000,
+        fec: true,
+        packet_loss_perc: 10,
+    };
+    apply_encoder_settings(&mut encoder, settings).unwrap();
+    assert_eq!(encoder.bitrate().unwrap(), Bitrate::BitsPerSecond(16_000));
+    assert!(encoder.inband_fec().unwrap());
+    assert_eq!(encoder.packet_loss_perc().unwrap(), 10);
+}
@@ -0,0 +1,173 @@
+mod utils;
+
+use discard::core::call::{CallAction, CallEvent, CallManager, CallState};
+use discard::utils::enums::{CallEndReason, CallSignal, SessionType};
+use utils::node_id;
+
+#[test]
+fn test_outgoing_call_accepted_and_hung_up() {
+    let peer = node_id();
+    let mut call = CallManager::new();
+
+    let actions = call.call(peer, SessionType::Call).unwrap();
+    assert!(actions.contains(&CallAction::Signal(
+        peer,
+        CallSignal::Ring(SessionType::Call)
+    )));
+    assert!(actions.contains(&CallAction::StartTimer(peer)));
+    assert_eq!(call.state(), CallState::Ringing(peer));
+    assert!(call.call(node_id(), SessionType::Call).is_err());
+
+    let actions = call.on_signal(peer, CallSignal::Accept(SessionType::Call));
+    assert!(actions.contains(&CallAction::Offer(peer, SessionType::Call)));
+    assert_eq!(call.state(), CallState::Active(peer));
+
+    //A ring timer from before the call was picked up does nothing
+    assert!(call.on_timeout(peer).is_empty());
+
+    let actions = call.hang_up().unwrap();
+    assert!(actions.contains(&CallAction::Signal(peer, CallSignal::HangUp)));
+    assert!(actions.contains(&CallAction::Close(peer)));
+    assert_eq!(call.state(), CallState::Idle);
+}
+
+#[test]
+fn test_incoming_call() {
+    let peer = node_id();
+    let mut call = CallManager::new();
+
+    let actions = call.on_signal(peer, CallSignal::Ring(SessionType::Call));
+    assert!(actions.contains(&CallAction::Event(CallEvent::Incoming(peer))));
+    assert_eq!(call.state(), CallState::Incoming(peer));
+
+    //Anyone else calling gets a busy signal
+    let other = node_id();
+    let actions = call.on_signal(other, CallSignal::Ring(SessionType::Call));
+    assert_eq!(actions, vec![CallAction::Signal(other, CallSignal::Busy)]);
+
+    //Answer has to be set up before the caller is told to send its offer
+    let actions = call.accept().unwrap();
+    assert_eq!(actions[0], CallAction::Answer(peer, SessionType::Call));
+    assert_eq!(
+        actions[1],
+        CallAction::Signal(peer, CallSignal::Accept(SessionType::Call))
+    );
+
+    let actions = call.on_signal(peer, CallSignal::HangUp);
+    assert!(actions.contains(&CallAction::Close(peer)));
+    assert!(actions.contains(&CallAction::Event(CallEvent::Ended(
+        peer,
+        CallEndReason::HungUp
+    ))));
+    assert_eq!(call.state(), CallState::Idle);
+}
+
+#[test]
+fn test_call_declined_busy_and_cancelled() {
+    let peer = node_id();
+    let mut call = CallManager::new();
+
+    call.call(peer, SessionType::Call).unwrap();
+    let actions = call.on_signal(peer, CallSignal::Decline);
+    assert_eq!(
+        actions,
+        vec![CallAction::Event(CallEvent::Ended(
+            peer,
+            CallEndReason::Declined
+        ))]
+    );
+
+    call.call(peer, SessionType::Call).unwrap();
+    call.on_signal(peer, CallSignal::Busy);
+    assert_eq!(call.state(), CallState::Idle);
+
+    call.on_signal(peer, CallSignal::Ring(SessionType::Call));
+    let actions = call.on_signal(peer, CallSignal::Cancel);
+    assert!(actions.contains(&CallAction::Event(CallEvent::Ended(
+        peer,
+        CallEndReason::Cancelled
+    ))));
+    assert!(call.accept().is_err());
+}
+
+#[test]
+fn test_unanswered_call_times_out() {
+    let peer = node_id();
+    let mut call = CallManager::new();
+
+    call.call(peer, SessionType::Call).unwrap();
+    let actions = call.on_timeout(peer);
+    assert!(actions.contains(&CallAction::Signal(peer, CallSignal::Cancel)));
+    assert!(actions.contains(&CallAction::Event(CallEvent::Ended(
+        peer,
+        CallEndReason::Missed
+    ))));
+
+    call.on_signal(peer, CallSignal::Ring(SessionType::Call));
+    call.on_timeout(peer);
+    assert_eq!(call.state(), CallState::Idle);
+}
+
+#[test]
+fn test_call_connection_ended() {
+    let peer = node_id();
+    let mut call = CallManager::new();
+
+    call.call(peer, SessionType::Call).unwrap();
+    call.on_signal(peer, CallSignal::Accept(SessionType::Call));
+    //Someone else's connection going away doesn't touch the call
+    assert!(call.on_connection_ended(node_id()).is_empty());
+    assert_eq!(call.state(), CallState::Active(peer));
+
+    //The handshake failed or the connection dropped without a hang up
+    let actions = call.on_connection_ended(peer);
+    assert!(actions.contains(&CallAction::Signal(peer, CallSignal::HangUp)));
+    assert!(actions.contains(&CallAction::Event(CallEvent::Ended(
+        peer,
+        CallEndReason::ConnectionLost
+    ))));
+    assert_eq!(call.state(), CallState::Idle);
+
+    //A new call can be placed right away
+    assert!(call.call(peer, SessionType::Call).is_ok());
+    call.on_connection_ended(peer);
+    assert_eq!(call.state(), CallState::Idle);
+}
+
+#[test]
+fn test_video_call() {
+    let peer = node_id();
+    let mut call = CallManager::new();
+    assert!(call.call(peer, SessionType::Chat).is_err());
+
+    let actions = call.call(peer, SessionType::Video).unwrap();
+    assert!(actions.contains(&CallAction::Signal(
+        peer,
+        CallSignal::Ring(SessionType::Video)
+    )));
+    assert_eq!(call.session_type(), SessionType::Video);
+
+    //Accepting something other than what we rang for doesn't start the handshake
+    assert!(call
+        .on_signal(peer, CallSignal::Accept(SessionType::Call))
+        .is_empty());
+    let actions = call.on_signal(peer, CallSignal::Accept(SessionType::Video));
+    assert!(actions.contains(&CallAction::Offer(peer, SessionType::Video)));
+    call.hang_up().unwrap();
+
+    //The callee answers the kind of call it was rung for
+    call.on_signal(peer, CallSignal::Ring(SessionType::Video));
+    let actions = call.accept().unwrap();
+    assert_eq!(actions[0], CallAction::Answer(peer, SessionType::Video));
+    assert_eq!(
+        actions[1],
+        CallAction::Signal(peer, CallSignal::Accept(SessionType::Video))
+    );
+    call.hang_up().unwrap();
+
+    //Nothing but calls can ring
+    assert!(call
+        .on_signal(peer, CallSignal::Ring(SessionType::Chat))
+        .is_empty());
+    assert_eq!(call.state(), CallState::Idle);
+}
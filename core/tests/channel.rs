@@ -0,0 +1,99 @@
+mod utils;
+
+use discard::core::audio::Volume;
+use discard::core::channel::{VoiceChannel, DEFAULT_VOLUME, MAX_VOLUME};
+use utils::node_id;
+
+#[test]
+fn test_voice_channel_participants() {
+    let mut voice_channel = VoiceChannel::new("general".to_string());
+    let peer = node_id();
+
+    assert!(voice_channel.add_participant(peer));
+    //Join and present can both arrive for the same peer
+    assert!(!voice_channel.add_participant(peer));
+    assert_eq!(voice_channel.participants(), vec![(peer, DEFAULT_VOLUME)]);
+
+    assert_eq!(voice_channel.set_volume(&peer, 50), Some(50));
+    assert_eq!(voice_channel.set_volume(&peer, 1000), Some(MAX_VOLUME));
+    assert_eq!(voice_channel.set_volume(&node_id(), 50), None);
+
+    assert!(voice_channel.remove_participant(&peer));
+    assert!(!voice_channel.contains(&peer));
+    assert!(voice_channel.participants().is_empty());
+}
+
+#[test]
+fn test_go_live_viewers() {
+    let mut voice_channel = VoiceChannel::new("general".to_string());
+    let viewer = node_id();
+    let stranger = node_id();
+    voice_channel.add_participant(viewer);
+
+    //Nobody can watch until we are live
+    assert!(!voice_channel.add_viewer(viewer));
+    assert!(voice_channel.end_live().is_none());
+
+    assert!(voice_channel.go_live());
+    assert!(!voice_channel.go_live());
+    assert!(voice_channel.is_live());
+    assert!(voice_channel.add_viewer(viewer));
+    assert!(!voice_channel.add_viewer(viewer));
+    //Only people in the channel can watch
+    assert!(!voice_channel.add_viewer(stranger));
+    assert_eq!(voice_channel.viewers(), vec![viewer]);
+
+    assert!(voice_channel.remove_viewer(&viewer));
+    assert!(!voice_channel.is_viewer(&viewer));
+    assert!(voice_channel.add_viewer(viewer));
+    //Leaving the channel stops them watching
+    voice_channel.remove_participant(&viewer);
+    assert!(voice_channel.viewers().is_empty());
+
+    voice_channel.add_participant(viewer);
+    voice_channel.add_viewer(viewer);
+    assert_eq!(voice_channel.end_live(), Some(vec![viewer]));
+    assert!(!voice_channel.is_live());
+    assert!(voice_channel.viewers().is_empty());
+}
+
+#[test]
+fn test_watch_streams() {
+    let mut voice_channel = VoiceChannel::new("general".to_string());
+    let streamer = node_id();
+
+    assert!(!voice_channel.watch(streamer));
+    //The announcement can arrive before the streamer's Present
+    assert!(voice_channel.set_streaming(streamer, true));
+    assert!(!voice_channel.set_streaming(streamer, true));
+    voice_channel.add_participant(streamer);
+    assert!(voice_channel.is_streaming(&streamer));
+
+    assert!(voice_channel.watch(streamer));
+    assert!(!voice_channel.watch(streamer));
+    assert_eq!(voice_channel.watching(), vec![streamer]);
+    assert!(voice_channel.unwatch(&streamer));
+    assert!(!voice_channel.unwatch(&streamer));
+
+    //A stream ending stops us watching it
+    voice_channel.watch(streamer);
+    assert!(voice_channel.set_streaming(streamer, false));
+    assert!(!voice_channel.is_watching(&streamer));
+    assert!(!voice_channel.watch(streamer));
+
+    voice_channel.set_streaming(streamer, true);
+    voice_channel.watch(streamer);
+    voice_channel.remove_participant(&streamer);
+    assert!(!voice_channel.is_streaming(&streamer));
+    assert!(voice_channel.watching().is_empty());
+}
+
+#[test]
+fn test_volume() {
+    let volume = Volume::default();
+    assert_eq!(volume.get(), 1.0);
+    volume.set(0.5);
+    assert_eq!(volume.get(), 0.5);
+    volume.set(-1.0);
+    assert_eq!(volume.get(), 0.0);
+}
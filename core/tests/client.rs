@@ -27,6 +27,7 @@ async fn test_data_channel() {
 
     let p1 = Client::new(test_paths[0]).await;
     let p2 = Client::new(test_paths[1]).await;
+    let p1_node_id = p1.get_node_id();
 
     //peer 1 channel to simulate client receiving a message
     let (tx1, rx1) = mpsc::channel::<RunMessage>(10);
@@ -67,10 +68,7 @@ async fn test_data_channel() {
         timestamp: chrono::Utc::now(),
     };
     let result = tx2
-        .send(RunMessage::SendMessage(
-            "TEMP".to_string(),
-            "test".to_string(),
-        ))
+        .send(RunMessage::SendMessage(p1_node_id, "test".to_string()))
         .await;
     assert!(result.is_ok());
     assert!(result.is_ok());
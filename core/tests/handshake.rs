@@ -0,0 +1,32 @@
+use discard::core::ipc::{ConnectionFailedResp, IPCResponse};
+use discard::utils::enums::{ConnectionFailure, HandshakePhase, SessionType};
+use discard::utils::errors::HandshakeError;
+use serde_json::json;
+
+#[test]
+fn test_failure_survives_context() {
+    let error = anyhow::anyhow!("connection refused").context(HandshakeError::new(
+        HandshakePhase::SendingDescription,
+        ConnectionFailure::PeerUnreachable,
+    ));
+
+    //The reason is taken from whatever error the handshake ended with
+    let failure = error
+        .downcast_ref::<HandshakeError>()
+        .expect("Handshake error should be found behind the context");
+    assert_eq!(failure.reason, ConnectionFailure::PeerUnreachable);
+    assert_eq!(failure.phase, HandshakePhase::SendingDescription);
+}
+
+#[test]
+fn test_failure_without_peer() {
+    //Nobody offered, so we never found out who we were waiting for
+    let response = IPCResponse::ConnectionFailed(ConnectionFailedResp {
+        node_id: None,
+        session_type: SessionType::Call,
+        reason: ConnectionFailure::IceFailed,
+    });
+    let value = serde_json::to_value(&response).expect("Error serializing response");
+    assert_eq!(value["data"]["nodeId"], json!(null));
+    assert_eq!(value["data"]["reason"], json!("IceFailed"));
+}
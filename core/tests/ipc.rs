@@ -73,7 +73,7 @@ async fn test_ipc_add_user() {
     let num_bytes = stream.write(&bytes).await.expect("Error writing to stream");
     println!("Wrote {}: ", num_bytes);
 
-    let message = RunMessage::GetUser("test_user1".to_string());
+    let message = RunMessage::GetUser(test_key);
     let bytes = serde_json::to_vec(&message).expect("Error serializing to bytes");
     let mut buf = vec![0; 1024];
     stream
@@ -0,0 +1,75 @@
+use discard::core::audio::{FRAME_SIZE, SAMPLE_RATE};
+use discard::core::mixer::{soft_clip, Mixer, MAX_MIXER_BUFFER, SOFT_CLIP_THRESHOLD};
+
+#[test]
+fn test_mixer_sums_sources_with_gain() {
+    let mut mixer = Mixer::new(SAMPLE_RATE, 1);
+    assert_eq!(mixer.mix(), None);
+
+    let a = mixer.add_source();
+    let b = mixer.add_source();
+    mixer.set_gain(b, 0.5);
+    mixer.push(a, &vec![0.2; FRAME_SIZE]);
+    mixer.push(b, &vec![0.2; FRAME_SIZE]);
+
+    let frame = mixer.mix().expect("Nothing was mixed");
+    assert_eq!(frame.len(), FRAME_SIZE);
+    assert!(frame.iter().all(|s| (s - 0.3).abs() < 1e-6));
+
+    //Both sources are drained
+    assert_eq!(mixer.mix(), None);
+}
+
+#[test]
+fn test_mixer_pads_short_sources() {
+    let mut mixer = Mixer::new(SAMPLE_RATE, 1);
+    let a = mixer.add_source();
+    mixer.push(a, &vec![0.5; FRAME_SIZE / 2]);
+
+    let frame = mixer.mix().unwrap();
+    assert_eq!(frame[0], 0.5);
+    assert_eq!(frame[FRAME_SIZE - 1], 0.0);
+
+    mixer.remove_source(a);
+    assert!(mixer.is_empty());
+}
+
+#[test]
+fn test_mixer_limits_buffered_audio() {
+    let mut mixer = Mixer::new(SAMPLE_RATE, 1);
+    let a = mixer.add_source();
+    mixer.push(a, &vec![0.1; MAX_MIXER_BUFFER * 2]);
+
+    let mut frames = 0;
+    while mixer.mix().is_some() {
+        frames += 1;
+    }
+    assert_eq!(frames, MAX_MIXER_BUFFER / FRAME_SIZE);
+}
+
+#[test]
+fn test_mixer_clipping_protection() {
+    let mut mixer = Mixer::new(SAMPLE_RATE, 1);
+    for _ in 0..4 {
+        let source = mixer.add_source();
+        mixer.push(source, &vec![0.9; FRAME_SIZE]);
+    }
+    let frame = mixer.mix().unwrap();
+    assert!(frame.iter().all(|s| *s <= 1.0 && *s > SOFT_CLIP_THRESHOLD));
+
+    assert_eq!(soft_clip(0.5), 0.5);
+    assert_eq!(soft_clip(-0.5), -0.5);
+    assert!(soft_clip(-10.0) >= -1.0);
+}
+
+#[test]
+fn test_mixer_resamples_to_output_device() {
+    //20ms at 44.1kHz stereo
+    let mut mixer = Mixer::new(44100, 2);
+    let a = mixer.add_source();
+    mixer.push(a, &vec![0.25; FRAME_SIZE]);
+
+    let frame = mixer.mix().unwrap();
+    assert_eq!(frame.len(), 882 * 2);
+    assert!(frame.iter().all(|s| (s - 0.25).abs() < 1e-6));
+}
@@ -0,0 +1,138 @@
+use std::sync::Arc;
+
+use discard::core::negotiation::{self, Negotiation, RemoteDescriptionAction};
+use discard::core::signal::Session;
+use discard::utils::constants::SESSION_MAX_SIZE;
+use discard::utils::enums::SessionType;
+use iroh::net::key::SecretKey;
+use tokio::time::{timeout, Duration};
+use webrtc::api::interceptor_registry::register_default_interceptors;
+use webrtc::api::media_engine::{MediaEngine, MIME_TYPE_OPUS, MIME_TYPE_VP8};
+use webrtc::api::APIBuilder;
+use webrtc::interceptor::registry::Registry;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::signaling_state::RTCSignalingState;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability;
+use webrtc::track::track_local::{track_local_static_sample::TrackLocalStaticSample, TrackLocal};
+
+//Set up the way the client sets its connections up
+async fn peer_connection() -> RTCPeerConnection {
+    let mut media_engine = MediaEngine::default();
+    media_engine.register_default_codecs().unwrap();
+    let registry = register_default_interceptors(Registry::new(), &mut media_engine).unwrap();
+    APIBuilder::new()
+        .with_media_engine(media_engine)
+        .with_interceptor_registry(registry)
+        .build()
+        .new_peer_connection(RTCConfiguration::default())
+        .await
+        .unwrap()
+}
+
+async fn add_track(pc: &RTCPeerConnection, mime_type: &str, id: &str) {
+    let track = Arc::new(TrackLocalStaticSample::new(
+        RTCRtpCodecCapability {
+            mime_type: mime_type.to_owned(),
+            ..Default::default()
+        },
+        id.to_owned(),
+        "webrtc-rs".to_owned(),
+    ));
+    pc.add_track(track as Arc<dyn TrackLocal + Send + Sync>)
+        .await
+        .unwrap();
+}
+
+#[test]
+fn test_roles_from_node_ids() {
+    let first = SecretKey::generate().public();
+    let second = SecretKey::generate().public();
+
+    //Exactly one side backs down
+    assert_ne!(
+        negotiation::is_polite(&first, &second),
+        negotiation::is_polite(&second, &first)
+    );
+}
+
+#[test]
+fn test_offer_collision() {
+    let polite = Negotiation::new(true);
+    let impolite = Negotiation::new(false);
+
+    //Both sent an offer and are waiting for an answer when the other's arrives
+    assert_eq!(
+        polite.on_remote_description(true, RTCSignalingState::HaveLocalOffer),
+        RemoteDescriptionAction::Rollback
+    );
+    assert_eq!(
+        impolite.on_remote_description(true, RTCSignalingState::HaveLocalOffer),
+        RemoteDescriptionAction::Ignore
+    );
+
+    //Then the impolite peer gets the polite peer's answer to its offer
+    assert_eq!(
+        impolite.on_remote_description(false, RTCSignalingState::HaveLocalOffer),
+        RemoteDescriptionAction::Apply
+    );
+}
+
+#[test]
+fn test_renegotiation() {
+    for negotiation in [Negotiation::new(true), Negotiation::new(false)] {
+        //An offer to add a track to an established connection is always answered
+        assert_eq!(
+            negotiation.on_remote_description(true, RTCSignalingState::Stable),
+            RemoteDescriptionAction::Apply
+        );
+        //An answer we aren't waiting for, e.g. to an offer we rolled back, goes nowhere
+        assert_eq!(
+            negotiation.on_remote_description(false, RTCSignalingState::Stable),
+            RemoteDescriptionAction::Ignore
+        );
+        //Still answering a previous offer
+        assert_eq!(
+            negotiation.on_remote_description(true, RTCSignalingState::HaveRemoteOffer),
+            RemoteDescriptionAction::Ignore
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_video_offer_exchange() {
+    let offerer = peer_connection().await;
+    let answerer = peer_connection().await;
+    add_track(&offerer, MIME_TYPE_OPUS, "audio").await;
+    add_track(&offerer, MIME_TYPE_VP8, "video").await;
+
+    let offer = offerer.create_offer(None).await.unwrap();
+    let mut gathered = offerer.gathering_complete_promise().await;
+    offerer.set_local_description(offer).await.unwrap();
+    //Candidates end up in the sdp once gathering is done
+    let _ = timeout(Duration::from_secs(5), gathered.recv()).await;
+
+    //Sent over the session exchange the same way the client does it
+    let session = Session {
+        session_type: SessionType::Video,
+        ice_candidate: None,
+        sdp: offerer.local_description().await,
+    };
+    let bytes = bincode::serialize(&session).unwrap();
+    assert!(
+        bytes.len() <= SESSION_MAX_SIZE,
+        "A video offer of {} bytes doesn't fit in a session",
+        bytes.len()
+    );
+
+    let session: Session = bincode::deserialize(&bytes).unwrap();
+    answerer
+        .set_remote_description(session.sdp.unwrap())
+        .await
+        .unwrap();
+    let answer = answerer.create_answer(None).await.unwrap();
+    assert!(answer.sdp.contains("m=video"));
+
+    offerer.close().await.unwrap();
+    answerer.close().await.unwrap();
+}
@@ -0,0 +1,110 @@
+mod utils;
+
+use discard::core::audio::FRAME_SIZE;
+use discard::core::processing::{
+    AudioProcessor, AutomaticGainControl, EchoCanceller, EchoReference, NoiseSuppressor,
+    ProcessingControls, AGC_TARGET_DB,
+};
+use discard::core::vad::rms;
+use discard::utils::enums::AudioProcessing;
+use std::sync::Arc;
+use utils::tone;
+
+//Deterministic white noise so the tests don't depend on a rand crate
+fn noise(len: usize, amplitude: f32, seed: &mut u32) -> Vec<f32> {
+    (0..len)
+        .map(|_| {
+            *seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            amplitude * ((*seed >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0)
+        })
+        .collect()
+}
+
+#[test]
+fn test_echo_is_cancelled() {
+    let mut seed = 1;
+    let mut canceller = EchoCanceller::new(64);
+    let mut history = vec![0.0; 10];
+    let mut residual = 0.0;
+    for frame in 0..50 {
+        //The microphone picks up the speaker 10 samples late at half the level
+        let reference = noise(FRAME_SIZE, 0.5, &mut seed);
+        history.extend(&reference);
+        let mut capture: Vec<f32> = history.drain(..FRAME_SIZE).map(|s| s * 0.5).collect();
+        let echo = rms(&capture);
+        canceller.process(&mut capture, &reference);
+        if frame == 49 {
+            residual = rms(&capture) / echo;
+        }
+    }
+    assert!(residual < 0.05, "Echo was only reduced to {}", residual);
+}
+
+#[test]
+fn test_noise_is_suppressed() {
+    let mut seed = 7;
+    let mut suppressor = NoiseSuppressor::default();
+    let mut frame = Vec::new();
+    for _ in 0..50 {
+        frame = noise(FRAME_SIZE, 0.01, &mut seed);
+        suppressor.process(&mut frame);
+    }
+    assert!(rms(&frame) < 0.01 * 0.2);
+
+    //Speech well above the noise floor comes through
+    let mut speech = tone(FRAME_SIZE, 0.3);
+    suppressor.process(&mut speech);
+    let mut speech = tone(FRAME_SIZE, 0.3);
+    suppressor.process(&mut speech);
+    assert!(rms(&speech) > rms(&tone(FRAME_SIZE, 0.3)) * 0.9);
+}
+
+#[test]
+fn test_gain_control_reaches_target() {
+    let mut agc = AutomaticGainControl::default();
+
+    //Silence doesn't get boosted
+    agc.process(&mut vec![0.0; FRAME_SIZE]);
+    assert_eq!(agc.gain(), 1.0);
+
+    //-40dBFS speech is brought up to the target
+    let quiet = tone(FRAME_SIZE, 0.01 * std::f32::consts::SQRT_2);
+    let mut frame = quiet.clone();
+    for _ in 0..100 {
+        frame = quiet.clone();
+        agc.process(&mut frame);
+    }
+    let level = 20.0 * rms(&frame).log10();
+    assert!((level - AGC_TARGET_DB).abs() < 1.0, "Level was {}", level);
+
+    //Loud speech is turned down without clipping
+    let mut agc = AutomaticGainControl::default();
+    let mut loud = vec![0.0; FRAME_SIZE];
+    for _ in 0..100 {
+        loud = tone(FRAME_SIZE, 1.0);
+        agc.process(&mut loud);
+    }
+    assert!(agc.gain() < 1.0);
+    assert!(loud.iter().all(|s| s.abs() <= 1.0));
+}
+
+#[test]
+fn test_processing_controls() {
+    let controls = Arc::new(ProcessingControls::default());
+    let settings = controls.settings();
+    assert!(settings.echo_cancellation && settings.noise_suppression && settings.auto_gain_control);
+
+    controls.set(AudioProcessing::NoiseSuppression, false);
+    assert!(!controls.is_enabled(AudioProcessing::NoiseSuppression));
+    assert!(!controls.settings().noise_suppression);
+
+    //With everything off the microphone goes through untouched
+    controls.set(AudioProcessing::EchoCancellation, false);
+    controls.set(AudioProcessing::AutoGainControl, false);
+    let echo_reference = EchoReference::default();
+    let mut processor = AudioProcessor::new(Arc::clone(&controls), &echo_reference);
+    echo_reference.push(&tone(FRAME_SIZE, 0.5));
+    let mut frame = tone(FRAME_SIZE, 0.1);
+    processor.process(&mut frame);
+    assert_eq!(frame, tone(FRAME_SIZE, 0.1));
+}
@@ -0,0 +1,31 @@
+use discard::core::reconnect::Backoff;
+use std::time::Duration;
+
+#[test]
+fn test_backoff_doubles_up_to_max() {
+    let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5), 5);
+
+    let delays: Vec<_> = std::iter::from_fn(|| backoff.next_delay()).collect();
+    assert_eq!(
+        delays,
+        [1, 2, 4, 5, 5].map(Duration::from_secs).to_vec(),
+        "Delays should double until they hit the max"
+    );
+    assert_eq!(backoff.attempt(), 5);
+    //Gives up after the last attempt
+    assert!(backoff.next_delay().is_none());
+}
+
+#[test]
+fn test_backoff_without_attempts() {
+    let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5), 0);
+    assert!(backoff.next_delay().is_none());
+    assert_eq!(backoff.attempt(), 0);
+}
+
+#[test]
+fn test_backoff_does_not_overflow() {
+    let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(30), 100);
+    let last = std::iter::from_fn(|| backoff.next_delay()).last();
+    assert_eq!(last, Some(Duration::from_secs(30)));
+}
@@ -0,0 +1,229 @@
+use discard::core::audio::{self, FRAME_SIZE, MAX_OPUS_PACKET_SIZE, SAMPLE_RATE};
+use discard::core::frame_source::{FrameSource, IvfSource, TestPattern};
+use discard::core::recorder::{
+    LocalTrackRecording, Recording, TrackRecorder, TrackRecording, VideoTrackRecorder,
+    VideoTrackRecording,
+};
+use discard::core::video::VIDEO_CLOCK_RATE;
+use discard::utils::enums::VideoCodec;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::Arc;
+use webrtc::media::io::{
+    h264_reader::{H264Reader, NalUnitType},
+    ogg_reader::OggReader,
+};
+use webrtc::rtp::{
+    codecs::h264::H264Payloader,
+    header::Header,
+    packet::Packet,
+    packetizer::{new_packetizer, Packetizer},
+    sequence::new_fixed_sequencer,
+};
+
+fn opus_packet() -> Vec<u8> {
+    let encoder = audio::new_encoder().unwrap();
+    let mut packet = vec![0u8; MAX_OPUS_PACKET_SIZE];
+    let len = encoder
+        .encode_float(&vec![0.1; FRAME_SIZE], &mut packet)
+        .unwrap();
+    packet.truncate(len);
+    packet
+}
+
+#[test]
+fn test_track_recorder_writes_ogg() {
+    let path = PathBuf::from("./test_track_recorder.ogg");
+    let packet = opus_packet();
+    {
+        let mut recorder = TrackRecorder::create(&path).unwrap();
+        recorder.write(&packet).unwrap();
+        //A skipped frame still moves the granule position forward
+        recorder.skip();
+        recorder.write(&packet).unwrap();
+    }
+
+    let (mut reader, header) = OggReader::new(File::open(&path).unwrap(), true).unwrap();
+    assert_eq!(header.sample_rate, SAMPLE_RATE);
+    assert_eq!(header.channels, 1);
+
+    let mut pages = Vec::new();
+    while let Ok((payload, page_header)) = reader.parse_next_page() {
+        pages.push((payload, page_header.granule_position));
+    }
+    //Comment header, both packets and the end of stream page
+    assert!(pages.len() >= 3);
+    assert!(pages.iter().any(|(payload, _)| payload[..] == packet[..]));
+    let last_granule = pages.last().unwrap().1;
+    assert_eq!(last_granule, 1 + 2 * FRAME_SIZE as u64);
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_recording_session() {
+    let root = PathBuf::from("./test-recordings");
+    let recording = Recording::default();
+    assert!(recording.stop().is_err());
+
+    let mut track_recording = TrackRecording::new("local".to_string());
+    //Nothing is written until a recording starts
+    track_recording.record(&recording, Some(&opus_packet()));
+
+    let directory = recording.start(&root).unwrap();
+    assert!(recording.is_active());
+    assert!(recording.start(&root).is_err());
+
+    track_recording.record(&recording, Some(&opus_packet()));
+    track_recording.record(&recording, None);
+    assert!(directory.join("local.ogg").exists());
+
+    assert_eq!(recording.stop().unwrap(), directory);
+    assert!(!recording.is_active());
+
+    let _ = std::fs::remove_dir_all(root);
+}
+
+#[test]
+fn test_local_track_recorded_once() {
+    let root = PathBuf::from("./test-local-recordings");
+    let recording = Recording::default();
+    let directory = recording.start(&root).unwrap();
+    let path = directory.join("local.ogg");
+    let packet = opus_packet();
+    {
+        let local = Arc::new(LocalTrackRecording::default());
+        //Two connections capturing in the same voice channel
+        let first = local.writer();
+        let second = local.writer();
+
+        first.record(&recording, Some(&packet));
+        second.record(&recording, Some(&packet));
+        first.record(&recording, Some(&packet));
+        second.record(&recording, Some(&packet));
+
+        //The second capture takes over once the first one stops
+        drop(first);
+        second.record(&recording, Some(&packet));
+    }
+
+    let (mut reader, _) = OggReader::new(File::open(&path).unwrap(), true).unwrap();
+    let mut last_granule = 0;
+    while let Ok((_, page_header)) = reader.parse_next_page() {
+        last_granule = page_header.granule_position;
+    }
+    //Three frames, the ones from the capture that wasn't writing are dropped
+    assert_eq!(last_granule, 1 + 2 * FRAME_SIZE as u64);
+
+    recording.stop().unwrap();
+    let _ = std::fs::remove_dir_all(root);
+}
+
+fn vp8_packet(timestamp: u32, tag: u8) -> Packet {
+    Packet {
+        header: Header {
+            timestamp,
+            marker: true,
+            ..Default::default()
+        },
+        //VP8 payload descriptor with the start of partition bit set
+        payload: vec![0x10, tag, 0xaa, 0xbb].into(),
+    }
+}
+
+#[test]
+fn test_video_recorder_writes_ivf() {
+    let path = PathBuf::from("./test_video_recorder.ivf");
+    {
+        let mut recorder = VideoTrackRecorder::create(&path, VideoCodec::Vp8).unwrap();
+        //Interframes before the first keyframe can't be decoded and are dropped
+        recorder.write(&vp8_packet(0, 0x11)).unwrap();
+        recorder.write(&vp8_packet(3000, 0x10)).unwrap();
+        recorder.write(&vp8_packet(6000, 0x11)).unwrap();
+    }
+
+    let mut source = IvfSource::open(&path).unwrap();
+    assert_eq!(source.codec(), VideoCodec::Vp8);
+    let first = source.next_frame().unwrap().unwrap();
+    assert!(first.keyframe);
+    assert_eq!(first.data, vec![0x10, 0xaa, 0xbb]);
+    assert!(!source.next_frame().unwrap().unwrap().keyframe);
+    assert!(source.next_frame().unwrap().is_none());
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_video_recording_session() {
+    let root = PathBuf::from("./test-video-recordings");
+    let recording = Recording::default();
+    let mut track_recording = VideoTrackRecording::new("peer".to_string(), VideoCodec::H264);
+    let mut pattern = TestPattern::new(64, 48, 15).unwrap();
+    let mut packetizer = new_packetizer(
+        1200,
+        102,
+        1,
+        Box::new(H264Payloader::default()),
+        Box::new(new_fixed_sequencer(0)),
+        VIDEO_CLOCK_RATE,
+    );
+    let mut send_frame = |track_recording: &mut VideoTrackRecording| {
+        let frame = pattern.next_frame().unwrap().unwrap();
+        let packets = packetizer
+            .packetize(&frame.data.into(), VIDEO_CLOCK_RATE / 15)
+            .unwrap();
+        for packet in packets {
+            track_recording.record(&recording, &packet);
+        }
+    };
+
+    //Nothing is written until a recording starts
+    send_frame(&mut track_recording);
+    let directory = recording.start(&root).unwrap();
+    for _ in 0..3 {
+        send_frame(&mut track_recording);
+    }
+    let path = directory.join("peer-video.h264");
+    recording.stop().unwrap();
+    //The file is closed with the next packet after the recording stops
+    send_frame(&mut track_recording);
+
+    //The pattern is partway through its keyframe interval, so the writer waits for one. Only the
+    //first frame was a keyframe and it went out before the recording started
+    let mut reader = H264Reader::new(BufReader::new(File::open(&path).unwrap()), 4096);
+    assert!(reader.next_nal().is_err());
+
+    //A fresh pattern starts with a keyframe
+    let directory = recording.start(&root).unwrap();
+    let mut pattern = TestPattern::new(64, 48, 15).unwrap();
+    for _ in 0..3 {
+        let frame = pattern.next_frame().unwrap().unwrap();
+        for packet in packetizer
+            .packetize(&frame.data.into(), VIDEO_CLOCK_RATE / 15)
+            .unwrap()
+        {
+            track_recording.record(&recording, &packet);
+        }
+    }
+    recording.stop().unwrap();
+    track_recording.record(&recording, &Packet::default());
+
+    let path = directory.join("peer-video.h264");
+    let mut reader = H264Reader::new(BufReader::new(File::open(&path).unwrap()), 4096);
+    let nal_types: Vec<NalUnitType> = std::iter::from_fn(|| reader.next_nal().ok())
+        .map(|nal| nal.unit_type)
+        .collect();
+    assert_eq!(
+        nal_types,
+        vec![
+            NalUnitType::SPS,
+            NalUnitType::PPS,
+            NalUnitType::CodedSliceIdr,
+            NalUnitType::CodedSliceNonIdr,
+            NalUnitType::CodedSliceNonIdr,
+        ]
+    );
+
+    let _ = std::fs::remove_dir_all(root);
+}
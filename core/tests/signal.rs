@@ -0,0 +1,103 @@
+mod utils;
+
+use discard::core::signal::{Session, SessionRouter};
+use discard::utils::enums::SessionType;
+use serde_json::json;
+use utils::node_id;
+use webrtc::ice_transport::ice_candidate::RTCIceCandidate;
+
+fn offer(session_type: SessionType) -> Session {
+    Session {
+        session_type,
+        ice_candidate: None,
+        sdp: Some(serde_json::from_value(json!({"type": "offer", "sdp": "v=0"})).unwrap()),
+    }
+}
+
+fn candidate(session_type: SessionType, port: u16) -> Session {
+    Session {
+        session_type,
+        ice_candidate: Some(RTCIceCandidate {
+            port,
+            ..Default::default()
+        }),
+        sdp: None,
+    }
+}
+
+fn port(session: &Session) -> u16 {
+    session.ice_candidate.as_ref().unwrap().port
+}
+
+#[test]
+fn test_routes_by_peer() {
+    let mut router = SessionRouter::default();
+    let (first, second) = (node_id(), node_id());
+
+    //Anything sent before the negotiation registers is held for it
+    router.route(first, candidate(SessionType::Call, 1));
+    let mut first_rx = router.register(first, SessionType::Call);
+    let mut second_rx = router.register(second, SessionType::Call);
+    router.route(second, candidate(SessionType::Call, 2));
+    router.route(first, candidate(SessionType::Call, 3));
+
+    assert_eq!(port(&first_rx.try_recv().unwrap()), 1);
+    assert_eq!(port(&first_rx.try_recv().unwrap()), 3);
+    assert!(first_rx.try_recv().is_err());
+    assert_eq!(port(&second_rx.try_recv().unwrap()), 2);
+    assert!(second_rx.try_recv().is_err());
+
+    //A stream with the same peer is negotiated separately from the call
+    let mut stream_rx = router.register(first, SessionType::Stream);
+    router.route(first, candidate(SessionType::Stream, 4));
+    assert_eq!(port(&stream_rx.try_recv().unwrap()), 4);
+    assert!(first_rx.try_recv().is_err());
+
+    //Same for a voice channel, a chat with the peer keeps its own sessions
+    let mut chat_rx = router.register(second, SessionType::Chat);
+    let mut channel_rx = router.register(second, SessionType::VoiceChannel);
+    router.route(second, candidate(SessionType::VoiceChannel, 6));
+    assert_eq!(port(&channel_rx.try_recv().unwrap()), 6);
+    assert!(chat_rx.try_recv().is_err());
+
+    //Once the connection is gone its sessions wait for the next one
+    drop(first_rx);
+    router.route(first, candidate(SessionType::Call, 5));
+    let mut first_rx = router.register(first, SessionType::Call);
+    assert_eq!(port(&first_rx.try_recv().unwrap()), 5);
+}
+
+#[test]
+fn test_wait_for_offer() {
+    let mut router = SessionRouter::default();
+    let (caller, streamer) = (node_id(), node_id());
+
+    let mut waiting = router.wait_for_offer(SessionType::Call);
+    //Stray candidates and other session types don't wake the answerer
+    router.route(caller, candidate(SessionType::Call, 1));
+    router.route(streamer, offer(SessionType::Stream));
+    assert!(waiting.try_recv().is_err());
+
+    router.route(caller, offer(SessionType::Call));
+    let (remote_node_id, mut sessions) = waiting.try_recv().unwrap();
+    assert_eq!(remote_node_id, caller);
+    assert_eq!(port(&sessions.try_recv().unwrap()), 1);
+    assert!(sessions.try_recv().unwrap().is_offer());
+    //Later sessions from the caller follow
+    router.route(caller, candidate(SessionType::Call, 2));
+    assert_eq!(port(&sessions.try_recv().unwrap()), 2);
+
+    //An offer that arrived before anyone was waiting is picked up straight away
+    let mut waiting = router.wait_for_offer(SessionType::Stream);
+    let (remote_node_id, mut sessions) = waiting.try_recv().unwrap();
+    assert_eq!(remote_node_id, streamer);
+    assert!(sessions.try_recv().unwrap().is_offer());
+
+    //An answerer that gave up doesn't swallow the offer
+    let gave_up = router.wait_for_offer(SessionType::Chat);
+    drop(gave_up);
+    let peer = node_id();
+    router.route(peer, offer(SessionType::Chat));
+    let mut waiting = router.wait_for_offer(SessionType::Chat);
+    assert_eq!(waiting.try_recv().unwrap().0, peer);
+}
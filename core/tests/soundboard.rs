@@ -0,0 +1,73 @@
+use discard::core::audio::{self, FRAME_SIZE, MAX_OPUS_PACKET_SIZE};
+use discard::core::recorder::TrackRecorder;
+use discard::core::soundboard::{list_sounds, load_sound, Soundboard};
+use discard::core::vad::rms;
+use std::path::PathBuf;
+
+const CLIP_FRAMES: usize = 10;
+
+fn write_clip(path: &PathBuf) {
+    let encoder = audio::new_encoder().unwrap();
+    let mut recorder = TrackRecorder::create(path).unwrap();
+    let mut packet = vec![0u8; MAX_OPUS_PACKET_SIZE];
+    for frame in 0..CLIP_FRAMES {
+        let pcm: Vec<f32> = (0..FRAME_SIZE)
+            .map(|i| {
+                let t = (frame * FRAME_SIZE + i) as f32 / 48000.0;
+                0.5 * (2.0 * std::f32::consts::PI * 440.0 * t).sin()
+            })
+            .collect();
+        let len = encoder.encode_float(&pcm, &mut packet).unwrap();
+        recorder.write(&packet[..len]).unwrap();
+    }
+}
+
+#[test]
+fn test_list_and_load_sounds() {
+    let directory = PathBuf::from("./test-sounds");
+    std::fs::create_dir_all(&directory).unwrap();
+    write_clip(&directory.join("airhorn.ogg"));
+    std::fs::write(directory.join("notes.txt"), "not a sound").unwrap();
+
+    assert_eq!(
+        list_sounds(&directory).unwrap(),
+        vec!["airhorn".to_string()]
+    );
+    assert!(list_sounds(&directory.join("missing")).unwrap().is_empty());
+
+    //The recorder's header asks for the default pre-skip to be dropped
+    let sound = load_sound(&directory.join("airhorn.ogg")).unwrap();
+    assert_eq!(sound.len(), CLIP_FRAMES * FRAME_SIZE - 3840);
+    assert!(rms(&sound) > 0.1);
+
+    let _ = std::fs::remove_dir_all(directory);
+}
+
+#[test]
+fn test_sounds_are_mixed_into_capture() {
+    let soundboard = Soundboard::default();
+    //Nobody is capturing yet
+    assert!(!soundboard.play(vec![0.5; FRAME_SIZE]));
+
+    let mut first = soundboard.subscribe();
+    let mut second = soundboard.subscribe();
+    assert!(soundboard.play(vec![0.5; FRAME_SIZE * 3 / 2]));
+
+    let mut frame = vec![0.1; FRAME_SIZE];
+    first.mix_into(&mut frame);
+    assert!(frame.iter().all(|sample| (sample - 0.6).abs() < 1e-6));
+    assert!(first.is_playing());
+
+    //Only the first half of the frame has anything left to play
+    let mut frame = vec![0.0; FRAME_SIZE];
+    first.mix_into(&mut frame);
+    assert!((frame[0] - 0.5).abs() < 1e-6);
+    assert_eq!(frame[FRAME_SIZE - 1], 0.0);
+    assert!(!first.is_playing());
+
+    //Every capture plays the whole clip on its own
+    let mut frame = vec![0.0; FRAME_SIZE];
+    second.mix_into(&mut frame);
+    assert!((frame[0] - 0.5).abs() < 1e-6);
+    assert!(second.is_playing());
+}
@@ -0,0 +1,184 @@
+mod utils;
+
+use std::time::{Duration, Instant};
+
+use discard::core::stats::{reported_jitter, StatsSampler};
+use serde_json::{json, Value};
+use utils::node_id;
+use webrtc::rtcp::{
+    packet::Packet, receiver_report::ReceiverReport, reception_report::ReceptionReport,
+};
+use webrtc::stats::StatsReport;
+
+const TIMESTAMP: f64 = 1_700_000_000.0;
+
+fn candidate(id: &str, stats_type: &str, candidate_type: &str, ip: &str, port: u16) -> Value {
+    json!({
+        "timestamp": TIMESTAMP,
+        "type": stats_type,
+        "id": id,
+        "candidateType": candidate_type,
+        "deleted": false,
+        "ip": ip,
+        "networkType": "udp4",
+        "port": port,
+        "priority": 0,
+        "relayProtocol": "",
+        "url": "",
+    })
+}
+
+fn candidate_pair(local: &str, remote: &str, nominated: bool, bytes: u64, rtt: f64) -> Value {
+    json!({
+        "timestamp": TIMESTAMP,
+        "type": "candidate-pair",
+        "id": format!("{}-{}", local, remote),
+        "localCandidateId": local,
+        "remoteCandidateId": remote,
+        "state": "succeeded",
+        "nominated": nominated,
+        "packetsSent": 0,
+        "packetsReceived": 0,
+        "bytesSent": bytes,
+        "bytesReceived": bytes / 2,
+        "lastPacketSentTimestamp": TIMESTAMP,
+        "lastPacketReceivedTimestamp": TIMESTAMP,
+        "totalRoundTripTime": 0.0,
+        "currentRoundTripTime": rtt,
+        "availableOutgoingBitrate": 0.0,
+        "availableIncomingBitrate": 0.0,
+        "requestsReceived": 0,
+        "requestsSent": 0,
+        "responsesReceived": 0,
+        "responsesSent": 0,
+        "consentRequestsSent": 0,
+        "circuitBreakerTriggerCount": 0,
+        "consentExpiredTimestamp": TIMESTAMP,
+        "firstRequestTimestamp": TIMESTAMP,
+        "lastRequestTimestamp": TIMESTAMP,
+        "retransmissionsSent": 0,
+    })
+}
+
+fn remote_inbound(round_trip_time: Option<f64>, packets_lost: i64, fraction_lost: f64) -> Value {
+    json!({
+        "timestamp": TIMESTAMP,
+        "type": "remote-inbound-rtp",
+        "id": "remote-inbound",
+        "ssrc": 1,
+        "kind": "audio",
+        "packetsReceived": 100,
+        "packetsLost": packets_lost,
+        "localId": "outbound",
+        "roundTripTime": round_trip_time,
+        "totalRoundTripTime": 0.0,
+        "fractionLost": fraction_lost,
+        "roundTripTimeMeasurements": 1,
+    })
+}
+
+fn report(stats: Vec<Value>) -> StatsReport {
+    let reports: serde_json::Map<String, Value> = stats
+        .into_iter()
+        .map(|stats| (stats["id"].as_str().unwrap().to_string(), stats))
+        .collect();
+    serde_json::from_value(Value::Object(reports)).unwrap()
+}
+
+#[test]
+fn test_summarize_selected_pair() {
+    let peer = node_id();
+    let mut sampler = StatsSampler::default();
+    let now = Instant::now();
+    let stats = |bytes: u64| {
+        report(vec![
+            candidate("local-host", "local-candidate", "host", "192.168.1.2", 5000),
+            candidate(
+                "remote-host",
+                "remote-candidate",
+                "host",
+                "192.168.1.3",
+                5001,
+            ),
+            candidate(
+                "local-relay",
+                "local-candidate",
+                "relay",
+                "203.0.113.1",
+                3478,
+            ),
+            candidate(
+                "remote-srflx",
+                "remote-candidate",
+                "srflx",
+                "198.51.100.7",
+                6000,
+            ),
+            candidate_pair("local-host", "remote-host", false, bytes * 2, 0.002),
+            candidate_pair("local-relay", "remote-srflx", true, bytes, 0.080),
+        ])
+    };
+
+    let first = sampler.summarize(peer, &stats(10_000), None, now);
+    assert_eq!(first.node_id, peer);
+    //Without remote inbound stats the RTT comes from the nominated pair
+    assert_eq!(first.round_trip_time_ms, Some(80));
+    assert!(first.relayed);
+    let local = first.local_candidate.unwrap();
+    assert_eq!(local.ip, "203.0.113.1");
+    assert_eq!(local.port, 3478);
+    assert_eq!(local.candidate_type, "relay");
+    assert_eq!(first.remote_candidate.unwrap().candidate_type, "srflx");
+    //Bitrates need a previous snapshot
+    assert_eq!(first.outgoing_bitrate, None);
+    assert_eq!(first.packets_lost, None);
+    assert_eq!(first.jitter_ms, None);
+
+    let second = sampler.summarize(peer, &stats(14_000), None, now + Duration::from_secs(2));
+    assert_eq!(second.outgoing_bitrate, Some(16_000));
+    assert_eq!(second.incoming_bitrate, Some(8_000));
+}
+
+#[test]
+fn test_summarize_remote_inbound() {
+    let mut sampler = StatsSampler::default();
+    let stats = report(vec![
+        candidate("local-host", "local-candidate", "host", "10.0.0.1", 5000),
+        candidate("remote-host", "remote-candidate", "host", "10.0.0.2", 5000),
+        candidate_pair("local-host", "remote-host", true, 1000, 0.010),
+        remote_inbound(Some(0.045), 7, 0.05),
+    ]);
+    //960 timestamp units is 20ms at 48kHz
+    let summary = sampler.summarize(node_id(), &stats, Some(960), Instant::now());
+    assert_eq!(summary.round_trip_time_ms, Some(45));
+    assert_eq!(summary.packets_lost, Some(7));
+    assert_eq!(summary.packet_loss_percent, Some(5));
+    assert_eq!(summary.jitter_ms, Some(20));
+    assert!(!summary.relayed);
+
+    //Nothing connected yet
+    let summary = sampler.summarize(node_id(), &report(vec![]), None, Instant::now());
+    assert_eq!(summary.round_trip_time_ms, None);
+    assert_eq!(summary.local_candidate, None);
+    assert!(!summary.relayed);
+}
+
+#[test]
+fn test_reported_jitter() {
+    let receiver_report = |jitter: u32| -> Box<dyn Packet + Send + Sync> {
+        Box::new(ReceiverReport {
+            reports: vec![ReceptionReport {
+                jitter,
+                ..Default::default()
+            }],
+            ..Default::default()
+        })
+    };
+    assert_eq!(reported_jitter(&[]), None);
+    let empty: Box<dyn Packet + Send + Sync> = Box::new(ReceiverReport::default());
+    assert_eq!(reported_jitter(&[empty]), None);
+    assert_eq!(
+        reported_jitter(&[receiver_report(100), receiver_report(240)]),
+        Some(240)
+    );
+}
@@ -1,4 +1,10 @@
+//Shared by every test crate, each one only uses some of the helpers
+#![allow(dead_code)]
+
+use discard::core::audio::SAMPLE_RATE;
 use discard::utils::enums::RunMessage;
+use discard::utils::types::NodeId;
+use iroh::net::key::SecretKey;
 use std::fs;
 use std::path::PathBuf;
 use tokio::sync::mpsc;
@@ -36,3 +42,16 @@ impl Drop for Cleanup {
         self.remove_test_paths();
     }
 }
+
+pub fn node_id() -> NodeId {
+    SecretKey::generate().public()
+}
+
+//A 440Hz sine at the Opus rate
+pub fn tone(len: usize, amplitude: f32) -> Vec<f32> {
+    (0..len)
+        .map(|i| {
+            amplitude * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / SAMPLE_RATE as f32).sin()
+        })
+        .collect()
+}
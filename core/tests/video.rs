@@ -0,0 +1,326 @@
+use std::fs::{self, File};
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread::{self, ThreadId};
+use std::time::Duration;
+
+use discard::core::frame_source::{open_file, FrameSource, IvfSource, TestPattern, VideoFrame};
+use discard::core::video::{FrameAssembler, LiveFeed, VIDEO_CLOCK_RATE};
+use discard::utils::enums::VideoCodec;
+use webrtc::media::io::{
+    h264_reader::{H264Reader, NalUnitType},
+    ivf_reader::IVFFileHeader,
+    ivf_writer::IVFWriter,
+    Writer,
+};
+use webrtc::rtp::{
+    codecs::h264::H264Payloader,
+    packetizer::{new_packetizer, Packetizer},
+    sequence::new_fixed_sequencer,
+};
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 48;
+const MACROBLOCKS: u32 = (WIDTH / 16 * HEIGHT / 16) as u32;
+
+//Reports the thread every frame is read on
+#[derive(Debug)]
+struct ThreadSource {
+    frames: usize,
+    thread_tx: mpsc::Sender<ThreadId>,
+}
+
+impl FrameSource for ThreadSource {
+    fn codec(&self) -> VideoCodec {
+        VideoCodec::Vp8
+    }
+
+    fn next_frame(&mut self) -> anyhow::Result<Option<VideoFrame>> {
+        if self.frames == 0 {
+            return Ok(None);
+        }
+        self.frames -= 1;
+        let _ = self.thread_tx.send(thread::current().id());
+        Ok(Some(VideoFrame {
+            data: vec![0; 16],
+            duration: Duration::from_millis(1),
+            keyframe: true,
+        }))
+    }
+}
+
+fn nal_types(frame: &VideoFrame) -> Vec<NalUnitType> {
+    let mut reader = H264Reader::new(Cursor::new(frame.data.clone()), 4096);
+    std::iter::from_fn(|| reader.next_nal().ok())
+        .map(|nal| nal.unit_type)
+        .collect()
+}
+
+//Reads back enough of a slice to count the macroblocks it codes and skips
+struct BitReader {
+    bytes: Vec<u8>,
+    position: usize,
+}
+
+impl BitReader {
+    fn new(nal: &[u8]) -> Self {
+        //Drop the header byte and undo emulation prevention
+        let mut bytes = Vec::new();
+        for &byte in &nal[1..] {
+            if byte == 3 && bytes.ends_with(&[0, 0]) {
+                continue;
+            }
+            bytes.push(byte);
+        }
+        Self { bytes, position: 0 }
+    }
+
+    fn bit(&mut self) -> u32 {
+        let bit = (self.bytes[self.position / 8] >> (7 - self.position % 8)) & 1;
+        self.position += 1;
+        bit as u32
+    }
+
+    fn bits(&mut self, count: u32) -> u32 {
+        (0..count).fold(0, |value, _| (value << 1) | self.bit())
+    }
+
+    fn ue(&mut self) -> u32 {
+        let mut zeros = 0;
+        while self.bit() == 0 {
+            zeros += 1;
+        }
+        (1 << zeros) - 1 + self.bits(zeros)
+    }
+
+    fn at_trailing_bits(&self) -> bool {
+        let remaining = self.bytes.len() * 8 - self.position;
+        remaining <= 8 && self.bytes[self.position / 8] << (self.position % 8) == 0x80
+    }
+}
+
+//Returns (coded, skipped) macroblocks
+fn count_macroblocks(nal: &[u8]) -> (u32, u32) {
+    let idr = nal[0] & 0x1f == 5;
+    let mut r = BitReader::new(nal);
+    assert_eq!(r.ue(), 0);
+    assert_eq!(r.ue(), if idr { 7 } else { 5 });
+    assert_eq!(r.ue(), 0);
+    r.bits(4);
+    if idr {
+        r.ue();
+        r.bits(2);
+    } else {
+        r.bits(3);
+    }
+    assert_eq!(r.ue(), 0); //slice_qp_delta
+    assert_eq!(r.ue(), 1); //deblocking off
+
+    let (mut coded, mut skipped) = (0, 0);
+    while coded + skipped < MACROBLOCKS {
+        if !idr {
+            skipped += r.ue();
+            if coded + skipped == MACROBLOCKS {
+                break;
+            }
+        }
+        assert_eq!(r.ue(), if idr { 25 } else { 30 });
+        r.position = r.position.div_ceil(8) * 8 + 384 * 8;
+        coded += 1;
+    }
+    assert!(r.at_trailing_bits());
+    (coded, skipped)
+}
+
+fn slice(frame: &VideoFrame) -> Vec<u8> {
+    let mut reader = H264Reader::new(Cursor::new(frame.data.clone()), 4096);
+    std::iter::from_fn(|| reader.next_nal().ok())
+        .find(|nal| {
+            matches!(
+                nal.unit_type,
+                NalUnitType::CodedSliceIdr | NalUnitType::CodedSliceNonIdr
+            )
+        })
+        .unwrap()
+        .data
+        .to_vec()
+}
+
+#[test]
+fn test_pattern_keyframes() {
+    let mut pattern = TestPattern::new(WIDTH, HEIGHT, 15).unwrap();
+    assert_eq!(pattern.codec(), VideoCodec::H264);
+    assert!(TestPattern::new(50, 48, 15).is_err());
+
+    let first = pattern.next_frame().unwrap().unwrap();
+    assert!(first.keyframe);
+    assert_eq!(first.duration, Duration::from_secs(1) / 15);
+    assert_eq!(
+        nal_types(&first),
+        vec![
+            NalUnitType::SPS,
+            NalUnitType::PPS,
+            NalUnitType::CodedSliceIdr
+        ]
+    );
+
+    let second = pattern.next_frame().unwrap().unwrap();
+    assert!(!second.keyframe);
+    assert_eq!(nal_types(&second), vec![NalUnitType::CodedSliceNonIdr]);
+    assert!(second.data.len() < first.data.len());
+
+    pattern.request_keyframe();
+    assert!(pattern.next_frame().unwrap().unwrap().keyframe);
+
+    //Every two seconds regardless
+    let keyframes = (3..30)
+        .map(|_| pattern.next_frame().unwrap().unwrap().keyframe)
+        .filter(|keyframe| *keyframe)
+        .count();
+    assert_eq!(keyframes, 0);
+    assert!(pattern.next_frame().unwrap().unwrap().keyframe);
+}
+
+#[test]
+fn test_pattern_slices_cover_picture() {
+    let mut pattern = TestPattern::new(WIDTH, HEIGHT, 15).unwrap();
+    let keyframe = pattern.next_frame().unwrap().unwrap();
+    assert_eq!(count_macroblocks(&slice(&keyframe)), (MACROBLOCKS, 0));
+
+    //The moving square only touches a few macroblocks, the rest are skipped
+    for _ in 0..5 {
+        let frame = pattern.next_frame().unwrap().unwrap();
+        let (coded, skipped) = count_macroblocks(&slice(&frame));
+        assert!(coded > 0);
+        assert!(skipped > 0);
+    }
+}
+
+#[test]
+fn test_h264_file_source() {
+    let path = PathBuf::from("./test_h264_file_source.h264");
+    let mut pattern = TestPattern::new(WIDTH, HEIGHT, 15).unwrap();
+    let frames: Vec<VideoFrame> = (0..5)
+        .map(|_| pattern.next_frame().unwrap().unwrap())
+        .collect();
+    fs::write(
+        &path,
+        frames
+            .iter()
+            .flat_map(|frame| frame.data.clone())
+            .collect::<Vec<u8>>(),
+    )
+    .unwrap();
+
+    let mut source = open_file(&path).unwrap();
+    assert_eq!(source.codec(), VideoCodec::H264);
+    for frame in &frames {
+        let read = source.next_frame().unwrap().unwrap();
+        assert_eq!(read.data, frame.data);
+        assert_eq!(read.keyframe, frame.keyframe);
+    }
+    assert!(source.next_frame().unwrap().is_none());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_ivf_source() {
+    let path = PathBuf::from("./test_ivf_source.ivf");
+    let header = IVFFileHeader {
+        signature: *b"DKIF",
+        version: 0,
+        header_size: 32,
+        four_cc: *b"VP80",
+        width: 64,
+        height: 48,
+        timebase_denominator: 30,
+        timebase_numerator: 1,
+        num_frames: 3,
+        unused: 0,
+    };
+    {
+        let mut writer = IVFWriter::new(File::create(&path).unwrap(), &header).unwrap();
+        for (i, tag) in [0x10u8, 0x11, 0x11].iter().enumerate() {
+            let packet = webrtc::rtp::packet::Packet {
+                header: webrtc::rtp::header::Header {
+                    timestamp: i as u32 * 3000,
+                    marker: true,
+                    ..Default::default()
+                },
+                //VP8 payload descriptor with the start of partition bit set
+                payload: vec![0x10, *tag, 0xaa, 0xbb].into(),
+            };
+            writer.write_rtp(&packet).unwrap();
+        }
+        writer.close().unwrap();
+    }
+
+    let mut source = IvfSource::open(&path).unwrap();
+    assert_eq!(source.codec(), VideoCodec::Vp8);
+    let first = source.next_frame().unwrap().unwrap();
+    assert!(first.keyframe);
+    assert_eq!(first.data, vec![0x10, 0xaa, 0xbb]);
+    let second = source.next_frame().unwrap().unwrap();
+    assert!(!second.keyframe);
+    assert!(source.next_frame().unwrap().is_some());
+    assert!(source.next_frame().unwrap().is_none());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_frame_assembler_rebuilds_frames() {
+    let mut pattern = TestPattern::new(WIDTH, HEIGHT, 15).unwrap();
+    let mut packetizer = new_packetizer(
+        1200,
+        102,
+        1,
+        Box::new(H264Payloader::default()),
+        Box::new(new_fixed_sequencer(0)),
+        VIDEO_CLOCK_RATE,
+    );
+    let mut assembler = FrameAssembler::new(VideoCodec::H264);
+    assert_eq!(assembler.codec(), VideoCodec::H264);
+
+    let mut sent = Vec::new();
+    let mut received = Vec::new();
+    for _ in 0..4 {
+        let frame = pattern.next_frame().unwrap().unwrap();
+        let packets = packetizer
+            .packetize(&frame.data.clone().into(), VIDEO_CLOCK_RATE / 15)
+            .unwrap();
+        //Keyframes are too big for one packet and get fragmented
+        if frame.keyframe {
+            assert!(packets.len() > 1);
+        }
+        for packet in packets {
+            received.extend(assembler.push(packet));
+        }
+        sent.push(frame.data);
+    }
+    //A frame is only complete once the next one starts
+    assert_eq!(received.len(), 3);
+    for (i, (timestamp, data)) in received.iter().enumerate() {
+        assert_eq!(data, &sent[i]);
+        assert_eq!(
+            timestamp.wrapping_sub(received[0].0),
+            i as u32 * VIDEO_CLOCK_RATE / 15
+        );
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_source_read_off_the_runtime() {
+    let (thread_tx, thread_rx) = mpsc::channel();
+    let source = ThreadSource {
+        frames: 3,
+        thread_tx,
+    };
+    let (_feed, _stream) = LiveFeed::start(Box::new(source));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    //File reads and drawing frames don't hold up the runtime's own thread
+    let threads: Vec<ThreadId> = thread_rx.try_iter().collect();
+    assert_eq!(threads.len(), 3);
+    assert!(threads.iter().all(|id| *id != thread::current().id()));
+}
@@ -0,0 +1,69 @@
+mod utils;
+
+use discard::core::audio::{FRAME_SIZE, SAMPLE_RATE};
+use discard::core::backend::{wav_spec, SyntheticBackend};
+use discard::core::soundboard::load_sound;
+use discard::core::vad::rms;
+use discard::core::voice_message::{
+    duration_ms, import_samples, waveform, write_voice_message, VoiceNoteRecorder, WAVEFORM_BARS,
+};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::time::{sleep, Duration};
+use utils::tone;
+
+#[test]
+fn test_waveform_and_duration() {
+    let mut samples = vec![0.0; SAMPLE_RATE as usize];
+    samples.extend(tone(SAMPLE_RATE as usize, 0.5));
+    assert_eq!(duration_ms(&samples), 2000);
+
+    let bars = waveform(&samples);
+    assert_eq!(bars.len(), WAVEFORM_BARS);
+    assert_eq!(bars[0], 0);
+    assert_eq!(*bars.iter().max().unwrap(), 255);
+
+    assert!(waveform(&[]).is_empty());
+    assert!(waveform(&[0.0; FRAME_SIZE]).iter().all(|bar| *bar == 0));
+}
+
+#[test]
+fn test_voice_message_round_trip() {
+    let path = PathBuf::from("./test_voice_message.ogg");
+    let samples = tone(FRAME_SIZE * 25 + 100, 0.5);
+    write_voice_message(&samples, &path).unwrap();
+    assert!(write_voice_message(&[], &path).is_err());
+
+    //Nothing is lost to the pre-skip, the end is padded up to a whole frame
+    let decoded = load_sound(&path).unwrap();
+    assert_eq!(decoded.len(), FRAME_SIZE * 26);
+    assert!(rms(&decoded[FRAME_SIZE..FRAME_SIZE * 25]) > 0.2);
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_import_wav() {
+    let path = PathBuf::from("./test_voice_message.wav");
+    let mut writer = hound::WavWriter::create(&path, wav_spec()).unwrap();
+    for sample in tone(FRAME_SIZE * 5, 0.5) {
+        writer.write_sample(sample).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    assert_eq!(import_samples(&path).unwrap().len(), FRAME_SIZE * 5);
+    assert!(import_samples(&PathBuf::from("./voice_message.mp3")).is_err());
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn test_record_voice_note() {
+    let backend = Arc::new(SyntheticBackend::sine(440.0));
+    let recorder = VoiceNoteRecorder::start(backend).await.unwrap();
+    sleep(Duration::from_millis(200)).await;
+    let samples = recorder.finish();
+    assert!(samples.len() >= FRAME_SIZE * 5);
+    assert_eq!(samples.len() % FRAME_SIZE, 0);
+    assert!(rms(&samples) > 0.2);
+}
@@ -41,8 +41,8 @@ type UpdateStatus struct {
 }
 
 type SendMessage struct {
-	DisplayName string `json:"displayName"`
-	Content     string `json:"content"`
+	NodeId  string `json:"nodeId"`
+	Content string `json:"content"`
 }
 
 func (client *TCPClient) AddUser(nodeId string, displayName string) {